@@ -0,0 +1,70 @@
+use spirv_std::glam::{vec3, Vec3};
+#[cfg(target_arch = "spirv")]
+use spirv_std::num_traits::Float;
+
+/// Rows of the matrix taking linear Rec.709 to linear Rec.2020, both with a D65 white point.
+const REC709_TO_REC2020: [Vec3; 3] = [
+    vec3(0.627_404, 0.329_283, 0.043_313),
+    vec3(0.069_097, 0.919_541, 0.011_362),
+    vec3(0.016_391, 0.088_013, 0.895_595),
+];
+
+/// Rows of the matrix taking linear Rec.709 to ACEScg (AP1 primaries, ACES white point), with a
+/// Bradford adaptation from D65.
+const REC709_TO_ACESCG: [Vec3; 3] = [
+    vec3(0.613_097, 0.339_523, 0.047_379),
+    vec3(0.070_194, 0.916_355, 0.013_451),
+    vec3(0.020_616, 0.109_570, 0.869_815),
+];
+
+/// Colour space of the output image, selected by `FrameUniforms::output_color_space`.
+///
+/// Rendering happens in linear Rec.709, the primaries of sRGB. Textures are decoded into it by
+/// sampling them through `_SRGB` formats, and this converts the result for the output image.
+#[derive(Clone, Copy)]
+#[repr(u32)]
+pub enum ColorSpace {
+    /// Rec.709 primaries with the sRGB transfer function, what image viewers expect.
+    Srgb,
+    /// Rec.709 primaries, no transfer function.
+    LinearSrgb,
+    /// Rec.2020 primaries with a 2.4 gamma, as for SDR Rec.2020 displays.
+    Rec2020,
+    /// ACEScg primaries, no transfer function.
+    AcesCg,
+}
+
+impl ColorSpace {
+    pub fn from_u32(id: u32) -> Self {
+        match id {
+            1 => Self::LinearSrgb,
+            2 => Self::Rec2020,
+            3 => Self::AcesCg,
+            _ => Self::Srgb,
+        }
+    }
+
+    /// Converts a linear Rec.709 colour into this colour space, encoded for storage.
+    pub fn encode(self, color: Vec3) -> Vec3 {
+        match self {
+            Self::Srgb => vec3(srgb_oetf(color.x), srgb_oetf(color.y), srgb_oetf(color.z)),
+            Self::LinearSrgb => color,
+            Self::Rec2020 => transform(REC709_TO_REC2020, color)
+                .max(Vec3::ZERO)
+                .powf(1.0 / 2.4),
+            Self::AcesCg => transform(REC709_TO_ACESCG, color),
+        }
+    }
+}
+
+fn transform(rows: [Vec3; 3], color: Vec3) -> Vec3 {
+    vec3(rows[0].dot(color), rows[1].dot(color), rows[2].dot(color))
+}
+
+fn srgb_oetf(value: f32) -> f32 {
+    if value <= 0.003_130_8 {
+        value.max(0.0) * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
@@ -0,0 +1,41 @@
+//! Shader-side debugging helpers.
+
+use spirv_std::{
+    arch::atomic_i_increment,
+    glam::UVec2,
+    memory::{Scope, Semantics},
+};
+
+use crate::pod::MAX_ASSERT_RECORDS;
+
+/// Prints a formatted line through `debugPrintfEXT`, e.g. `debug_print!("t: %f", payload.t)`.
+///
+/// Compiles to nothing unless the shader is built with `SPV_KHR_non_semantic_info`, which the
+/// host's `debug-printf` feature enables. The output shows up as validation layer messages; see
+/// the validation layers' debug printf documentation for the format string rules.
+#[macro_export]
+macro_rules! debug_print {
+    ($($arg:tt)*) => {
+        #[cfg(target_feature = "ext:SPV_KHR_non_semantic_info")]
+        unsafe {
+            spirv_std::macros::debug_printfln!($($arg)*);
+        }
+    };
+}
+
+/// Records an invariant violation of `kind` at `pixel` in the assert buffer, which the host
+/// reports after the dispatch in debug builds.
+pub fn record_assert(asserts: &mut [u32], kind: u32, pixel: UVec2) {
+    let index = unsafe {
+        atomic_i_increment::<u32, { Scope::QueueFamily as u32 }, { Semantics::NONE.bits() }>(
+            &mut asserts[0],
+        )
+    };
+
+    if index < MAX_ASSERT_RECORDS {
+        let record = (1 + 3 * index) as usize;
+        asserts[record] = kind;
+        asserts[record + 1] = pixel.x;
+        asserts[record + 2] = pixel.y;
+    }
+}
@@ -0,0 +1,61 @@
+use core::f32::consts::TAU;
+
+use spirv_std::glam::Vec2;
+#[cfg(target_arch = "spirv")]
+use spirv_std::num_traits::Float;
+
+/// Standard deviation of the Gaussian filter, in pixels.
+const GAUSSIAN_SIGMA: f32 = 0.5;
+
+/// Pixel reconstruction filter, selected by `FrameUniforms::pixel_filter`.
+///
+/// Sample offsets are drawn uniformly from the square of half-width `radius` around the pixel
+/// centre and weighted by the filter, so every filter works with the same sampling code and a
+/// box of radius 0.5 is the plain per-pixel average.
+#[derive(Clone, Copy)]
+#[repr(u32)]
+pub enum PixelFilter {
+    Box,
+    Tent,
+    /// Truncated at `radius` and shifted so it reaches zero there.
+    Gaussian,
+    /// Four-term Blackman-Harris window stretched over `radius`.
+    BlackmanHarris,
+}
+
+impl PixelFilter {
+    pub fn from_u32(id: u32) -> Self {
+        match id {
+            1 => Self::Tent,
+            2 => Self::Gaussian,
+            3 => Self::BlackmanHarris,
+            _ => Self::Box,
+        }
+    }
+
+    /// Turns a uniform sample in `[0, 1)^2` into an offset from the pixel centre and the weight
+    /// of the sample taken there.
+    pub fn sample(self, radius: f32, u: Vec2) -> (Vec2, f32) {
+        let offset = (u * 2.0 - Vec2::ONE) * radius;
+        (
+            offset,
+            self.evaluate(offset.x, radius) * self.evaluate(offset.y, radius),
+        )
+    }
+
+    /// One axis of the separable filter, at distance `x` from the pixel centre.
+    fn evaluate(self, x: f32, radius: f32) -> f32 {
+        match self {
+            Self::Box => 1.0,
+            Self::Tent => (1.0 - x.abs() / radius).max(0.0),
+            Self::Gaussian => {
+                let gaussian = |x: f32| (-x * x / (2.0 * GAUSSIAN_SIGMA * GAUSSIAN_SIGMA)).exp();
+                (gaussian(x) - gaussian(radius)).max(0.0)
+            }
+            Self::BlackmanHarris => {
+                let t = TAU * (0.5 + 0.5 * x / radius);
+                0.35875 - 0.48829 * t.cos() + 0.14128 * (2.0 * t).cos() - 0.01168 * (3.0 * t).cos()
+            }
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use spirv_std::{
+    glam::{Vec3, Vec4},
+    ray_tracing::{AccelerationStructure, RayFlags},
+};
+
+use crate::{
+    math::{cosine_hemisphere, offset_ray, Onb},
+    pod::{VISIBLE_TO_CAMERA, VISIBLE_TO_SECONDARY, VISIBLE_TO_SHADOW},
+    rand::DefaultRng,
+    stats::RayCounts,
+    RayPayload,
+};
+
+/// Only used for camera rays; rays leaving a surface start at an offset origin instead.
+const T_MIN: f32 = 0.001;
+const T_MAX: f32 = 1000.0;
+const AO_RADIUS: f32 = 1.0;
+
+/// Strategy used by raygen to turn a camera ray into a colour, selected by
+/// `FrameUniforms::integrator`.
+#[derive(Clone, Copy)]
+#[repr(u32)]
+pub enum Integrator {
+    /// Unlit instance colour, or the skybox on a miss.
+    Flat,
+    /// Cosine-weighted ambient occlusion within `AO_RADIUS` of the first hit.
+    AmbientOcclusion,
+    /// World-space shading normal of the first hit, mapped to `[0, 1]`.
+    Normal,
+    /// Diffuse path tracing with the instance colour as albedo, lit only by the environment.
+    Path,
+}
+
+impl Integrator {
+    pub fn from_u32(id: u32) -> Self {
+        match id {
+            1 => Self::AmbientOcclusion,
+            2 => Self::Normal,
+            3 => Self::Path,
+            _ => Self::Flat,
+        }
+    }
+
+    /// Estimates the colour seen along one camera ray. The camera ray is traced against
+    /// `primary_as`, which may hold only the instances in view; every other ray uses
+    /// `top_level_as`. If the camera ray misses, `backplate` is composited over the environment
+    /// using its alpha. Rays leaving a surface start at `offset_ray`'s position, or `ray_epsilon`
+    /// along the normal if that is positive. Paths are cut off after `max_bounces` bounces.
+    /// Every ray traced is counted in `ray_counts`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn radiance(
+        self,
+        primary_as: &AccelerationStructure,
+        top_level_as: &AccelerationStructure,
+        origin: Vec3,
+        direction: Vec3,
+        backplate: Vec4,
+        ray_epsilon: f32,
+        max_bounces: u32,
+        rng: &mut DefaultRng,
+        ray_counts: &mut RayCounts,
+        payload: &mut RayPayload,
+    ) -> Vec3 {
+        ray_counts.primary += 1;
+        trace(
+            primary_as,
+            RayFlags::OPAQUE,
+            VISIBLE_TO_CAMERA,
+            origin,
+            direction,
+            T_MIN,
+            T_MAX,
+            payload,
+        );
+
+        if payload.is_miss != 0 {
+            ray_counts.camera_misses += 1;
+            return payload.color.lerp(backplate.truncate(), backplate.w);
+        }
+
+        match self {
+            Self::Flat => payload.color,
+            Self::AmbientOcclusion => {
+                let normal = face_forward(payload.normal, direction);
+                let position = leave_surface(
+                    origin + payload.t * direction,
+                    face_forward(payload.geometric_normal, direction),
+                    ray_epsilon,
+                );
+                let ao_direction =
+                    Onb::build_from_w(normal).local_to_world(cosine_hemisphere(rng.next_vec2()));
+
+                // Occlusion rays only need to know whether anything was hit, so the closest hit
+                // shader is skipped and only the miss shader clears `is_miss`.
+                payload.is_miss = 0;
+                ray_counts.secondary += 1;
+                trace(
+                    top_level_as,
+                    RayFlags::OPAQUE
+                        | RayFlags::TERMINATE_ON_FIRST_HIT
+                        | RayFlags::SKIP_CLOSEST_HIT_SHADER,
+                    VISIBLE_TO_SHADOW,
+                    position,
+                    ao_direction,
+                    0.0,
+                    AO_RADIUS,
+                    payload,
+                );
+
+                if payload.is_miss != 0 {
+                    Vec3::ONE
+                } else {
+                    ray_counts.occluded += 1;
+                    Vec3::ZERO
+                }
+            }
+            Self::Normal => payload.normal * 0.5 + 0.5,
+            Self::Path => {
+                // Lambertian surfaces sampled by their cosine-weighted BSDF, so each bounce only
+                // scales the path by the albedo.
+                let mut throughput = payload.color;
+                let mut position = origin + payload.t * direction;
+                let mut direction = direction;
+                let mut bounce = 0;
+                while bounce < max_bounces {
+                    let normal = face_forward(payload.normal, direction);
+                    let origin = leave_surface(
+                        position,
+                        face_forward(payload.geometric_normal, direction),
+                        ray_epsilon,
+                    );
+                    direction = Onb::build_from_w(normal)
+                        .local_to_world(cosine_hemisphere(rng.next_vec2()));
+
+                    ray_counts.secondary += 1;
+                    trace(
+                        top_level_as,
+                        RayFlags::OPAQUE,
+                        VISIBLE_TO_SECONDARY,
+                        origin,
+                        direction,
+                        0.0,
+                        T_MAX,
+                        payload,
+                    );
+
+                    if payload.is_miss != 0 {
+                        return throughput * payload.color;
+                    }
+
+                    ray_counts.occluded += 1;
+                    throughput *= payload.color;
+                    position = origin + payload.t * direction;
+                    bounce += 1;
+                }
+
+                Vec3::ZERO
+            }
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn trace(
+    top_level_as: &AccelerationStructure,
+    ray_flags: RayFlags,
+    cull_mask: u32,
+    origin: Vec3,
+    direction: Vec3,
+    t_min: f32,
+    t_max: f32,
+    payload: &mut RayPayload,
+) {
+    unsafe {
+        top_level_as.trace_ray(
+            ray_flags,
+            cull_mask as i32,
+            0,
+            0,
+            0,
+            origin,
+            t_min,
+            direction,
+            t_max,
+            payload,
+        );
+    }
+}
+
+/// Origin for rays leaving the surface at `position` on the side `normal` points to.
+fn leave_surface(position: Vec3, normal: Vec3, ray_epsilon: f32) -> Vec3 {
+    if ray_epsilon > 0.0 {
+        position + ray_epsilon * normal
+    } else {
+        offset_ray(position, normal)
+    }
+}
+
+fn face_forward(normal: Vec3, direction: Vec3) -> Vec3 {
+    if normal.dot(direction) > 0.0 {
+        -normal
+    } else {
+        normal
+    }
+}
@@ -0,0 +1,93 @@
+//! Ray tests for the procedural primitives drawn by intersection shaders.
+
+use spirv_std::glam::{vec4, Vec3, Vec4};
+#[cfg(target_arch = "spirv")]
+use spirv_std::num_traits::Float;
+
+/// Returned by the intersection tests when the ray misses.
+const MISS: Vec4 = Vec4::new(-1.0, 0.0, 0.0, 0.0);
+
+/// Intersects a ray with the round cone swept by a sphere moving from `start.xyz` with radius
+/// `start.w` to `end.xyz` with radius `end.w`. `direction` must be normalized.
+///
+/// Returns the distance to the first hit in `x` and the unit surface normal in `yzw`, or a
+/// negative distance if the ray misses. Hits behind the origin are returned as well.
+/// From Inigo Quilez, "Rounded cone - intersection".
+pub fn intersect_round_cone(origin: Vec3, direction: Vec3, start: Vec4, end: Vec4) -> Vec4 {
+    let (a, b) = (start.truncate(), end.truncate());
+    let (ra, rb) = (start.w, end.w);
+
+    let ba = b - a;
+    let oa = origin - a;
+    let ob = origin - b;
+    let rr = ra - rb;
+    let m0 = ba.dot(ba);
+    let m1 = ba.dot(oa);
+    let m2 = ba.dot(direction);
+    let m3 = direction.dot(oa);
+    let m5 = oa.dot(oa);
+    let m6 = ob.dot(direction);
+    let m7 = ob.dot(ob);
+
+    // Cone body between the two spheres.
+    let d2 = m0 - rr * rr;
+    let k2 = d2 - m2 * m2;
+    let k1 = d2 * m3 - m1 * m2 + m2 * rr * ra;
+    let k0 = d2 * m5 - m1 * m1 + m1 * rr * ra * 2.0 - m0 * ra * ra;
+    let h = k1 * k1 - k0 * k2;
+    if h < 0.0 {
+        return MISS;
+    }
+
+    let t = (-h.sqrt() - k1) / k2;
+    let y = m1 - ra * rr + t * m2;
+    if y > 0.0 && y < d2 {
+        let normal = (d2 * (oa + t * direction) - ba * y).normalize();
+        return vec4(t, normal.x, normal.y, normal.z);
+    }
+
+    // Spherical caps at either end.
+    let h1 = m3 * m3 - m5 + ra * ra;
+    let h2 = m6 * m6 - m7 + rb * rb;
+    if h1 < 0.0 && h2 < 0.0 {
+        return MISS;
+    }
+
+    let mut hit = MISS;
+    if h1 > 0.0 {
+        let t = -m3 - h1.sqrt();
+        let normal = (oa + t * direction) / ra;
+        hit = vec4(t, normal.x, normal.y, normal.z);
+    }
+    if h2 > 0.0 {
+        let t = -m6 - h2.sqrt();
+        if h1 <= 0.0 || t < hit.x {
+            let normal = (ob + t * direction) / rb;
+            hit = vec4(t, normal.x, normal.y, normal.z);
+        }
+    }
+    hit
+}
+
+/// Intersects a ray with the sphere at `sphere.xyz` with radius `sphere.w`. `direction` must be
+/// normalized.
+///
+/// Returns the distance to the first hit in `x` and the unit surface normal in `yzw`, or a
+/// negative distance if the ray misses. Rays starting inside the sphere return the exit point.
+pub fn intersect_sphere(origin: Vec3, direction: Vec3, sphere: Vec4) -> Vec4 {
+    let center = sphere.truncate();
+    let radius = sphere.w;
+
+    let oc = origin - center;
+    let b = oc.dot(direction);
+    let c = oc.dot(oc) - radius * radius;
+    let h = b * b - c;
+    if h < 0.0 {
+        return MISS;
+    }
+
+    let h = h.sqrt();
+    let t = if -b - h >= 0.0 { -b - h } else { -b + h };
+    let normal = (oc + t * direction) / radius;
+    vec4(t, normal.x, normal.y, normal.z)
+}
@@ -1,12 +1,80 @@
 #![no_std]
+#![cfg_attr(target_arch = "spirv", feature(asm_experimental_arch))]
+
+pub mod color;
+pub mod debug;
+pub mod filter;
+pub mod integrator;
+pub mod intersection;
+pub mod math;
+pub mod pod;
+pub mod rand;
+pub mod stats;
 
 use spirv_std::{
+    arch::report_intersection,
     glam::{uvec2, vec2, vec3, vec4, UVec3, Vec2, Vec3, Vec4},
-    image::Image,
-    ray_tracing::{AccelerationStructure, RayFlags},
+    image::{Cubemap, Image, SampledImage},
+    ray_tracing::AccelerationStructure,
     spirv,
 };
 
+use crate::{
+    color::ColorSpace,
+    debug::record_assert,
+    filter::PixelFilter,
+    integrator::Integrator,
+    intersection::{intersect_round_cone, intersect_sphere},
+    pod::{
+        CurveSegment, FrameUniforms, MeshInfo, Point, ASSERT_MESH_INDEX_OUT_OF_RANGE,
+        ASSERT_NAN_NORMAL,
+    },
+    rand::DefaultRng,
+    stats::RayCounts,
+};
+
+#[derive(Clone, Copy, Default)]
+pub struct RayPayload {
+    pub color: Vec3,
+    pub normal: Vec3,
+    /// Normal of the hit triangle itself, or of the procedural surface, used to offset rays
+    /// leaving the surface.
+    pub geometric_normal: Vec3,
+    pub t: f32,
+    pub is_miss: u32,
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct Vertex {
+    pub pos: [f32; 3],
+    pub normal: [f32; 3],
+    pub uv: [f32; 2],
+}
+
+/// Column-major 4x3 matrix as provided by the `object_to_world`/`world_to_object` built-ins.
+#[derive(Clone, Copy)]
+#[spirv(matrix)]
+pub struct Affine3 {
+    pub x_axis: Vec3,
+    pub y_axis: Vec3,
+    pub z_axis: Vec3,
+    pub w_axis: Vec3,
+}
+
+impl Affine3 {
+    /// Transforms a normal by the transpose of this matrix. For `world_to_object` that is the
+    /// inverse transpose of `object_to_world`, which is how normals go from object to world space.
+    pub fn transform_normal_transposed(&self, normal: Vec3) -> Vec3 {
+        vec3(
+            self.x_axis.dot(normal),
+            self.y_axis.dot(normal),
+            self.z_axis.dot(normal),
+        )
+        .normalize()
+    }
+}
+
 #[spirv(fragment)]
 pub fn main_fs(output: &mut Vec4, color: Vec3) {
     *output = color.extend(1.0);
@@ -33,57 +101,272 @@ pub fn main_vs(
 }
 
 #[spirv(miss)]
-pub fn main_miss(#[spirv(incoming_ray_payload)] out: &mut Vec3) {
-    *out = vec3(0.5, 0.5, 0.5);
+pub fn main_miss(
+    #[spirv(world_ray_direction)] world_ray_direction: Vec3,
+    #[spirv(descriptor_set = 0, binding = 3)] skybox: &SampledImage<Cubemap>,
+    #[spirv(uniform, descriptor_set = 0, binding = 6)] uniforms: &FrameUniforms,
+    #[spirv(incoming_ray_payload)] out: &mut RayPayload,
+) {
+    // Seams between faces are handled by the sampler: Vulkan always filters cube maps seamlessly.
+    let color: Vec4 = skybox.sample_by_lod(world_ray_direction, 0.0);
+    out.color = color.truncate() * uniforms.sky_intensity;
+    out.is_miss = 1;
 }
 
 #[spirv(closest_hit)]
+#[allow(clippy::too_many_arguments)]
 pub fn main_closest_hit(
-    #[spirv(incoming_ray_payload)] out: &mut Vec3,
+    #[spirv(launch_id)] launch_id: UVec3,
+    #[spirv(incoming_ray_payload)] out: &mut RayPayload,
+    #[spirv(hit_attribute)] barycentrics: &Vec2,
+    #[spirv(instance_id)] id: u32,
+    #[spirv(instance_custom_index)] mesh_index: u32,
+    #[spirv(primitive_id)] primitive_id: u32,
+    #[spirv(ray_tmax)] t: f32,
+    #[spirv(world_to_object)] world_to_object: Affine3,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 2)] colors: &[Vec3],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 4)] vertices: &[Vertex],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 5)] indices: &[u32],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 7)] mesh_infos: &[MeshInfo],
+    #[spirv(uniform, descriptor_set = 0, binding = 6)] uniforms: &FrameUniforms,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 10)] asserts: &mut [u32],
+) {
+    let pixel = uvec2(
+        launch_id.x + uniforms.tile_offset_x,
+        launch_id.y + uniforms.tile_offset_y,
+    );
+
+    if mesh_index as usize >= mesh_infos.len() {
+        record_assert(asserts, ASSERT_MESH_INDEX_OUT_OF_RANGE, pixel);
+        *out = RayPayload {
+            color: vec3(1.0, 0.0, 1.0),
+            ..Default::default()
+        };
+        return;
+    }
+
+    let mesh_info = mesh_infos[mesh_index as usize];
+    let index = (mesh_info.first_index + 3 * primitive_id) as usize;
+    let first_vertex = mesh_info.first_vertex;
+    let v0 = vertices[(first_vertex + indices[index]) as usize];
+    let v1 = vertices[(first_vertex + indices[index + 1]) as usize];
+    let v2 = vertices[(first_vertex + indices[index + 2]) as usize];
+    let n0 = Vec3::from(v0.normal);
+    let n1 = Vec3::from(v1.normal);
+    let n2 = Vec3::from(v2.normal);
+
+    let object_normal =
+        (1.0 - barycentrics.x - barycentrics.y) * n0 + barycentrics.x * n1 + barycentrics.y * n2;
+
+    let normal = world_to_object.transform_normal_transposed(object_normal);
+
+    let object_geometric_normal =
+        (Vec3::from(v1.pos) - Vec3::from(v0.pos)).cross(Vec3::from(v2.pos) - Vec3::from(v0.pos));
+    let geometric_normal = world_to_object.transform_normal_transposed(object_geometric_normal);
+
+    if normal.is_nan() {
+        record_assert(asserts, ASSERT_NAN_NORMAL, pixel);
+    }
+
+    *out = RayPayload {
+        color: colors[id as usize],
+        normal,
+        geometric_normal,
+        t,
+        is_miss: 0,
+    };
+}
+
+/// Intersects a ray with one hair segment. The instance custom index is the curve set's first
+/// segment in the shared segment buffer.
+#[spirv(intersection)]
+pub fn curve_intersection(
+    #[spirv(object_ray_origin)] origin: Vec3,
+    #[spirv(object_ray_direction)] direction: Vec3,
+    #[spirv(instance_custom_index)] first_segment: u32,
+    #[spirv(primitive_id)] primitive_id: u32,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 12)] segments: &[CurveSegment],
+    #[spirv(hit_attribute)] object_normal: &mut Vec3,
+) {
+    let segment = segments[(first_segment + primitive_id) as usize];
+
+    // The cone test wants a unit direction; scale the distance back to the ray's own units.
+    let length = direction.length();
+    let hit = intersect_round_cone(origin, direction / length, segment.start, segment.end);
+    if hit.x < 0.0 {
+        return;
+    }
+
+    *object_normal = vec3(hit.y, hit.z, hit.w);
+    unsafe {
+        report_intersection(hit.x / length, 0);
+    }
+}
+
+#[spirv(closest_hit)]
+pub fn curve_closest_hit(
+    #[spirv(incoming_ray_payload)] out: &mut RayPayload,
+    #[spirv(hit_attribute)] object_normal: &Vec3,
     #[spirv(instance_id)] id: u32,
+    #[spirv(ray_tmax)] t: f32,
+    #[spirv(world_to_object)] world_to_object: Affine3,
     #[spirv(storage_buffer, descriptor_set = 0, binding = 2)] colors: &[Vec3],
 ) {
-    *out = colors[id as usize];
+    // The round cone is smooth, so the shading and geometric normals are the same.
+    let normal = world_to_object.transform_normal_transposed(*object_normal);
+
+    *out = RayPayload {
+        color: colors[id as usize],
+        normal,
+        geometric_normal: normal,
+        t,
+        is_miss: 0,
+    };
+}
+
+/// Intersects a ray with one point of a point cloud, drawn as a small sphere. The instance
+/// custom index is the cloud's first point in the shared point buffer.
+#[spirv(intersection)]
+pub fn point_intersection(
+    #[spirv(object_ray_origin)] origin: Vec3,
+    #[spirv(object_ray_direction)] direction: Vec3,
+    #[spirv(instance_custom_index)] first_point: u32,
+    #[spirv(primitive_id)] primitive_id: u32,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 13)] points: &[Point],
+    #[spirv(hit_attribute)] object_normal: &mut Vec3,
+) {
+    let point = points[(first_point + primitive_id) as usize];
+
+    let length = direction.length();
+    let hit = intersect_sphere(origin, direction / length, point.sphere);
+    if hit.x < 0.0 {
+        return;
+    }
+
+    *object_normal = vec3(hit.y, hit.z, hit.w);
+    unsafe {
+        report_intersection(hit.x / length, 0);
+    }
+}
+
+/// Shades a point with its own colour rather than the instance's, as scans come with one.
+#[spirv(closest_hit)]
+pub fn point_closest_hit(
+    #[spirv(incoming_ray_payload)] out: &mut RayPayload,
+    #[spirv(hit_attribute)] object_normal: &Vec3,
+    #[spirv(instance_custom_index)] first_point: u32,
+    #[spirv(primitive_id)] primitive_id: u32,
+    #[spirv(ray_tmax)] t: f32,
+    #[spirv(world_to_object)] world_to_object: Affine3,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 13)] points: &[Point],
+) {
+    let normal = world_to_object.transform_normal_transposed(*object_normal);
+
+    *out = RayPayload {
+        color: points[(first_point + primitive_id) as usize]
+            .color
+            .truncate(),
+        normal,
+        geometric_normal: normal,
+        t,
+        is_miss: 0,
+    };
 }
 
 #[spirv(ray_generation)]
+#[allow(clippy::too_many_arguments)]
 pub fn main_ray_generation(
     #[spirv(launch_id)] launch_id: UVec3,
-    #[spirv(launch_size)] launch_size: UVec3,
     #[spirv(descriptor_set = 0, binding = 0)] top_level_as: &AccelerationStructure,
-    #[spirv(descriptor_set = 0, binding = 1)] image: &Image!(2D, format = rgba8, sampled = false),
-    #[spirv(ray_payload)] payload: &mut Vec3,
+    #[spirv(descriptor_set = 0, binding = 8)] primary_as: &AccelerationStructure,
+    #[spirv(descriptor_set = 0, binding = 1)] image: &Image!(2D, type = f32, sampled = false),
+    #[spirv(uniform, descriptor_set = 0, binding = 6)] uniforms: &FrameUniforms,
+    #[spirv(descriptor_set = 0, binding = 9)] backplate: &SampledImage<
+        Image!(2D, type = f32, sampled),
+    >,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 11)] stats: &mut [u32],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 14)] accumulation: &mut [Vec4],
+    #[spirv(ray_payload)] payload: &mut RayPayload,
 ) {
-    let pixel_center = vec2(launch_id.x as f32, launch_id.y as f32) + vec2(0.5, 0.5);
-    let in_uv = pixel_center / vec2(launch_size.x as f32, launch_size.y as f32);
+    // Large images are rendered in several dispatches, so the launch id is relative to the
+    // current tile.
+    let pixel = uvec2(
+        launch_id.x + uniforms.tile_offset_x,
+        launch_id.y + uniforms.tile_offset_y,
+    );
+    let image_size = vec2(uniforms.image_width as f32, uniforms.image_height as f32);
 
-    let d = in_uv * 2.0 - Vec2::ONE;
-    let aspect_ratio = launch_size.x as f32 / launch_size.y as f32;
+    let mut rng = DefaultRng::new(
+        pixel.y * uniforms.image_width + pixel.x,
+        uniforms.frame_index,
+    );
+    let integrator = Integrator::from_u32(uniforms.integrator);
+    let pixel_filter = PixelFilter::from_u32(uniforms.pixel_filter);
 
-    let origin = vec3(0.0, 0.0, -2.0);
-    let direction = vec3(d.x * aspect_ratio, -d.y, 1.0).normalize();
-    let cull_mask = 0xff;
-    let tmin = 0.001;
-    let tmax = 1000.0;
+    let origin = uniforms.camera_origin.truncate();
+    let horizontal = uniforms.camera_horizontal.truncate();
+    let vertical = uniforms.camera_vertical.truncate();
+    let forward = uniforms.camera_forward.truncate();
 
-    *payload = Vec3::ZERO;
+    // Accumulate all samples locally so the image is written only once per launch element.
+    let mut color = Vec3::ZERO;
+    let mut weight_sum = 0.0;
+    let mut ray_counts = RayCounts::default();
+    let mut i = 0;
+    while i < uniforms.spp {
+        let (offset, weight) = pixel_filter.sample(uniforms.filter_radius, rng.next_vec2());
+        let in_uv = (pixel.as_vec2() + 0.5 + offset) / image_size;
 
-    unsafe {
-        top_level_as.trace_ray(
-            RayFlags::OPAQUE,
-            cull_mask,
-            0,
-            0,
-            0,
-            origin,
-            tmin,
-            direction,
-            tmax,
-            payload,
-        );
+        let d = in_uv * 2.0 - Vec2::ONE;
+        let direction = (forward + d.x * horizontal - d.y * vertical).normalize();
+
+        // The backplate is mapped to the screen, so only camera rays see it; reflected and
+        // occlusion rays still see the skybox.
+        let backplate_color = if uniforms.backplate != 0 {
+            backplate.sample_by_lod(in_uv, 0.0)
+        } else {
+            Vec4::ZERO
+        };
+
+        let rays_before = ray_counts.total();
+        color += weight
+            * integrator.radiance(
+                primary_as,
+                top_level_as,
+                origin,
+                direction,
+                backplate_color,
+                uniforms.ray_epsilon,
+                uniforms.max_bounces,
+                &mut rng,
+                &mut ray_counts,
+                payload,
+            );
+        ray_counts.record_path(ray_counts.total() - rays_before);
+        weight_sum += weight;
+        i += 1;
+    }
+
+    ray_counts.flush(stats);
+
+    // Frames after the first add their weighted samples to those of the earlier ones.
+    let pixel_index = (pixel.y * uniforms.image_width + pixel.x) as usize;
+    let mut sum = color.extend(weight_sum);
+    if uniforms.frame_index > 0 {
+        sum += accumulation[pixel_index];
+    }
+    accumulation[pixel_index] = sum;
+
+    // Every sample lands within the pixel's own filter footprint, so the weighted average is the
+    // filtered pixel value without splatting into neighbours.
+    let mut color = Vec3::ZERO;
+    if sum.w > 0.0 {
+        color = sum.truncate() / sum.w;
     }
+    color *= uniforms.exposure;
 
+    let output_color_space = ColorSpace::from_u32(uniforms.output_color_space);
     unsafe {
-        image.write(uvec2(launch_id.x, launch_id.y), payload.extend(1.0));
+        image.write(pixel, output_color_space.encode(color).extend(1.0));
     }
 }
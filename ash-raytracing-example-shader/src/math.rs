@@ -0,0 +1,308 @@
+use core::f32::consts::{FRAC_1_PI, PI};
+
+use spirv_std::glam::{vec2, vec3, Vec2, Vec3};
+#[cfg(target_arch = "spirv")]
+use spirv_std::num_traits::Float;
+
+/// Orthonormal basis around a unit vector `w`, used to bring directions sampled in a local
+/// frame (with `z` up) into world space.
+#[derive(Clone, Copy)]
+pub struct Onb {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+
+impl Onb {
+    /// Builds a basis whose `w` axis is `n`. `n` must be normalized.
+    ///
+    /// Branchless construction from Duff et al., "Building an Orthonormal Basis, Revisited".
+    pub fn build_from_w(n: Vec3) -> Self {
+        let sign = if n.z >= 0.0 { 1.0 } else { -1.0 };
+        let a = -1.0 / (sign + n.z);
+        let b = n.x * n.y * a;
+
+        Self {
+            u: vec3(1.0 + sign * n.x * n.x * a, sign * b, -sign * n.x),
+            v: vec3(b, sign + n.y * n.y * a, -n.y),
+            w: n,
+        }
+    }
+
+    /// Transforms a direction from the local frame to world space.
+    pub fn local_to_world(&self, a: Vec3) -> Vec3 {
+        a.x * self.u + a.y * self.v + a.z * self.w
+    }
+
+    /// Transforms a direction from world space to the local frame.
+    pub fn world_to_local(&self, a: Vec3) -> Vec3 {
+        vec3(a.dot(self.u), a.dot(self.v), a.dot(self.w))
+    }
+}
+
+/// Samples a direction on the `z`-up hemisphere with density `cos(theta) / PI`.
+pub fn cosine_hemisphere(u: Vec2) -> Vec3 {
+    let r = u.x.sqrt();
+    let phi = 2.0 * PI * u.y;
+
+    vec3(r * phi.cos(), r * phi.sin(), (1.0 - u.x).max(0.0).sqrt())
+}
+
+pub fn cosine_hemisphere_pdf(cos_theta: f32) -> f32 {
+    cos_theta.max(0.0) * FRAC_1_PI
+}
+
+/// Samples a direction on the `z`-up hemisphere with constant density `1 / (2 PI)`.
+pub fn uniform_hemisphere(u: Vec2) -> Vec3 {
+    let z = u.x;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * u.y;
+
+    vec3(r * phi.cos(), r * phi.sin(), z)
+}
+
+pub fn uniform_hemisphere_pdf() -> f32 {
+    0.5 * FRAC_1_PI
+}
+
+/// GGX (Trowbridge-Reitz) normal distribution for a half vector at `cos_theta` from the normal.
+pub fn ggx_d(cos_theta: f32, alpha: f32) -> f32 {
+    if cos_theta <= 0.0 {
+        return 0.0;
+    }
+
+    let a2 = alpha * alpha;
+    let d = cos_theta * cos_theta * (a2 - 1.0) + 1.0;
+    a2 / (PI * d * d)
+}
+
+/// Samples a GGX half vector on the `z`-up hemisphere with density `D(h) * cos(theta_h)`.
+pub fn ggx_half_vector(u: Vec2, alpha: f32) -> Vec3 {
+    let a2 = alpha * alpha;
+    let cos_theta = ((1.0 - u.x) / (1.0 + (a2 - 1.0) * u.x)).max(0.0).sqrt();
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * u.y;
+
+    vec3(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
+
+pub fn ggx_half_vector_pdf(cos_theta: f32, alpha: f32) -> f32 {
+    ggx_d(cos_theta, alpha) * cos_theta.max(0.0)
+}
+
+/// Moves a hit point off its surface along the geometric normal `n` so that rays leaving it
+/// don't hit the same triangle again.
+///
+/// The offset is a fixed number of ulps, so it scales with the magnitude of the position instead
+/// of being a fixed distance that is too small far from the origin and too large for small
+/// geometry. Close to the origin, where ulps get tiny, a small constant offset is used instead.
+/// From Wächter and Binder, "A Fast and Robust Method for Avoiding Self-Intersection".
+pub fn offset_ray(p: Vec3, n: Vec3) -> Vec3 {
+    vec3(
+        offset_component(p.x, n.x),
+        offset_component(p.y, n.y),
+        offset_component(p.z, n.z),
+    )
+}
+
+fn offset_component(p: f32, n: f32) -> f32 {
+    const ORIGIN: f32 = 1.0 / 32.0;
+    const FLOAT_SCALE: f32 = 1.0 / 65536.0;
+    const INT_SCALE: f32 = 256.0;
+
+    if p.abs() < ORIGIN {
+        return p + FLOAT_SCALE * n;
+    }
+
+    let offset = (INT_SCALE * n) as i32;
+    let offset = if p < 0.0 { -offset } else { offset };
+    f32::from_bits((p.to_bits() as i32 + offset) as u32)
+}
+
+/// Samples a point uniformly on a triangle, returned as the barycentrics `(b1, b2)` of the
+/// second and third vertices.
+pub fn uniform_triangle(u: Vec2) -> Vec2 {
+    let su = u.x.sqrt();
+    vec2(u.y * su, 1.0 - su)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use super::*;
+    use crate::rand::DefaultRng;
+
+    const SAMPLES: u32 = 200_000;
+
+    fn samples(seed: u32) -> impl Iterator<Item = Vec2> {
+        let mut rng = DefaultRng::new(0, seed);
+        (0..SAMPLES).map(move |_| rng.next_vec2())
+    }
+
+    /// Uniformly distributed unit vector, from two uniform samples.
+    fn unit_vector(u: Vec2) -> Vec3 {
+        let z = 1.0 - 2.0 * u.x;
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * PI * u.y;
+        vec3(r * phi.cos(), r * phi.sin(), z)
+    }
+
+    /// Monte Carlo estimate of the integral of `f` over the `z`-up hemisphere.
+    fn integrate_hemisphere(seed: u32, f: impl Fn(Vec3) -> f32) -> f32 {
+        samples(seed)
+            .map(|u| f(uniform_hemisphere(u)) / uniform_hemisphere_pdf())
+            .sum::<f32>()
+            / SAMPLES as f32
+    }
+
+    fn mean(values: impl Iterator<Item = Vec3>) -> Vec3 {
+        values.fold(Vec3::ZERO, |sum, value| sum + value) / SAMPLES as f32
+    }
+
+    #[test]
+    fn onb_is_orthonormal() {
+        // Include the poles, where the construction switches sign.
+        let normals = samples(1)
+            .map(unit_vector)
+            .chain([Vec3::Z, -Vec3::Z, Vec3::X, -Vec3::Y]);
+
+        for n in normals {
+            let onb = Onb::build_from_w(n);
+            for axis in [onb.u, onb.v, onb.w] {
+                assert!((axis.length() - 1.0).abs() < 1e-4, "{:?} for {:?}", axis, n);
+            }
+            assert!(onb.u.dot(onb.v).abs() < 1e-4, "{:?}", n);
+            assert!(onb.u.dot(onb.w).abs() < 1e-4, "{:?}", n);
+            assert!(onb.v.dot(onb.w).abs() < 1e-4, "{:?}", n);
+            // Right-handed, so local z up maps to `w`.
+            assert!((onb.u.cross(onb.v) - onb.w).length() < 1e-4, "{:?}", n);
+
+            let a = vec3(0.3, -0.5, 0.8);
+            assert!((onb.world_to_local(onb.local_to_world(a)) - a).length() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn uniform_hemisphere_moments() {
+        let directions = samples(2).map(uniform_hemisphere).collect::<Vec<_>>();
+        for d in &directions {
+            assert!((d.length() - 1.0).abs() < 1e-4 && d.z >= 0.0, "{:?}", d);
+        }
+
+        // E[z] = 1/2, and x and y are symmetric around zero.
+        let mean = mean(directions.into_iter());
+        assert!((mean - vec3(0.0, 0.0, 0.5)).length() < 0.01, "{:?}", mean);
+    }
+
+    #[test]
+    fn cosine_hemisphere_moments() {
+        let directions = samples(3).map(cosine_hemisphere).collect::<Vec<_>>();
+        for d in &directions {
+            assert!((d.length() - 1.0).abs() < 1e-4 && d.z >= 0.0, "{:?}", d);
+        }
+
+        // E[z] = integral of cos^2 / PI over the hemisphere = 2/3.
+        let mean = mean(directions.into_iter());
+        assert!(
+            (mean - vec3(0.0, 0.0, 2.0 / 3.0)).length() < 0.01,
+            "{:?}",
+            mean
+        );
+    }
+
+    #[test]
+    fn hemisphere_pdfs_integrate_to_one() {
+        let uniform = integrate_hemisphere(4, |_| uniform_hemisphere_pdf());
+        assert!((uniform - 1.0).abs() < 1e-4, "{}", uniform);
+
+        let cosine = integrate_hemisphere(5, |d| cosine_hemisphere_pdf(d.z));
+        assert!((cosine - 1.0).abs() < 0.01, "{}", cosine);
+    }
+
+    #[test]
+    fn ggx_pdf_integrates_to_one_and_matches_samples() {
+        for alpha in [0.3, 0.6, 1.0] {
+            let total = integrate_hemisphere(6, |h| ggx_half_vector_pdf(h.z, alpha));
+            assert!((total - 1.0).abs() < 0.03, "alpha {}: {}", alpha, total);
+
+            // The mean cosine of the samples has to agree with the pdf they claim to follow.
+            let expected = integrate_hemisphere(7, |h| h.z * ggx_half_vector_pdf(h.z, alpha));
+            let directions = samples(8)
+                .map(|u| ggx_half_vector(u, alpha))
+                .collect::<Vec<_>>();
+            for h in &directions {
+                assert!((h.length() - 1.0).abs() < 1e-4 && h.z >= 0.0, "{:?}", h);
+            }
+            let mean = mean(directions.into_iter());
+            assert!(
+                (mean.z - expected).abs() < 0.01,
+                "alpha {}: {} vs {}",
+                alpha,
+                mean.z,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn uniform_triangle_is_uniform() {
+        // The midpoints split the triangle into four of equal area: three at the corners and
+        // the one in the middle, which has every barycentric below 1/2.
+        let mut counts = [0u32; 4];
+        let mut sum = Vec2::ZERO;
+        for u in samples(9) {
+            let b = uniform_triangle(u);
+            let b0 = 1.0 - b.x - b.y;
+            assert!(b.x >= 0.0 && b.y >= 0.0 && b0 >= -1e-6, "{:?}", b);
+
+            let corner = if b0 > 0.5 {
+                0
+            } else if b.x > 0.5 {
+                1
+            } else if b.y > 0.5 {
+                2
+            } else {
+                3
+            };
+            counts[corner] += 1;
+            sum += b;
+        }
+
+        for count in counts {
+            let fraction = count as f32 / SAMPLES as f32;
+            assert!((fraction - 0.25).abs() < 0.01, "{:?}", counts);
+        }
+
+        let mean = sum / SAMPLES as f32;
+        assert!(
+            (mean - Vec2::splat(1.0 / 3.0)).length() < 0.01,
+            "{:?}",
+            mean
+        );
+    }
+
+    #[test]
+    fn offset_ray_leaves_the_surface_at_any_scale() {
+        // Points on the plane x + y = 2c, from near the origin to as far as rays reach.
+        let n = vec3(1.0, 1.0, 0.0).normalize();
+        for c in [1e-3f32, 0.5, 10.0, 800.0, 1e5] {
+            let p = vec3(c, c, 0.25);
+            let offset = offset_ray(p, n);
+            assert!(offset.x > p.x && offset.y > p.y, "{:?} -> {:?}", p, offset);
+            assert!(
+                (offset - p).length() < 1e-3 * c.max(1.0),
+                "{:?} -> {:?}",
+                p,
+                offset
+            );
+        }
+
+        // A fixed epsilon that is fine near the origin vanishes in rounding a few hundred units
+        // away, which is what `--ray-epsilon` shows in the grazing scene.
+        let far = vec3(800.0, 800.0, 0.25);
+        assert_eq!(far + 1e-5 * n, far);
+    }
+}
@@ -0,0 +1,106 @@
+use spirv_std::glam::Vec4;
+
+/// Per-frame parameters shared by the ray tracing stages, bound as a uniform buffer.
+///
+/// Mirrored by `FrameUniforms` in the host crate. Vectors are stored as `Vec4` so the layout
+/// is the same on the host and in std140.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct FrameUniforms {
+    /// xyz: camera position.
+    pub camera_origin: Vec4,
+    /// xyz: camera right vector, scaled by the half-width of the image plane at distance 1.
+    pub camera_horizontal: Vec4,
+    /// xyz: camera up vector, scaled by the half-height of the image plane at distance 1.
+    pub camera_vertical: Vec4,
+    /// xyz: unit view direction.
+    pub camera_forward: Vec4,
+    /// Frames accumulated into the image so far. Frame 0 starts over; later ones add their
+    /// samples to the accumulation buffer.
+    pub frame_index: u32,
+    pub spp: u32,
+    pub integrator: u32,
+    /// Multiplier applied to the skybox radiance.
+    pub sky_intensity: f32,
+    /// Linear scale applied to the final pixel colour.
+    pub exposure: f32,
+    /// Size of the whole output image. A dispatch may cover only one tile of it.
+    pub image_width: u32,
+    pub image_height: u32,
+    /// Pixel position of the current dispatch's top-left corner.
+    pub tile_offset_x: u32,
+    pub tile_offset_y: u32,
+    /// Non-zero if camera rays that miss show the backplate image instead of the skybox.
+    pub backplate: u32,
+    /// `PixelFilter` used to weight samples within a pixel.
+    pub pixel_filter: u32,
+    /// Half-width of the filter footprint, in pixels.
+    pub filter_radius: f32,
+    /// `ColorSpace` the output image is written in.
+    pub output_color_space: u32,
+    /// If positive, rays leaving a surface start this far along its normal instead of at the
+    /// position `offset_ray` picks. Only meant to show the self-intersection it causes.
+    pub ray_epsilon: f32,
+    /// Bounces a path takes before it is cut off.
+    pub max_bounces: u32,
+    pub _padding: u32,
+}
+
+/// Instance mask bits. Each kind of ray only hits instances whose mask shares a bit with the
+/// ray's cull mask.
+pub const VISIBLE_TO_CAMERA: u32 = 1 << 0;
+/// Occlusion-only rays, such as ambient occlusion.
+pub const VISIBLE_TO_SHADOW: u32 = 1 << 1;
+/// Rays that continue a path from a surface, such as the path tracer's bounces.
+pub const VISIBLE_TO_SECONDARY: u32 = 1 << 2;
+
+/// Where a mesh's vertices and indices start in the shared vertex and index buffers, looked up
+/// by instance custom index in closest hit.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct MeshInfo {
+    pub first_index: u32,
+    pub first_vertex: u32,
+}
+
+/// One tapered piece of a hair strand. The curve intersection shader looks it up by instance
+/// custom index plus primitive id.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct CurveSegment {
+    /// xyz: start point, w: radius there.
+    pub start: Vec4,
+    /// xyz: end point, w: radius there.
+    pub end: Vec4,
+}
+
+/// One point of a point cloud. Looked up by instance custom index plus primitive id.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct Point {
+    /// xyz: centre, w: radius.
+    pub sphere: Vec4,
+    /// rgb: colour.
+    pub color: Vec4,
+}
+
+/// Kinds of invariant violation recorded in the assert buffer.
+pub const ASSERT_NAN_NORMAL: u32 = 1;
+pub const ASSERT_MESH_INDEX_OUT_OF_RANGE: u32 = 2;
+
+/// Violations the assert buffer has room for. Later ones are only counted.
+///
+/// The buffer holds the total count followed by `(kind, pixel x, pixel y)` for each record.
+pub const MAX_ASSERT_RECORDS: u32 = 64;
+
+/// Counters in the statistics buffer. They wrap after 2^32 rays, so the host adds them up and
+/// clears them after every pass.
+pub const STAT_PRIMARY_RAYS: usize = 0;
+pub const STAT_SECONDARY_RAYS: usize = 1;
+pub const STAT_CAMERA_MISSES: usize = 2;
+/// Secondary rays that hit something before their maximum distance.
+pub const STAT_OCCLUDED_RAYS: usize = 3;
+/// First of `PATH_LENGTH_BINS` counters holding how many paths traced 1, 2, ... rays. The last
+/// bin also counts all longer paths.
+pub const STAT_PATH_LENGTHS: usize = 4;
+pub const PATH_LENGTH_BINS: usize = 4;
@@ -0,0 +1,38 @@
+use spirv_std::glam::{vec2, Vec2};
+
+/// PCG-based random number generator, cheap enough to keep per invocation.
+///
+/// See Jarzynski and Olano, "Hash Functions for GPU Rendering".
+#[derive(Clone, Copy)]
+pub struct DefaultRng {
+    state: u32,
+}
+
+impl DefaultRng {
+    /// Creates a generator for one launch element, decorrelated by a per-dispatch `seed`.
+    pub fn new(index: u32, seed: u32) -> Self {
+        Self {
+            state: pcg_hash(index ^ pcg_hash(seed)),
+        }
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        self.state = pcg_hash(self.state);
+        self.state
+    }
+
+    /// Returns a float uniformly distributed in `[0, 1)`.
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 * (1.0 / (1u32 << 24) as f32)
+    }
+
+    pub fn next_vec2(&mut self) -> Vec2 {
+        vec2(self.next_f32(), self.next_f32())
+    }
+}
+
+fn pcg_hash(input: u32) -> u32 {
+    let state = input.wrapping_mul(747796405).wrapping_add(2891336453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+    (word >> 22) ^ word
+}
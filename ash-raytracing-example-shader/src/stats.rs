@@ -0,0 +1,67 @@
+//! Ray statistics. Each launch element counts locally and adds its totals to the statistics
+//! buffer once, so the atomics don't contend on every ray.
+
+use spirv_std::{
+    arch::atomic_i_add,
+    memory::{Scope, Semantics},
+};
+
+use crate::pod::{
+    PATH_LENGTH_BINS, STAT_CAMERA_MISSES, STAT_OCCLUDED_RAYS, STAT_PATH_LENGTHS, STAT_PRIMARY_RAYS,
+    STAT_SECONDARY_RAYS,
+};
+
+#[derive(Clone, Copy, Default)]
+pub struct RayCounts {
+    pub primary: u32,
+    pub secondary: u32,
+    pub camera_misses: u32,
+    pub occluded: u32,
+    pub path_lengths: [u32; PATH_LENGTH_BINS],
+}
+
+impl RayCounts {
+    pub fn total(&self) -> u32 {
+        self.primary + self.secondary
+    }
+
+    /// Records a finished path that traced `rays` rays.
+    pub fn record_path(&mut self, rays: u32) {
+        // `Ord::min` goes through `Ordering`, which needs Int8.
+        let bin = if rays as usize >= PATH_LENGTH_BINS {
+            PATH_LENGTH_BINS - 1
+        } else if rays > 0 {
+            rays as usize - 1
+        } else {
+            0
+        };
+        self.path_lengths[bin] += 1;
+    }
+
+    /// Adds these counts to the statistics buffer.
+    pub fn flush(&self, stats: &mut [u32]) {
+        add(stats, STAT_PRIMARY_RAYS, self.primary);
+        add(stats, STAT_SECONDARY_RAYS, self.secondary);
+        add(stats, STAT_CAMERA_MISSES, self.camera_misses);
+        add(stats, STAT_OCCLUDED_RAYS, self.occluded);
+
+        let mut bin = 0;
+        while bin < PATH_LENGTH_BINS {
+            add(stats, STAT_PATH_LENGTHS + bin, self.path_lengths[bin]);
+            bin += 1;
+        }
+    }
+}
+
+fn add(stats: &mut [u32], counter: usize, value: u32) {
+    if value == 0 {
+        return;
+    }
+
+    unsafe {
+        atomic_i_add::<u32, { Scope::QueueFamily as u32 }, { Semantics::NONE.bits() }>(
+            &mut stats[counter],
+            value,
+        );
+    }
+}
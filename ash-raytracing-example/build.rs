@@ -1,16 +1,51 @@
 use std::error::Error;
 
-use spirv_builder::{Capability, MetadataPrintout, SpirvBuilder};
+use spirv_builder::{
+    Capability, MetadataPrintout, ShaderPanicStrategy, SpirvBuilder, SpirvMetadata,
+};
+
+fn feature_enabled(feature: &str) -> bool {
+    let name = feature.to_uppercase().replace('-', "_");
+    std::env::var_os(format!("CARGO_FEATURE_{}", name)).is_some()
+}
 
 fn main() -> Result<(), Box<dyn Error>> {
-    SpirvBuilder::new(
+    // `cargo clippy` wraps rustc with clippy-driver, which would otherwise also compile the
+    // shader crate and turns off the MIR inlining rust-gpu relies on.
+    std::env::remove_var("RUSTC_WORKSPACE_WRAPPER");
+
+    let mut builder = SpirvBuilder::new(
         "../ash-raytracing-example-shader",
         "spirv-unknown-vulkan1.2",
     )
     .capability(Capability::RayTracingKHR)
+    // The storage image is written through views of more than one format.
+    .capability(Capability::StorageImageWriteWithoutFormat)
     .extension("SPV_KHR_ray_tracing")
-    .print_metadata(MetadataPrintout::Full)
-    .build()?;
+    .print_metadata(MetadataPrintout::Full);
+
+    // Turns the shader crate's `debug_print!` into real `debugPrintfEXT` calls, and makes
+    // panicking invocations print their message before they exit.
+    if feature_enabled("debug-printf") {
+        builder = builder
+            .extension("SPV_KHR_non_semantic_info")
+            .shader_panic_strategy(ShaderPanicStrategy::DebugPrintfThenExit {
+                print_inputs: true,
+                print_backtrace: true,
+            });
+    }
+
+    // Keeps names and source locations for graphics debuggers.
+    if feature_enabled("shader-debug-info") {
+        builder = builder.spirv_metadata(SpirvMetadata::Full);
+    }
+
+    // Skips optimization, so the SPIR-V maps more directly onto the Rust source.
+    if feature_enabled("shader-unoptimized") {
+        builder = builder.release(false);
+    }
+
+    builder.build()?;
 
     Ok(())
 }
@@ -0,0 +1,121 @@
+//! Bookkeeping for device memory, so the example can report where its allocations go and how
+//! close it is to the driver's budget.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use ash::vk;
+
+/// Warn once a heap's usage passes this fraction of its budget.
+const BUDGET_WARNING_THRESHOLD: f64 = 0.9;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AllocationCategory {
+    AccelerationStructure,
+    Scratch,
+    /// Vertex, index, instance and per-mesh or per-instance data.
+    Geometry,
+    Texture,
+    /// Render targets and readback images.
+    Image,
+    Uniform,
+    ShaderBindingTable,
+    Staging,
+    /// Buffers the shaders write diagnostics into.
+    Debug,
+}
+
+impl AllocationCategory {
+    const ALL: [Self; 9] = [
+        Self::AccelerationStructure,
+        Self::Scratch,
+        Self::Geometry,
+        Self::Texture,
+        Self::Image,
+        Self::Uniform,
+        Self::ShaderBindingTable,
+        Self::Staging,
+        Self::Debug,
+    ];
+
+    /// Whether this kind of resource may live in host-visible memory when device-local memory
+    /// runs out. It is slower to access, but better than failing to render.
+    pub fn allows_host_fallback(self) -> bool {
+        matches!(self, Self::Texture | Self::Geometry)
+    }
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: AtomicU64 = AtomicU64::new(0);
+
+static CURRENT: [AtomicU64; AllocationCategory::ALL.len()] = [ZERO; AllocationCategory::ALL.len()];
+static PEAK: [AtomicU64; AllocationCategory::ALL.len()] = [ZERO; AllocationCategory::ALL.len()];
+
+pub fn record_allocation(category: AllocationCategory, size: vk::DeviceSize) {
+    let current = CURRENT[category as usize].fetch_add(size, Ordering::Relaxed) + size;
+    PEAK[category as usize].fetch_max(current, Ordering::Relaxed);
+}
+
+pub fn record_free(category: AllocationCategory, size: vk::DeviceSize) {
+    CURRENT[category as usize].fetch_sub(size, Ordering::Relaxed);
+}
+
+/// Prints the live and peak allocation size per category, followed by each heap's usage and
+/// budget when `VK_EXT_memory_budget` is enabled.
+pub fn print_report(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    memory_budget_enabled: bool,
+) {
+    println!("Device memory:");
+
+    for category in AllocationCategory::ALL {
+        println!(
+            "  {:<22} {:>10} KiB (peak {} KiB)",
+            format!("{:?}", category),
+            CURRENT[category as usize].load(Ordering::Relaxed) / 1024,
+            PEAK[category as usize].load(Ordering::Relaxed) / 1024,
+        );
+    }
+
+    if !memory_budget_enabled {
+        println!("  VK_EXT_memory_budget is not supported; heap budgets are unavailable.");
+        return;
+    }
+
+    let mut budget_properties = vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+    let mut memory_properties2 = vk::PhysicalDeviceMemoryProperties2::builder()
+        .push_next(&mut budget_properties)
+        .build();
+
+    unsafe {
+        instance.get_physical_device_memory_properties2(physical_device, &mut memory_properties2)
+    };
+
+    let memory_properties = memory_properties2.memory_properties;
+
+    for (i, heap) in memory_properties.memory_heaps[..memory_properties.memory_heap_count as usize]
+        .iter()
+        .enumerate()
+    {
+        let usage = budget_properties.heap_usage[i];
+        let budget = budget_properties.heap_budget[i];
+        let device_local = heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL);
+
+        println!(
+            "  heap {}{}: {} MiB used of {} MiB budget ({} MiB total)",
+            i,
+            if device_local { " (device local)" } else { "" },
+            usage / (1024 * 1024),
+            budget / (1024 * 1024),
+            heap.size / (1024 * 1024),
+        );
+
+        if budget > 0 && usage as f64 > budget as f64 * BUDGET_WARNING_THRESHOLD {
+            eprintln!(
+                "warning: heap {} is at {:.0}% of its budget; larger scenes may fail to allocate.",
+                i,
+                usage as f64 / budget as f64 * 100.0,
+            );
+        }
+    }
+}
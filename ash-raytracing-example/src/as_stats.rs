@@ -0,0 +1,66 @@
+//! Sizes of the acceleration structures built for the scene, printed as a table once the scene
+//! is ready so the cost of each mesh and of compaction is easy to compare.
+
+use ash::vk;
+
+#[derive(Clone, Debug)]
+pub struct AccelerationStructureStats {
+    pub name: String,
+    /// Triangles for a BLAS, instances for a TLAS.
+    pub primitive_count: u32,
+    /// Size the build asked for.
+    pub built_size: vk::DeviceSize,
+    /// Size after compaction, or `built_size` if the structure wasn't compacted.
+    pub final_size: vk::DeviceSize,
+    pub scratch_size: vk::DeviceSize,
+}
+
+pub fn print_table(stats: &[AccelerationStructureStats]) {
+    println!("Acceleration structures:");
+    println!(
+        "  {:<14} {:>10} {:>12} {:>12} {:>12} {:>7}",
+        "name", "primitives", "built KiB", "final KiB", "scratch KiB", "saved"
+    );
+
+    for stats in stats {
+        print_row(
+            &stats.name,
+            stats.primitive_count,
+            stats.built_size,
+            stats.final_size,
+            stats.scratch_size,
+        );
+    }
+
+    print_row(
+        "total",
+        stats.iter().map(|stats| stats.primitive_count).sum(),
+        stats.iter().map(|stats| stats.built_size).sum(),
+        stats.iter().map(|stats| stats.final_size).sum(),
+        stats.iter().map(|stats| stats.scratch_size).sum(),
+    );
+}
+
+fn print_row(
+    name: &str,
+    primitive_count: u32,
+    built_size: vk::DeviceSize,
+    final_size: vk::DeviceSize,
+    scratch_size: vk::DeviceSize,
+) {
+    let saved = if built_size > 0 {
+        (built_size - final_size) as f64 / built_size as f64 * 100.0
+    } else {
+        0.0
+    };
+
+    println!(
+        "  {:<14} {:>10} {:>12} {:>12} {:>12} {:>6.1}%",
+        name,
+        primitive_count,
+        built_size / 1024,
+        final_size / 1024,
+        scratch_size / 1024,
+        saved,
+    );
+}
@@ -0,0 +1,626 @@
+//! Bounding volume hierarchy over a mesh's triangles, built on the host with binned SAH.
+//!
+//! The hardware path never needs this; it exists for the planned software traversal fallback
+//! and to compare BVH quality across meshes. `Bvh::intersect` is the reference traversal the
+//! fallback will be checked against.
+
+use glam::Vec3;
+
+use crate::mesh::Mesh;
+
+#[derive(Clone, Copy, Debug)]
+pub struct BvhOptions {
+    /// Nodes with at most this many triangles become leaves once splitting stops paying off.
+    pub max_leaf_size: usize,
+    /// Number of centroid bins evaluated per axis when choosing a split.
+    pub bin_count: usize,
+    /// Relative cost of visiting an interior node, against one triangle intersection.
+    pub traversal_cost: f32,
+}
+
+impl Default for BvhOptions {
+    fn default() -> Self {
+        Self {
+            max_leaf_size: 4,
+            bin_count: 12,
+            traversal_cost: 1.0,
+        }
+    }
+}
+
+/// Flattened node, laid out for upload as a storage buffer.
+///
+/// Leaves have `count > 0` and cover `primitive_indices[first..first + count]`. Interior nodes
+/// have `count == 0`, their left child directly follows them and `first` is the right child.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct BvhNode {
+    pub min: [f32; 3],
+    pub first: u32,
+    pub max: [f32; 3],
+    pub count: u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct Bvh {
+    pub nodes: Vec<BvhNode>,
+    /// Triangle indices, reordered so every leaf covers a contiguous range.
+    pub primitive_indices: Vec<u32>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct BvhStats {
+    pub node_count: usize,
+    pub leaf_count: usize,
+    pub max_depth: usize,
+    pub max_leaf_size: usize,
+    /// Expected cost of a random ray hitting the root, in triangle intersections.
+    pub sah_cost: f32,
+}
+
+/// Closest intersection found by `Bvh::intersect`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Hit {
+    /// Distance along the ray, in multiples of its direction.
+    pub t: f32,
+    /// Index of the triangle in the mesh, not in `primitive_indices`.
+    pub triangle: u32,
+    /// Barycentrics of the second and third vertices.
+    pub barycentrics: [f32; 2],
+}
+
+#[derive(Clone, Copy)]
+struct Aabb {
+    min: Vec3,
+    max: Vec3,
+}
+
+impl Aabb {
+    const EMPTY: Self = Self {
+        min: Vec3::splat(f32::INFINITY),
+        max: Vec3::splat(f32::NEG_INFINITY),
+    };
+
+    fn grow(&mut self, point: Vec3) {
+        self.min = self.min.min(point);
+        self.max = self.max.max(point);
+    }
+
+    fn union(self, other: Self) -> Self {
+        Self {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    fn surface_area(&self) -> f32 {
+        let extent = (self.max - self.min).max(Vec3::ZERO);
+        2.0 * (extent.x * extent.y + extent.y * extent.z + extent.z * extent.x)
+    }
+
+    /// Slab test. Returns where the ray enters the box if it overlaps `[t_min, t_max]`.
+    fn intersect(&self, origin: Vec3, inv_direction: Vec3, t_min: f32, t_max: f32) -> Option<f32> {
+        let t0 = (self.min - origin) * inv_direction;
+        let t1 = (self.max - origin) * inv_direction;
+        // `max_element` and `min_element` skip the NaNs of axis-parallel rays starting on a slab.
+        let t_entry = t0.min(t1).max_element().max(t_min);
+        let t_exit = t0.max(t1).min_element().min(t_max);
+        (t_entry <= t_exit).then_some(t_entry)
+    }
+}
+
+/// Möller-Trumbore ray/triangle intersection. Returns the distance and the barycentrics of `b`
+/// and `c` if the ray hits the triangle with `t` in `[t_min, t_max]`.
+pub fn intersect_triangle(
+    origin: Vec3,
+    direction: Vec3,
+    a: Vec3,
+    b: Vec3,
+    c: Vec3,
+    t_min: f32,
+    t_max: f32,
+) -> Option<(f32, [f32; 2])> {
+    let edge1 = b - a;
+    let edge2 = c - a;
+    let p = direction.cross(edge2);
+    let determinant = edge1.dot(p);
+    if determinant.abs() < f32::EPSILON {
+        return None;
+    }
+
+    let inv_determinant = 1.0 / determinant;
+    let s = origin - a;
+    let u = s.dot(p) * inv_determinant;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+
+    let q = s.cross(edge1);
+    let v = direction.dot(q) * inv_determinant;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    let t = edge2.dot(q) * inv_determinant;
+    (t_min..=t_max).contains(&t).then_some((t, [u, v]))
+}
+
+struct Primitive {
+    bounds: Aabb,
+    centroid: Vec3,
+}
+
+impl Bvh {
+    pub fn build(mesh: &Mesh, options: &BvhOptions) -> Self {
+        let primitives = mesh
+            .indices
+            .chunks_exact(3)
+            .map(|triangle| {
+                let mut bounds = Aabb::EMPTY;
+                for &index in triangle {
+                    bounds.grow(Vec3::from(mesh.vertices[index as usize].pos));
+                }
+                Primitive {
+                    bounds,
+                    centroid: (bounds.min + bounds.max) * 0.5,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let mut bvh = Self {
+            nodes: Vec::with_capacity(primitives.len() * 2),
+            primitive_indices: (0..primitives.len() as u32).collect(),
+        };
+
+        if !primitives.is_empty() {
+            bvh.build_node(&primitives, 0, primitives.len(), options);
+        }
+
+        bvh
+    }
+
+    /// Builds the node covering `primitive_indices[start..end]` and returns its index.
+    fn build_node(
+        &mut self,
+        primitives: &[Primitive],
+        start: usize,
+        end: usize,
+        options: &BvhOptions,
+    ) -> usize {
+        let mut bounds = Aabb::EMPTY;
+        let mut centroid_bounds = Aabb::EMPTY;
+        for &primitive in &self.primitive_indices[start..end] {
+            bounds = bounds.union(primitives[primitive as usize].bounds);
+            centroid_bounds.grow(primitives[primitive as usize].centroid);
+        }
+
+        let node_index = self.nodes.len();
+        self.nodes.push(BvhNode {
+            min: bounds.min.to_array(),
+            first: start as u32,
+            max: bounds.max.to_array(),
+            count: (end - start) as u32,
+        });
+
+        let count = end - start;
+        if count == 1 {
+            return node_index;
+        }
+
+        let split = self.find_split(
+            primitives,
+            start,
+            end,
+            &centroid_bounds,
+            bounds.surface_area(),
+            options,
+        );
+        let leaf_cost = count as f32;
+
+        let mid = match split {
+            Some((axis, position, cost)) if count > options.max_leaf_size || cost < leaf_cost => {
+                let mid = partition(&mut self.primitive_indices[start..end], |&primitive| {
+                    primitives[primitive as usize].centroid[axis] < position
+                }) + start;
+                // Every centroid landed on one side, which binning can't rule out for
+                // near-coincident centroids; fall back to an even split.
+                if mid == start || mid == end {
+                    start + count / 2
+                } else {
+                    mid
+                }
+            }
+            // All centroids coincide, so no plane separates them.
+            None if count > options.max_leaf_size => start + count / 2,
+            _ => return node_index,
+        };
+
+        self.build_node(primitives, start, mid, options);
+        let right = self.build_node(primitives, mid, end, options);
+
+        let node = &mut self.nodes[node_index];
+        node.first = right as u32;
+        node.count = 0;
+
+        node_index
+    }
+
+    /// Returns the axis, plane and SAH cost of the cheapest binned split, or `None` if the
+    /// centroids have no extent.
+    fn find_split(
+        &self,
+        primitives: &[Primitive],
+        start: usize,
+        end: usize,
+        centroid_bounds: &Aabb,
+        parent_area: f32,
+        options: &BvhOptions,
+    ) -> Option<(usize, f32, f32)> {
+        let mut best: Option<(usize, f32, f32)> = None;
+        let bin_count = options.bin_count.max(2);
+
+        for axis in 0..3 {
+            let axis_min = centroid_bounds.min[axis];
+            let axis_extent = centroid_bounds.max[axis] - axis_min;
+            if axis_extent <= 0.0 {
+                continue;
+            }
+
+            let mut bins = vec![(Aabb::EMPTY, 0usize); bin_count];
+            let scale = bin_count as f32 / axis_extent;
+
+            for &primitive in &self.primitive_indices[start..end] {
+                let primitive = &primitives[primitive as usize];
+                let bin =
+                    (((primitive.centroid[axis] - axis_min) * scale) as usize).min(bin_count - 1);
+                bins[bin].0 = bins[bin].0.union(primitive.bounds);
+                bins[bin].1 += 1;
+            }
+
+            // Sweep from the right first so each candidate plane can combine both sides.
+            let mut right_costs = vec![0.0; bin_count];
+            let mut right_bounds = Aabb::EMPTY;
+            let mut right_count = 0;
+            for bin in (1..bin_count).rev() {
+                right_bounds = right_bounds.union(bins[bin].0);
+                right_count += bins[bin].1;
+                right_costs[bin] = right_bounds.surface_area() * right_count as f32;
+            }
+
+            let mut left_bounds = Aabb::EMPTY;
+            let mut left_count = 0;
+            for bin in 1..bin_count {
+                left_bounds = left_bounds.union(bins[bin - 1].0);
+                left_count += bins[bin - 1].1;
+
+                if left_count == 0 || left_count == end - start {
+                    continue;
+                }
+
+                let cost = left_bounds.surface_area() * left_count as f32 + right_costs[bin];
+                if best.map_or(true, |(_, _, best_cost)| cost < best_cost) {
+                    best = Some((axis, axis_min + bin as f32 / scale, cost));
+                }
+            }
+        }
+
+        best.map(|(axis, position, cost)| {
+            (
+                axis,
+                position,
+                options.traversal_cost + cost / parent_area.max(f32::MIN_POSITIVE),
+            )
+        })
+    }
+
+    /// Finds the closest triangle of `mesh`, which this BVH must have been built for, that the
+    /// ray `origin + t * direction` hits with `t` in `[t_min, t_max]`.
+    pub fn intersect(
+        &self,
+        mesh: &Mesh,
+        origin: Vec3,
+        direction: Vec3,
+        t_min: f32,
+        t_max: f32,
+    ) -> Option<Hit> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let inv_direction = direction.recip();
+        let mut closest: Option<Hit> = None;
+        let mut t_max = t_max;
+
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            // `t_max` shrinks with every hit, so nodes pushed before a closer hit was found are
+            // culled here.
+            if node_bounds(node)
+                .intersect(origin, inv_direction, t_min, t_max)
+                .is_none()
+            {
+                continue;
+            }
+
+            if node.count > 0 {
+                let first = node.first as usize;
+                for &triangle in &self.primitive_indices[first..first + node.count as usize] {
+                    let [a, b, c] = [0, 1, 2].map(|i| {
+                        Vec3::from(
+                            mesh.vertices[mesh.indices[3 * triangle as usize + i] as usize].pos,
+                        )
+                    });
+                    if let Some((t, barycentrics)) =
+                        intersect_triangle(origin, direction, a, b, c, t_min, t_max)
+                    {
+                        t_max = t;
+                        closest = Some(Hit {
+                            t,
+                            triangle,
+                            barycentrics,
+                        });
+                    }
+                }
+            } else {
+                stack.push(node.first as usize);
+                stack.push(index + 1);
+            }
+        }
+
+        closest
+    }
+
+    pub fn stats(&self, options: &BvhOptions) -> BvhStats {
+        let mut stats = BvhStats {
+            node_count: self.nodes.len(),
+            leaf_count: 0,
+            max_depth: 0,
+            max_leaf_size: 0,
+            sah_cost: 0.0,
+        };
+
+        let Some(root) = self.nodes.first() else {
+            return stats;
+        };
+        let root_area = node_bounds(root).surface_area().max(f32::MIN_POSITIVE);
+
+        let mut stack = vec![(0, 1)];
+        while let Some((index, depth)) = stack.pop() {
+            let node = &self.nodes[index];
+            let relative_area = node_bounds(node).surface_area() / root_area;
+            stats.max_depth = stats.max_depth.max(depth);
+
+            if node.count > 0 {
+                stats.leaf_count += 1;
+                stats.max_leaf_size = stats.max_leaf_size.max(node.count as usize);
+                stats.sah_cost += relative_area * node.count as f32;
+            } else {
+                stats.sah_cost += relative_area * options.traversal_cost;
+                stack.push((index + 1, depth + 1));
+                stack.push((node.first as usize, depth + 1));
+            }
+        }
+
+        stats
+    }
+
+    /// Serialises the BVH as little-endian node and index arrays, each prefixed by its length.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(
+            8 + self.nodes.len() * std::mem::size_of::<BvhNode>()
+                + self.primitive_indices.len() * 4,
+        );
+
+        bytes.extend_from_slice(&(self.nodes.len() as u32).to_le_bytes());
+        for node in &self.nodes {
+            for value in node.min {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+            bytes.extend_from_slice(&node.first.to_le_bytes());
+            for value in node.max {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+            bytes.extend_from_slice(&node.count.to_le_bytes());
+        }
+
+        bytes.extend_from_slice(&(self.primitive_indices.len() as u32).to_le_bytes());
+        for index in &self.primitive_indices {
+            bytes.extend_from_slice(&index.to_le_bytes());
+        }
+
+        bytes
+    }
+}
+
+fn node_bounds(node: &BvhNode) -> Aabb {
+    Aabb {
+        min: Vec3::from(node.min),
+        max: Vec3::from(node.max),
+    }
+}
+
+/// Moves the elements matching `predicate` to the front and returns how many there are.
+fn partition<T>(slice: &mut [T], predicate: impl Fn(&T) -> bool) -> usize {
+    let mut first_false = 0;
+    for i in 0..slice.len() {
+        if predicate(&slice[i]) {
+            slice.swap(first_false, i);
+            first_false += 1;
+        }
+    }
+    first_false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{mesh::Vertex, primitives, random::Random};
+
+    fn random_vec3(random: &mut Random, min: f32, max: f32) -> Vec3 {
+        Vec3::new(
+            random.range(min, max),
+            random.range(min, max),
+            random.range(min, max),
+        )
+    }
+
+    /// `count` triangles of up to `size` across, scattered through the cube of half-width 1.
+    fn random_triangles(random: &mut Random, count: usize, size: f32) -> Mesh {
+        let mut mesh = Mesh::default();
+        for _ in 0..count {
+            let center = random_vec3(random, -1.0, 1.0);
+            for _ in 0..3 {
+                mesh.indices.push(mesh.vertices.len() as u32);
+                mesh.vertices.push(Vertex {
+                    pos: (center + random_vec3(random, -size, size)).to_array(),
+                    normal: [0.0; 3],
+                    uv: [0.0; 2],
+                });
+            }
+        }
+        mesh
+    }
+
+    fn brute_force(mesh: &Mesh, origin: Vec3, direction: Vec3, t_max: f32) -> Option<Hit> {
+        let mut closest: Option<Hit> = None;
+        for (triangle, indices) in mesh.indices.chunks_exact(3).enumerate() {
+            let [a, b, c] = [0, 1, 2].map(|i| Vec3::from(mesh.vertices[indices[i] as usize].pos));
+            let t_max = closest.map_or(t_max, |hit| hit.t);
+            if let Some((t, barycentrics)) =
+                intersect_triangle(origin, direction, a, b, c, 0.0, t_max)
+            {
+                closest = Some(Hit {
+                    t,
+                    triangle: triangle as u32,
+                    barycentrics,
+                });
+            }
+        }
+        closest
+    }
+
+    /// Shoots random rays, from outside and from inside the mesh, and checks the BVH finds the
+    /// same closest hits as testing every triangle. Returns how many rays hit.
+    fn compare_with_brute_force(mesh: &Mesh, options: &BvhOptions, seed: u32) -> usize {
+        let bvh = Bvh::build(mesh, options);
+        let mut random = Random::new(seed);
+        let mut hits = 0;
+
+        for i in 0..2000 {
+            let origin = if i % 2 == 0 {
+                random_vec3(&mut random, -3.0, 3.0)
+            } else {
+                random_vec3(&mut random, -0.5, 0.5)
+            };
+            // Aim roughly at the mesh so most rays have something to hit.
+            let target = random_vec3(&mut random, -1.0, 1.0);
+            let direction = (target - origin).normalize();
+            // Axis-parallel rays exercise the slab test's infinities.
+            let direction = if i % 50 == 0 { Vec3::X } else { direction };
+
+            let expected = brute_force(mesh, origin, direction, f32::INFINITY);
+            let actual = bvh.intersect(mesh, origin, direction, 0.0, f32::INFINITY);
+
+            match (expected, actual) {
+                (None, None) => {}
+                (Some(expected), Some(actual)) => {
+                    hits += 1;
+                    // Triangles hit at the same distance may be reported in either order.
+                    assert!(
+                        (expected.t - actual.t).abs() <= 1e-5 * expected.t.max(1.0),
+                        "ray {}: {:?} vs {:?}",
+                        i,
+                        expected,
+                        actual
+                    );
+                    if expected.triangle != actual.triangle {
+                        assert_eq!(expected.t, actual.t, "ray {}", i);
+                    }
+                }
+                _ => panic!("ray {}: {:?} vs {:?}", i, expected, actual),
+            }
+        }
+
+        hits
+    }
+
+    #[test]
+    fn matches_brute_force_on_random_triangles() {
+        let mut random = Random::new(1);
+        for (count, size) in [(1, 0.5), (10, 0.5), (200, 0.2), (1000, 0.05)] {
+            let mesh = random_triangles(&mut random, count, size);
+            for options in [
+                BvhOptions::default(),
+                BvhOptions {
+                    max_leaf_size: 1,
+                    bin_count: 2,
+                    traversal_cost: 1.0,
+                },
+                BvhOptions {
+                    max_leaf_size: 16,
+                    bin_count: 32,
+                    traversal_cost: 0.1,
+                },
+            ] {
+                let hits = compare_with_brute_force(&mesh, &options, count as u32);
+                assert!(hits > 0, "no ray hit {} triangles", count);
+            }
+        }
+    }
+
+    #[test]
+    fn matches_brute_force_on_closed_meshes() {
+        for (i, mesh) in [
+            primitives::icosphere(0.9, 3),
+            primitives::torus(0.6, 0.3, 32, 16),
+            primitives::cuboid(Vec3::splat(0.7)),
+        ]
+        .iter()
+        .enumerate()
+        {
+            let hits = compare_with_brute_force(mesh, &BvhOptions::default(), i as u32 + 10);
+            assert!(hits > 0);
+        }
+    }
+
+    #[test]
+    fn handles_coincident_triangles() {
+        // No plane separates identical centroids, so this takes the even-split fallback.
+        let mut random = Random::new(3);
+        let triangle = random_triangles(&mut random, 1, 0.5);
+        let mesh = Mesh {
+            vertices: triangle.vertices.repeat(20),
+            indices: (0..60).collect(),
+        };
+        compare_with_brute_force(&mesh, &BvhOptions::default(), 4);
+    }
+
+    #[test]
+    fn empty_mesh_has_no_hits() {
+        let bvh = Bvh::build(&Mesh::default(), &BvhOptions::default());
+        assert!(bvh
+            .intersect(&Mesh::default(), Vec3::ZERO, Vec3::Z, 0.0, f32::INFINITY)
+            .is_none());
+    }
+
+    #[test]
+    fn respects_ray_interval() {
+        let mesh = primitives::cuboid(Vec3::splat(0.5));
+        let bvh = Bvh::build(&mesh, &BvhOptions::default());
+        let origin = Vec3::new(0.1, 0.2, -2.0);
+
+        let hit = bvh
+            .intersect(&mesh, origin, Vec3::Z, 0.0, f32::INFINITY)
+            .unwrap();
+        assert!((hit.t - 1.5).abs() < 1e-5, "{:?}", hit);
+
+        // Starting past the front face finds the back face.
+        let hit = bvh
+            .intersect(&mesh, origin, Vec3::Z, 1.6, f32::INFINITY)
+            .unwrap();
+        assert!((hit.t - 2.5).abs() < 1e-5, "{:?}", hit);
+
+        assert!(bvh.intersect(&mesh, origin, Vec3::Z, 0.0, 1.4).is_none());
+    }
+}
@@ -0,0 +1,146 @@
+//! Saves the accumulation buffer of an interrupted render, so `--resume` can add passes to it
+//! instead of starting over.
+//!
+//! The file is a small header of little-endian `u32`s followed by the buffer as little-endian
+//! `f32`s, row-major from the top left.
+
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+const MAGIC: [u8; 4] = *b"ARTC";
+const VERSION: u32 = 1;
+/// Magic, version, width, height, passes and samples.
+const HEADER_SIZE: usize = 24;
+
+/// Everything a render needs to carry on where an earlier one stopped.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Checkpoint {
+    pub width: u32,
+    pub height: u32,
+    /// Accumulation passes done, so a resumed render starts with the next frame index and
+    /// draws new random numbers.
+    pub passes: u32,
+    /// Samples per pixel accumulated.
+    pub samples: u32,
+    /// Weighted sum of each pixel's samples, with the sum of the weights in the last component.
+    pub accumulation: Vec<[f32; 4]>,
+}
+
+impl Checkpoint {
+    pub fn write(&self, path: &Path) {
+        assert_eq!(
+            self.accumulation.len(),
+            self.width as usize * self.height as usize
+        );
+
+        let mut file = BufWriter::new(File::create(path).unwrap());
+        file.write_all(&MAGIC).unwrap();
+        for value in [VERSION, self.width, self.height, self.passes, self.samples] {
+            file.write_all(&value.to_le_bytes()).unwrap();
+        }
+        for value in self.accumulation.iter().flatten() {
+            file.write_all(&value.to_le_bytes()).unwrap();
+        }
+        file.flush().unwrap();
+    }
+
+    /// Reads a checkpoint that `write` saved, or says what is wrong with the file.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let bytes = fs::read(path).map_err(|err| format!("can't read {:?}: {}", path, err))?;
+        if bytes.len() < HEADER_SIZE || bytes[..4] != MAGIC {
+            return Err(format!("{:?} isn't a checkpoint", path));
+        }
+
+        let header = bytes[4..HEADER_SIZE]
+            .chunks_exact(4)
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+            .collect::<Vec<_>>();
+        let [version, width, height, passes, samples] = header[..] else {
+            unreachable!()
+        };
+        if version != VERSION {
+            return Err(format!(
+                "{:?} is a version {} checkpoint, expected version {}",
+                path, version, VERSION
+            ));
+        }
+
+        let pixel_count = width as usize * height as usize;
+        if bytes.len() != HEADER_SIZE + pixel_count * 16 {
+            return Err(format!(
+                "{:?} is cut short or too long for {}x{} pixels",
+                path, width, height
+            ));
+        }
+
+        let accumulation = bytes[HEADER_SIZE..]
+            .chunks_exact(16)
+            .map(|pixel| {
+                [0, 1, 2, 3].map(|i| f32::from_le_bytes(pixel[4 * i..][..4].try_into().unwrap()))
+            })
+            .collect();
+
+        Ok(Self {
+            width,
+            height,
+            passes,
+            samples,
+            accumulation,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "ash-raytracing-example-{}-{}.checkpoint",
+            name,
+            std::process::id()
+        ))
+    }
+
+    #[test]
+    fn round_trips() {
+        let checkpoint = Checkpoint {
+            width: 2,
+            height: 1,
+            passes: 3,
+            samples: 12,
+            accumulation: vec![[1.0, 2.0, 3.0, 0.5], [-4.0, 5.5, 6.0, 12.0]],
+        };
+        let path = temp_path("round-trip");
+        checkpoint.write(&path);
+        let loaded = Checkpoint::load(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded, Ok(checkpoint));
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let path = temp_path("other");
+
+        fs::write(&path, b"\x89PNG\r\n\x1a\n and more").unwrap();
+        assert!(Checkpoint::load(&path)
+            .unwrap_err()
+            .contains("isn't a checkpoint"));
+
+        // A 1x1 header with the pixel missing.
+        let mut bytes = MAGIC.to_vec();
+        for value in [VERSION, 1, 1, 1, 1] {
+            bytes.extend(value.to_le_bytes());
+        }
+        fs::write(&path, &bytes).unwrap();
+        assert!(Checkpoint::load(&path).unwrap_err().contains("cut short"));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,128 @@
+//! Hair and fur as chains of tapered segments. The BLAS only holds one box per segment; the
+//! curve intersection shader finds the actual surface by treating each segment as a round cone.
+
+use std::f32::consts::PI;
+
+use ash::vk;
+use glam::{vec3, Vec3};
+
+/// Thinnest a strand gets at its tip, relative to its root.
+const TIP_RADIUS_SCALE: f32 = 0.1;
+
+/// One tapered piece of a strand, mirrored by `CurveSegment` in the shader crate.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct CurveSegment {
+    pub start: [f32; 3],
+    pub start_radius: f32,
+    pub end: [f32; 3],
+    pub end_radius: f32,
+}
+
+impl CurveSegment {
+    fn bounds(&self) -> (Vec3, Vec3) {
+        let (start, end) = (Vec3::from(self.start), Vec3::from(self.end));
+        let (start_radius, end_radius) =
+            (Vec3::splat(self.start_radius), Vec3::splat(self.end_radius));
+        (
+            (start - start_radius).min(end - end_radius),
+            (start + start_radius).max(end + end_radius),
+        )
+    }
+}
+
+/// Strands stored as independent segments. Consecutive segments of a strand share an end point
+/// and radius, so the round cones join without gaps.
+#[derive(Clone, Debug, Default)]
+pub struct Curves {
+    pub segments: Vec<CurveSegment>,
+}
+
+impl Curves {
+    pub fn segment_count(&self) -> usize {
+        self.segments.len()
+    }
+
+    /// Axis-aligned bounds of every segment, including its thickness.
+    pub fn bounds(&self) -> (Vec3, Vec3) {
+        self.segments.iter().fold(
+            (Vec3::splat(f32::INFINITY), Vec3::splat(f32::NEG_INFINITY)),
+            |(min, max), segment| {
+                let (segment_min, segment_max) = segment.bounds();
+                (min.min(segment_min), max.max(segment_max))
+            },
+        )
+    }
+
+    /// One box per segment, in the layout BLAS builds read for AABB geometry.
+    pub fn aabbs(&self) -> Vec<vk::AabbPositionsKHR> {
+        self.segments
+            .iter()
+            .map(|segment| {
+                let (min, max) = segment.bounds();
+                vk::AabbPositionsKHR {
+                    min_x: min.x,
+                    min_y: min.y,
+                    min_z: min.z,
+                    max_x: max.x,
+                    max_y: max.y,
+                    max_z: max.z,
+                }
+            })
+            .collect()
+    }
+
+    /// Bytes the segment and box buffers of this curve set take up on the device.
+    pub fn geometry_size(&self) -> usize {
+        self.segments.len()
+            * (std::mem::size_of::<CurveSegment>() + std::mem::size_of::<vk::AabbPositionsKHR>())
+    }
+}
+
+/// Fur on a sphere of `radius` centred on the origin. `strand_count` strands are spread evenly
+/// over the surface, each `length` long and split into `segments_per_strand` segments. Strands
+/// start out along the surface normal, droop under gravity by `droop` per segment and taper
+/// from `root_radius` at the surface.
+pub fn fur_ball(
+    radius: f32,
+    strand_count: u32,
+    length: f32,
+    segments_per_strand: u32,
+    root_radius: f32,
+    droop: f32,
+) -> Curves {
+    let golden_angle = PI * (3.0 - 5.0f32.sqrt());
+    let segment_length = length / segments_per_strand as f32;
+    let mut segments = Vec::with_capacity((strand_count * segments_per_strand) as usize);
+
+    for i in 0..strand_count {
+        // Fibonacci lattice: evenly spaced heights, each turned by the golden angle.
+        let y = 1.0 - 2.0 * (i as f32 + 0.5) / strand_count as f32;
+        let ring_radius = (1.0 - y * y).sqrt();
+        let (sin, cos) = (i as f32 * golden_angle).sin_cos();
+        let normal = vec3(cos * ring_radius, y, sin * ring_radius);
+
+        let mut position = normal * radius;
+        let mut direction = normal;
+        let mut strand_radius = root_radius;
+
+        for j in 0..segments_per_strand {
+            let next_position = position + direction * segment_length;
+            let next_radius = root_radius
+                * (1.0 - (1.0 - TIP_RADIUS_SCALE) * (j + 1) as f32 / segments_per_strand as f32);
+
+            segments.push(CurveSegment {
+                start: position.to_array(),
+                start_radius: strand_radius,
+                end: next_position.to_array(),
+                end_radius: next_radius,
+            });
+
+            position = next_position;
+            strand_radius = next_radius;
+            direction = (direction - Vec3::Y * droop).normalize();
+        }
+    }
+
+    Curves { segments }
+}
@@ -0,0 +1,77 @@
+//! CPU-side displacement: subdivides a mesh and pushes its vertices along their normals by a
+//! height map before the BLAS is built, so detailed surfaces don't need dense source meshes.
+
+use std::{fs::File, path::Path};
+
+use glam::{Vec2, Vec3};
+
+use crate::mesh::Mesh;
+
+/// Single-channel height map with values in `[0, 1]`.
+pub struct HeightMap {
+    width: u32,
+    height: u32,
+    values: Vec<f32>,
+}
+
+impl HeightMap {
+    /// Loads the first channel of a PNG. Returns `None` if the file does not exist.
+    pub fn load(path: &Path) -> Option<Self> {
+        if !path.exists() {
+            return None;
+        }
+
+        let mut decoder =
+            png::Decoder::new(File::open(path).expect("failed to open displacement map"));
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+        let mut reader = decoder.read_info().unwrap();
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf).unwrap();
+
+        let channels = info.color_type.samples();
+        let values = buf[..info.buffer_size()]
+            .chunks_exact(channels)
+            .map(|pixel| pixel[0] as f32 / 255.0)
+            .collect();
+
+        Some(Self {
+            width: info.width,
+            height: info.height,
+            values,
+        })
+    }
+
+    /// Bilinearly samples the map at `uv`, clamping to the edges.
+    pub fn sample(&self, uv: Vec2) -> f32 {
+        let x = (uv.x * self.width as f32 - 0.5).clamp(0.0, (self.width - 1) as f32);
+        let y = (uv.y * self.height as f32 - 0.5).clamp(0.0, (self.height - 1) as f32);
+
+        let (x0, y0) = (x as u32, y as u32);
+        let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+        let (fx, fy) = (x.fract(), y.fract());
+
+        let value = |x: u32, y: u32| self.values[(y * self.width + x) as usize];
+        let top = value(x0, y0) * (1.0 - fx) + value(x1, y0) * fx;
+        let bottom = value(x0, y1) * (1.0 - fx) + value(x1, y1) * fx;
+
+        top * (1.0 - fy) + bottom * fy
+    }
+}
+
+/// Subdivides `mesh` `subdivisions` times, then offsets each vertex along its normal by
+/// `scale` times the height map value at its texture coordinate and regenerates normals.
+pub fn displace(mesh: &Mesh, height_map: &HeightMap, subdivisions: u32, scale: f32) -> Mesh {
+    let mut mesh = mesh.clone();
+    for _ in 0..subdivisions {
+        mesh = mesh.subdivide();
+    }
+
+    for vertex in &mut mesh.vertices {
+        let height = height_map.sample(Vec2::from(vertex.uv));
+        let pos = Vec3::from(vertex.pos) + Vec3::from(vertex.normal) * height * scale;
+        vertex.pos = pos.to_array();
+    }
+
+    mesh.generate_normals();
+    mesh
+}
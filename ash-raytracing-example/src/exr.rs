@@ -0,0 +1,248 @@
+//! Writes uncompressed 32-bit float OpenEXR files, so accumulated renders can be saved without
+//! quantizing them to 8 bits.
+//!
+//! Only what a single-part RGBA scanline image needs is implemented; see "The OpenEXR File
+//! Layout" in the OpenEXR documentation.
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+};
+
+const MAGIC: u32 = 20000630;
+/// File format version 2, single-part scanline image.
+const VERSION: u32 = 2;
+const PIXEL_TYPE_FLOAT: i32 = 2;
+const NO_COMPRESSION: u8 = 0;
+const INCREASING_Y: u8 = 0;
+/// Channels have to be listed in alphabetical order, and scanlines store them in that order.
+const CHANNELS: [(&str, usize); 4] = [("A", 3), ("B", 2), ("G", 1), ("R", 0)];
+
+/// Writes `pixels`, row-major RGBA from the top left, to `path`.
+pub fn write_rgba32f(path: &str, width: u32, height: u32, pixels: &[f32]) {
+    assert_eq!(pixels.len(), 4 * width as usize * height as usize);
+
+    let mut header = Vec::new();
+    header.extend(MAGIC.to_le_bytes());
+    header.extend(VERSION.to_le_bytes());
+
+    let mut channels = Vec::new();
+    for (name, _) in CHANNELS {
+        channels.extend(name.as_bytes());
+        channels.push(0);
+        channels.extend(PIXEL_TYPE_FLOAT.to_le_bytes());
+        // pLinear and three reserved bytes.
+        channels.extend([0; 4]);
+        // x and y sampling.
+        channels.extend(1i32.to_le_bytes());
+        channels.extend(1i32.to_le_bytes());
+    }
+    channels.push(0);
+
+    let window = [0, 0, width as i32 - 1, height as i32 - 1]
+        .into_iter()
+        .flat_map(i32::to_le_bytes)
+        .collect::<Vec<_>>();
+
+    attribute(&mut header, "channels", "chlist", &channels);
+    attribute(&mut header, "compression", "compression", &[NO_COMPRESSION]);
+    attribute(&mut header, "dataWindow", "box2i", &window);
+    attribute(&mut header, "displayWindow", "box2i", &window);
+    attribute(&mut header, "lineOrder", "lineOrder", &[INCREASING_Y]);
+    attribute(
+        &mut header,
+        "pixelAspectRatio",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+    attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    attribute(
+        &mut header,
+        "screenWindowWidth",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+    header.push(0);
+
+    // Uncompressed files hold one scanline per chunk: its y, its size, then each channel's
+    // values for the whole row.
+    let row_size = CHANNELS.len() * 4 * width as usize;
+    let chunk_size = 8 + row_size;
+    let first_chunk = header.len() + 8 * height as usize;
+
+    let mut file = BufWriter::new(File::create(path).unwrap());
+    file.write_all(&header).unwrap();
+    for y in 0..height as usize {
+        file.write_all(&((first_chunk + y * chunk_size) as u64).to_le_bytes())
+            .unwrap();
+    }
+
+    for (y, row) in pixels.chunks_exact(4 * width as usize).enumerate() {
+        file.write_all(&(y as i32).to_le_bytes()).unwrap();
+        file.write_all(&(row_size as i32).to_le_bytes()).unwrap();
+        for (_, component) in CHANNELS {
+            for pixel in row.chunks_exact(4) {
+                file.write_all(&pixel[component].to_le_bytes()).unwrap();
+            }
+        }
+    }
+
+    file.flush().unwrap();
+}
+
+fn attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend(name.as_bytes());
+    header.push(0);
+    header.extend(kind.as_bytes());
+    header.push(0);
+    header.extend((value.len() as i32).to_le_bytes());
+    header.extend(value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes `pixels` to a file of its own in the temp directory and reads the bytes back.
+    fn write_and_read(name: &str, width: u32, height: u32, pixels: &[f32]) -> Vec<u8> {
+        let path = std::env::temp_dir().join(format!(
+            "ash-raytracing-example-{}-{}.exr",
+            name,
+            std::process::id()
+        ));
+        write_rgba32f(path.to_str().unwrap(), width, height, pixels);
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        bytes
+    }
+
+    /// Reads the values of a file one after another.
+    struct Reader<'a> {
+        bytes: &'a [u8],
+        offset: usize,
+    }
+
+    impl Reader<'_> {
+        fn take(&mut self, count: usize) -> &[u8] {
+            let bytes = &self.bytes[self.offset..self.offset + count];
+            self.offset += count;
+            bytes
+        }
+
+        fn u32(&mut self) -> u32 {
+            u32::from_le_bytes(self.take(4).try_into().unwrap())
+        }
+
+        fn i32(&mut self) -> i32 {
+            i32::from_le_bytes(self.take(4).try_into().unwrap())
+        }
+
+        fn u64(&mut self) -> u64 {
+            u64::from_le_bytes(self.take(8).try_into().unwrap())
+        }
+
+        fn f32(&mut self) -> f32 {
+            f32::from_le_bytes(self.take(4).try_into().unwrap())
+        }
+
+        fn string(&mut self) -> String {
+            let length = self.bytes[self.offset..]
+                .iter()
+                .position(|&byte| byte == 0)
+                .unwrap();
+            let string = String::from_utf8(self.take(length).to_vec()).unwrap();
+            self.offset += 1;
+            string
+        }
+    }
+
+    #[test]
+    fn writes_a_scanline_image() {
+        // Two RGBA pixels.
+        let bytes = write_and_read("2x1", 2, 1, &[1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0]);
+        let mut reader = Reader {
+            bytes: &bytes,
+            offset: 0,
+        };
+
+        assert_eq!(reader.u32(), MAGIC);
+        assert_eq!(reader.u32(), VERSION);
+
+        let mut attributes = Vec::new();
+        loop {
+            let name = reader.string();
+            if name.is_empty() {
+                break;
+            }
+            let kind = reader.string();
+            let size = reader.i32() as usize;
+            attributes.push((name, kind, reader.take(size).to_vec()));
+        }
+        let names = attributes
+            .iter()
+            .map(|(name, kind, _)| (name.as_str(), kind.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                ("channels", "chlist"),
+                ("compression", "compression"),
+                ("dataWindow", "box2i"),
+                ("displayWindow", "box2i"),
+                ("lineOrder", "lineOrder"),
+                ("pixelAspectRatio", "float"),
+                ("screenWindowCenter", "v2f"),
+                ("screenWindowWidth", "float"),
+            ]
+        );
+
+        // Each channel is its name, then 16 bytes: pixel type, pLinear and reserved, sampling.
+        let channels = &attributes[0].2;
+        assert_eq!(channels.len(), 4 * (2 + 16) + 1);
+        for (i, name) in ["A", "B", "G", "R"].iter().enumerate() {
+            let channel = &channels[i * 18..(i + 1) * 18];
+            assert_eq!(&channel[..2], [name.as_bytes()[0], 0]);
+            assert_eq!(channel[2..6], PIXEL_TYPE_FLOAT.to_le_bytes());
+        }
+        assert_eq!(attributes[1].2, [NO_COMPRESSION]);
+        let window = [0i32, 0, 1, 0]
+            .into_iter()
+            .flat_map(i32::to_le_bytes)
+            .collect::<Vec<_>>();
+        assert_eq!(attributes[2].2, window);
+        assert_eq!(attributes[3].2, window);
+
+        // One scanline: the offset table points right past itself.
+        let first_chunk = reader.offset + 8;
+        assert_eq!(reader.u64(), first_chunk as u64);
+
+        assert_eq!(reader.i32(), 0);
+        assert_eq!(reader.i32(), 4 * 4 * 2);
+        let values = (0..8).map(|_| reader.f32()).collect::<Vec<_>>();
+        assert_eq!(values, [4.0, 8.0, 3.0, 7.0, 2.0, 6.0, 1.0, 5.0]);
+        assert_eq!(reader.offset, bytes.len());
+    }
+
+    #[test]
+    fn offsets_point_at_each_scanline() {
+        let (width, height) = (2, 3);
+        let pixels = (0..4 * width * height)
+            .map(|i| i as f32)
+            .collect::<Vec<_>>();
+        let bytes = write_and_read("2x3", width, height, &pixels);
+
+        let chunk_size = 8 + 4 * 4 * width as usize;
+        let first_chunk = bytes.len() - height as usize * chunk_size;
+        let table = first_chunk - 8 * height as usize;
+        for y in 0..height as usize {
+            let offset = u64::from_le_bytes(bytes[table + 8 * y..][..8].try_into().unwrap());
+            assert_eq!(offset as usize, first_chunk + y * chunk_size);
+
+            let chunk = &bytes[first_chunk + y * chunk_size..];
+            assert_eq!(chunk[..4], (y as i32).to_le_bytes());
+            // The first value of the row is the alpha of its first pixel.
+            let alpha = f32::from_le_bytes(chunk[8..12].try_into().unwrap());
+            assert_eq!(alpha, pixels[4 * width as usize * y + 3]);
+        }
+    }
+}
@@ -0,0 +1,379 @@
+//! Imports triangle meshes, node transforms, base colours and the first camera from glTF files,
+//! so real content can be rendered instead of the built-in scene.
+
+use std::{fs, path::Path};
+
+use ash::vk;
+use base64::Engine;
+use glam::{vec3, Affine3A, Mat4, Vec3};
+
+use crate::{
+    mesh::{Mesh, Vertex},
+    scenes::Scene,
+    transform_bounds, Camera, Shape, VISIBLE_TO_ALL,
+};
+
+/// Vertical field of view of the camera placed for files that don't have one.
+const FRAMING_VFOV_DEGREES: f32 = 90.0;
+
+/// One placement of a glTF mesh.
+#[derive(Clone, Copy, Debug)]
+pub struct GltfInstance {
+    /// Index into `GltfScene::meshes`.
+    pub mesh: usize,
+    pub transform: Affine3A,
+    /// Base colour factor of the material of the mesh's first primitive.
+    pub color: [f32; 3],
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct GltfScene {
+    /// One mesh per glTF mesh, with all of its triangle primitives merged.
+    pub meshes: Vec<Mesh>,
+    pub instances: Vec<GltfInstance>,
+    /// The first perspective camera in the node hierarchy, if any.
+    pub(crate) camera: Option<Camera>,
+}
+
+impl GltfScene {
+    /// Loads the default scene of a `.gltf` or `.glb` file. Buffers may be in the GLB binary
+    /// chunk, base64 data URIs or files next to `path`. Textures are ignored.
+    ///
+    /// Panics if the scene has no node with triangles to render.
+    pub fn load(path: &Path) -> Self {
+        let gltf = gltf::Gltf::open(path).expect("failed to open glTF file");
+        let buffers = gltf
+            .buffers()
+            .map(|buffer| load_buffer(path, &buffer, gltf.blob.as_deref()))
+            .collect::<Vec<_>>();
+
+        let mut scene = Self {
+            meshes: gltf
+                .meshes()
+                .map(|mesh| load_mesh(&mesh, &buffers))
+                .collect(),
+            ..Default::default()
+        };
+
+        let colors = gltf
+            .meshes()
+            .map(|mesh| {
+                mesh.primitives().next().map_or([1.0; 3], |primitive| {
+                    let color = primitive
+                        .material()
+                        .pbr_metallic_roughness()
+                        .base_color_factor();
+                    [color[0], color[1], color[2]]
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let root = gltf
+            .default_scene()
+            .or_else(|| gltf.scenes().next())
+            .expect("glTF file has no scenes");
+
+        // glTF is right-handed, while the camera here puts +x on the right when looking down +z.
+        // Mirroring z keeps the image the right way round.
+        let handedness = Affine3A::from_scale(vec3(1.0, 1.0, -1.0));
+        for node in root.nodes() {
+            scene.add_node(&node, handedness, &colors);
+        }
+
+        assert!(
+            !scene.instances.is_empty(),
+            "{} has no meshes with triangles in its scene",
+            path.display()
+        );
+
+        scene
+    }
+
+    /// Turns the file's contents into a scene to render, building every mesh's BLAS with
+    /// `blas_flags`. Files without a camera get one that looks at the whole scene. Meshes without
+    /// triangles are never instanced, so they are left out rather than given an empty BLAS.
+    pub fn into_scene(self, blas_flags: vk::BuildAccelerationStructureFlagsKHR) -> Scene {
+        let camera = self.camera.unwrap_or_else(|| {
+            let (min, max) = self.bounds();
+            Camera::framing(min, max, FRAMING_VFOV_DEGREES)
+        });
+
+        // New index of each kept mesh. The entries of dropped meshes are never looked up.
+        let mut remap = Vec::with_capacity(self.meshes.len());
+        let mut meshes = Vec::with_capacity(self.meshes.len());
+        for mesh in self.meshes {
+            remap.push(meshes.len() as u32);
+            if mesh.triangle_count() > 0 {
+                meshes.push((mesh, blas_flags));
+            }
+        }
+
+        Scene {
+            instances: self
+                .instances
+                .iter()
+                .map(|instance| {
+                    (
+                        Shape::Mesh(remap[instance.mesh]),
+                        instance.transform,
+                        instance.color,
+                        VISIBLE_TO_ALL,
+                    )
+                })
+                .collect(),
+            meshes,
+            curve_sets: Vec::new(),
+            point_clouds: Vec::new(),
+            camera,
+        }
+    }
+
+    /// Axis-aligned bounds of every instance in world space.
+    pub fn bounds(&self) -> (Vec3, Vec3) {
+        self.instances.iter().fold(
+            (Vec3::splat(f32::INFINITY), Vec3::splat(f32::NEG_INFINITY)),
+            |(min, max), instance| {
+                let (mesh_min, mesh_max) = self.meshes[instance.mesh].bounds();
+                let (instance_min, instance_max) =
+                    transform_bounds(instance.transform, mesh_min, mesh_max);
+                (min.min(instance_min), max.max(instance_max))
+            },
+        )
+    }
+
+    fn add_node(&mut self, node: &gltf::Node, parent: Affine3A, colors: &[[f32; 3]]) {
+        let transform =
+            parent * Affine3A::from_mat4(Mat4::from_cols_array_2d(&node.transform().matrix()));
+
+        // Meshes without triangle primitives have nothing to trace and no bounds.
+        if let Some(mesh) = node
+            .mesh()
+            .filter(|mesh| self.meshes[mesh.index()].triangle_count() > 0)
+        {
+            self.instances.push(GltfInstance {
+                mesh: mesh.index(),
+                transform,
+                color: colors[mesh.index()],
+            });
+        }
+
+        if let (None, Some(camera)) = (self.camera, node.camera()) {
+            if let gltf::camera::Projection::Perspective(perspective) = camera.projection() {
+                // glTF cameras look down their local -z with +y up.
+                self.camera = Some(Camera {
+                    origin: transform.translation.into(),
+                    look_at: transform.transform_point3(-Vec3::Z),
+                    up: transform.transform_vector3(Vec3::Y),
+                    vfov_degrees: perspective.yfov().to_degrees(),
+                });
+            }
+        }
+
+        for child in node.children() {
+            self.add_node(&child, transform, colors);
+        }
+    }
+}
+
+fn load_buffer(path: &Path, buffer: &gltf::Buffer, blob: Option<&[u8]>) -> Vec<u8> {
+    match buffer.source() {
+        gltf::buffer::Source::Bin => blob.expect("GLB file has no binary chunk").to_vec(),
+        gltf::buffer::Source::Uri(uri) => match uri.strip_prefix("data:") {
+            Some(data) => {
+                let (_, encoded) = data
+                    .split_once(";base64,")
+                    .expect("only base64 data URIs are supported");
+                base64::engine::general_purpose::STANDARD
+                    .decode(encoded)
+                    .expect("invalid base64 in glTF data URI")
+            }
+            None => fs::read(path.parent().unwrap_or(Path::new("")).join(uri))
+                .expect("failed to read glTF buffer"),
+        },
+    }
+}
+
+/// Merges the triangle primitives of `mesh` into one mesh. Other primitive modes are skipped.
+/// Missing normals are left zero, so `Mesh::optimize` generates them.
+fn load_mesh(mesh: &gltf::Mesh, buffers: &[Vec<u8>]) -> Mesh {
+    let mut result = Mesh::default();
+
+    for primitive in mesh.primitives() {
+        if primitive.mode() != gltf::mesh::Mode::Triangles {
+            eprintln!(
+                "warning: skipping {:?} primitive of glTF mesh {:?}",
+                primitive.mode(),
+                mesh.name().unwrap_or_default()
+            );
+            continue;
+        }
+
+        let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+        let positions = reader
+            .read_positions()
+            .expect("glTF primitive without positions")
+            .collect::<Vec<_>>();
+        let normals = reader
+            .read_normals()
+            .map_or_else(|| vec![[0.0; 3]; positions.len()], Iterator::collect);
+        let uvs = reader.read_tex_coords(0).map_or_else(
+            || vec![[0.0; 2]; positions.len()],
+            |uvs| uvs.into_f32().collect(),
+        );
+
+        let first_vertex = result.vertices.len() as u32;
+        match reader.read_indices() {
+            Some(indices) => result
+                .indices
+                .extend(indices.into_u32().map(|index| first_vertex + index)),
+            None => result
+                .indices
+                .extend(first_vertex..first_vertex + positions.len() as u32),
+        }
+
+        result.vertices.extend(
+            positions
+                .into_iter()
+                .zip(normals)
+                .zip(uvs)
+                .map(|((pos, normal), uv)| Vertex { pos, normal, uv }),
+        );
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    /// Writes `json` to a `.gltf` file of its own in the temp directory.
+    fn write_gltf(name: &str, json: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "ash-raytracing-example-{}-{}.gltf",
+            name,
+            std::process::id()
+        ));
+        fs::write(&path, json).unwrap();
+        path
+    }
+
+    /// A file with one node per entry of `meshes_json`, whose primitives read positions from
+    /// accessor 0: one triangle with a corner at z = 1.
+    fn triangle_gltf(meshes_json: &[&str]) -> String {
+        let positions = [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 1.0]
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect::<Vec<_>>();
+        let node_indices = (0..meshes_json.len())
+            .map(|i| i.to_string())
+            .collect::<Vec<_>>();
+        let nodes = (0..meshes_json.len())
+            .map(|i| format!(r#"{{ "mesh": {} }}"#, i))
+            .collect::<Vec<_>>();
+        format!(
+            r#"{{
+                "asset": {{ "version": "2.0" }},
+                "scene": 0,
+                "scenes": [{{ "nodes": [{}] }}],
+                "nodes": [{}],
+                "meshes": [{}],
+                "buffers": [{{
+                    "byteLength": 36,
+                    "uri": "data:application/octet-stream;base64,{}"
+                }}],
+                "bufferViews": [{{ "buffer": 0, "byteLength": 36 }}],
+                "accessors": [{{
+                    "bufferView": 0,
+                    "componentType": 5126,
+                    "count": 3,
+                    "type": "VEC3",
+                    "min": [0, 0, 0],
+                    "max": [1, 1, 1]
+                }}]
+            }}"#,
+            node_indices.join(", "),
+            nodes.join(", "),
+            meshes_json.join(", "),
+            base64::engine::general_purpose::STANDARD.encode(positions)
+        )
+    }
+
+    #[test]
+    fn loads_a_triangle_mirrored_along_z() {
+        let path = write_gltf(
+            "triangle",
+            &triangle_gltf(&[r#"{ "primitives": [{ "attributes": { "POSITION": 0 } }] }"#]),
+        );
+        let scene = GltfScene::load(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(scene.meshes.len(), 1);
+        assert_eq!(scene.meshes[0].triangle_count(), 1);
+        assert_eq!(scene.instances.len(), 1);
+        assert_eq!(scene.bounds(), (vec3(0.0, 0.0, -1.0), vec3(1.0, 1.0, 0.0)));
+
+        // Without a camera in the file, the scene is framed from in front.
+        let scene = scene.into_scene(vk::BuildAccelerationStructureFlagsKHR::empty());
+        assert!(scene.camera.origin.is_finite());
+        assert!(scene.camera.origin.z < -1.0);
+        assert!(matches!(scene.instances[..], [(Shape::Mesh(0), ..)]));
+    }
+
+    #[test]
+    #[should_panic(expected = "has no meshes with triangles")]
+    fn rejects_a_scene_without_meshes() {
+        let path = write_gltf(
+            "empty",
+            r#"{
+                "asset": { "version": "2.0" },
+                "scene": 0,
+                "scenes": [{ "nodes": [0] }],
+                "nodes": [{ "name": "empty" }]
+            }"#,
+        );
+        let result = std::panic::catch_unwind(|| GltfScene::load(&path));
+        fs::remove_file(&path).unwrap();
+        std::panic::resume_unwind(result.unwrap_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "has no meshes with triangles")]
+    fn rejects_a_scene_whose_meshes_have_no_triangles() {
+        // Mode 0 is points, which is skipped.
+        let path = write_gltf(
+            "points",
+            &triangle_gltf(&[
+                r#"{ "primitives": [{ "attributes": { "POSITION": 0 }, "mode": 0 }] }"#,
+            ]),
+        );
+        let result = std::panic::catch_unwind(|| GltfScene::load(&path));
+        fs::remove_file(&path).unwrap();
+        std::panic::resume_unwind(result.unwrap_err());
+    }
+
+    #[test]
+    fn leaves_out_meshes_without_triangles() {
+        // Mode 0 is points, so only the second mesh has triangles.
+        let path = write_gltf(
+            "mixed",
+            &triangle_gltf(&[
+                r#"{ "primitives": [{ "attributes": { "POSITION": 0 }, "mode": 0 }] }"#,
+                r#"{ "primitives": [{ "attributes": { "POSITION": 0 } }] }"#,
+            ]),
+        );
+        let scene = GltfScene::load(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(scene.meshes.len(), 2);
+        assert_eq!(scene.instances.len(), 1);
+        assert_eq!(scene.instances[0].mesh, 1);
+
+        let scene = scene.into_scene(vk::BuildAccelerationStructureFlagsKHR::empty());
+        assert_eq!(scene.meshes.len(), 1);
+        assert_eq!(scene.meshes[0].0.triangle_count(), 1);
+        assert!(matches!(scene.instances[..], [(Shape::Mesh(0), ..)]));
+    }
+}
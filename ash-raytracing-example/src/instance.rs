@@ -0,0 +1,201 @@
+//! Builds `vk::AccelerationStructureInstanceKHR`s, whose bit-packed fields and row-major
+//! transform are easy to get wrong by hand.
+
+use ash::vk::{self, Packed24_8};
+use glam::{Affine3A, Mat4};
+
+/// Largest value that fits the 24-bit custom index and record offset fields.
+const MAX_24_BIT: u32 = (1 << 24) - 1;
+
+/// One TLAS instance of the BLAS at `device_handle`. Unset fields default to an identity
+/// transform, custom index and record offset 0, a mask that every ray matches and no flags.
+#[derive(Clone, Copy, Debug)]
+pub struct InstanceBuilder {
+    device_handle: vk::DeviceAddress,
+    transform: Affine3A,
+    custom_index: u32,
+    mask: u8,
+    sbt_record_offset: u32,
+    flags: vk::GeometryInstanceFlagsKHR,
+}
+
+impl InstanceBuilder {
+    pub fn new(device_handle: vk::DeviceAddress) -> Self {
+        Self {
+            device_handle,
+            transform: Affine3A::IDENTITY,
+            custom_index: 0,
+            mask: 0xff,
+            sbt_record_offset: 0,
+            flags: vk::GeometryInstanceFlagsKHR::empty(),
+        }
+    }
+
+    /// Object to world transform. Build one from a `Mat4` with `Affine3A::from_mat4`.
+    pub fn transform(mut self, transform: Affine3A) -> Self {
+        self.transform = transform;
+        self
+    }
+
+    /// `InstanceCustomIndexKHR` in the shaders. Only 24 bits are stored.
+    pub fn custom_index(mut self, custom_index: u32) -> Self {
+        assert!(
+            custom_index <= MAX_24_BIT,
+            "custom index {} doesn't fit in 24 bits",
+            custom_index
+        );
+        self.custom_index = custom_index;
+        self
+    }
+
+    /// Rays only hit the instance if this and their cull mask have a bit in common.
+    pub fn mask(mut self, mask: u8) -> Self {
+        self.mask = mask;
+        self
+    }
+
+    /// Offset of the instance's hit group records in the shader binding table. Only 24 bits
+    /// are stored.
+    pub fn sbt_record_offset(mut self, sbt_record_offset: u32) -> Self {
+        assert!(
+            sbt_record_offset <= MAX_24_BIT,
+            "shader binding table record offset {} doesn't fit in 24 bits",
+            sbt_record_offset
+        );
+        self.sbt_record_offset = sbt_record_offset;
+        self
+    }
+
+    pub fn flags(mut self, flags: vk::GeometryInstanceFlagsKHR) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    pub fn build(self) -> vk::AccelerationStructureInstanceKHR {
+        // Every flag defined so far fits in the 8 bits the instance has for them.
+        let flags = u8::try_from(self.flags.as_raw()).expect("instance flags don't fit in 8 bits");
+
+        vk::AccelerationStructureInstanceKHR {
+            transform: transform_matrix(self.transform),
+            instance_custom_index_and_mask: Packed24_8::new(self.custom_index, self.mask),
+            instance_shader_binding_table_record_offset_and_flags: Packed24_8::new(
+                self.sbt_record_offset,
+                flags,
+            ),
+            acceleration_structure_reference: vk::AccelerationStructureReferenceKHR {
+                device_handle: self.device_handle,
+            },
+        }
+    }
+}
+
+/// Row-major 3x4 matrix as TLAS instances expect it.
+fn transform_matrix(transform: Affine3A) -> vk::TransformMatrixKHR {
+    // The transpose of the column-major 4x4 matrix lists its rows; the last one is implied.
+    let rows = Mat4::from(transform).transpose().to_cols_array();
+    vk::TransformMatrixKHR {
+        matrix: rows[..12].try_into().unwrap(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3A;
+
+    use super::*;
+
+    const HANDLE: vk::DeviceAddress = 0x1234_5678_9abc_def0;
+
+    #[test]
+    fn defaults() {
+        let instance = InstanceBuilder::new(HANDLE).build();
+
+        assert_eq!(
+            instance.transform.matrix,
+            [1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0]
+        );
+        assert_eq!(instance.instance_custom_index_and_mask.low_24(), 0);
+        assert_eq!(instance.instance_custom_index_and_mask.high_8(), 0xff);
+        assert_eq!(
+            instance
+                .instance_shader_binding_table_record_offset_and_flags
+                .low_24(),
+            0
+        );
+        assert_eq!(
+            instance
+                .instance_shader_binding_table_record_offset_and_flags
+                .high_8(),
+            0
+        );
+        assert_eq!(
+            unsafe { instance.acceleration_structure_reference.device_handle },
+            HANDLE
+        );
+    }
+
+    #[test]
+    fn packs_custom_index_and_mask() {
+        for (custom_index, mask) in [(MAX_24_BIT, 0xa5), (0x12_3456, 0), (0, 0x01)] {
+            let packed = InstanceBuilder::new(HANDLE)
+                .custom_index(custom_index)
+                .mask(mask)
+                .build()
+                .instance_custom_index_and_mask;
+            assert_eq!(packed.low_24(), custom_index);
+            assert_eq!(packed.high_8(), mask);
+        }
+    }
+
+    #[test]
+    fn packs_sbt_record_offset_and_flags() {
+        let flags = vk::GeometryInstanceFlagsKHR::TRIANGLE_FACING_CULL_DISABLE
+            | vk::GeometryInstanceFlagsKHR::FORCE_OPAQUE;
+        let packed = InstanceBuilder::new(HANDLE)
+            .sbt_record_offset(0xab_cdef)
+            .flags(flags)
+            .build()
+            .instance_shader_binding_table_record_offset_and_flags;
+
+        assert_eq!(packed.low_24(), 0xab_cdef);
+        assert_eq!(u32::from(packed.high_8()), flags.as_raw());
+        assert_eq!(packed.high_8(), 0x05);
+    }
+
+    #[test]
+    fn transform_is_row_major() {
+        let transform = Affine3A::from_cols(
+            Vec3A::new(1.0, 2.0, 3.0),
+            Vec3A::new(4.0, 5.0, 6.0),
+            Vec3A::new(7.0, 8.0, 9.0),
+            Vec3A::new(10.0, 11.0, 12.0),
+        );
+        let instance = InstanceBuilder::new(HANDLE).transform(transform).build();
+
+        // Each row is one output coordinate: three column entries, then the translation.
+        assert_eq!(
+            instance.transform.matrix,
+            [1.0, 4.0, 7.0, 10.0, 2.0, 5.0, 8.0, 11.0, 3.0, 6.0, 9.0, 12.0]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "custom index 16777216 doesn't fit in 24 bits")]
+    fn rejects_custom_index_over_24_bits() {
+        InstanceBuilder::new(HANDLE).custom_index(MAX_24_BIT + 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "record offset 16777216 doesn't fit in 24 bits")]
+    fn rejects_sbt_record_offset_over_24_bits() {
+        InstanceBuilder::new(HANDLE).sbt_record_offset(MAX_24_BIT + 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "instance flags don't fit in 8 bits")]
+    fn rejects_flags_over_8_bits() {
+        InstanceBuilder::new(HANDLE)
+            .flags(vk::GeometryInstanceFlagsKHR::from_raw(0x100))
+            .build();
+    }
+}
@@ -0,0 +1,143 @@
+//! Compile-time checks that the host-side mirrors of shader structs still match the shader
+//! crate, so a field added on one side only fails the build instead of garbling buffers. The
+//! shared constants need no check, as the host imports them from `pod`.
+//!
+//! Only sizes and field offsets are compared. Alignment differs on purpose: the host stores
+//! vectors as `[f32; N]`, while the shader crate's `Vec4` is 16-byte aligned when compiled for
+//! the host.
+
+use ash_raytracing_example_shader as shader;
+
+use crate::{curves::CurveSegment, mesh::Vertex, points::Point, FrameUniforms, MeshInfo};
+
+/// Byte offset of `$field` in `$ty`, usable in constants.
+macro_rules! offset_of {
+    ($ty:ty, $field:ident) => {{
+        let value = std::mem::MaybeUninit::<$ty>::uninit();
+        let base = value.as_ptr();
+        // SAFETY: only the field's address is taken; the uninitialized value is never read.
+        unsafe {
+            std::ptr::addr_of!((*base).$field)
+                .cast::<u8>()
+                .offset_from(base.cast::<u8>()) as usize
+        }
+    }};
+}
+
+/// Asserts that two structs have the same size and that each `host => shader` pair of fields
+/// sits at the same offset.
+macro_rules! assert_layout {
+    ($host:ty, $shader:ty { $($host_field:ident => $shader_field:ident),* $(,)? }) => {
+        const _: () = {
+            assert!(
+                std::mem::size_of::<$host>() == std::mem::size_of::<$shader>(),
+                concat!(stringify!($host), " and ", stringify!($shader), " differ in size"),
+            );
+            $(
+                assert!(
+                    offset_of!($host, $host_field) == offset_of!($shader, $shader_field),
+                    concat!(
+                        stringify!($host), "::", stringify!($host_field), " and ",
+                        stringify!($shader), "::", stringify!($shader_field),
+                        " are at different offsets",
+                    ),
+                );
+            )*
+        };
+    };
+}
+
+assert_layout!(FrameUniforms, shader::pod::FrameUniforms {
+    camera_origin => camera_origin,
+    camera_horizontal => camera_horizontal,
+    camera_vertical => camera_vertical,
+    camera_forward => camera_forward,
+    frame_index => frame_index,
+    spp => spp,
+    integrator => integrator,
+    sky_intensity => sky_intensity,
+    exposure => exposure,
+    image_width => image_width,
+    image_height => image_height,
+    tile_offset_x => tile_offset_x,
+    tile_offset_y => tile_offset_y,
+    backplate => backplate,
+    pixel_filter => pixel_filter,
+    filter_radius => filter_radius,
+    output_color_space => output_color_space,
+    ray_epsilon => ray_epsilon,
+    max_bounces => max_bounces,
+});
+
+assert_layout!(MeshInfo, shader::pod::MeshInfo {
+    first_index => first_index,
+    first_vertex => first_vertex,
+});
+
+assert_layout!(Vertex, shader::Vertex {
+    pos => pos,
+    normal => normal,
+    uv => uv,
+});
+
+// The shader packs each end point and its radius into one `Vec4`.
+assert_layout!(CurveSegment, shader::pod::CurveSegment {
+    start => start,
+    end => end,
+});
+
+// Likewise the centre and radius.
+assert_layout!(Point, shader::pod::Point {
+    position => sphere,
+    color => color,
+});
+
+// The checks above only compare the two sides with each other. These pin down the layout the
+// shaders' std140 and std430 declarations expect, which neither side can see.
+#[cfg(test)]
+mod tests {
+    use std::mem::size_of;
+
+    use super::*;
+
+    #[test]
+    fn frame_uniforms_layout() {
+        assert_eq!(size_of::<FrameUniforms>(), 128);
+        // std140 needs the uniform block to be a whole number of vec4s.
+        assert_eq!(size_of::<FrameUniforms>() % 16, 0);
+        assert_eq!(offset_of!(FrameUniforms, camera_origin), 0);
+        assert_eq!(offset_of!(FrameUniforms, camera_forward), 48);
+        assert_eq!(offset_of!(FrameUniforms, frame_index), 64);
+        assert_eq!(offset_of!(FrameUniforms, output_color_space), 112);
+        assert_eq!(offset_of!(FrameUniforms, max_bounces), 120);
+    }
+
+    #[test]
+    fn mesh_info_layout() {
+        assert_eq!(size_of::<MeshInfo>(), 8);
+        assert_eq!(offset_of!(MeshInfo, first_vertex), 4);
+    }
+
+    #[test]
+    fn vertex_layout() {
+        // Tightly packed; closest hit indexes the vertex buffer in 32-byte steps.
+        assert_eq!(size_of::<Vertex>(), 32);
+        assert_eq!(offset_of!(Vertex, normal), 12);
+        assert_eq!(offset_of!(Vertex, uv), 24);
+    }
+
+    #[test]
+    fn curve_segment_layout() {
+        assert_eq!(size_of::<CurveSegment>(), 32);
+        assert_eq!(offset_of!(CurveSegment, start_radius), 12);
+        assert_eq!(offset_of!(CurveSegment, end), 16);
+        assert_eq!(offset_of!(CurveSegment, end_radius), 28);
+    }
+
+    #[test]
+    fn point_layout() {
+        assert_eq!(size_of::<Point>(), 32);
+        assert_eq!(offset_of!(Point, radius), 12);
+        assert_eq!(offset_of!(Point, color), 16);
+    }
+}
@@ -2,28 +2,495 @@ use std::{
     collections::HashSet,
     ffi::{c_void, CStr, CString},
     fs::File,
-    io::Write,
+    marker::PhantomData,
     os::raw::c_char,
+    path::{Path, PathBuf},
     ptr::{self, null},
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
 };
 
-use ash::{
-    prelude::VkResult,
-    util::Align,
-    vk::{self, Packed24_8},
+use allocations::AllocationCategory;
+use as_stats::AccelerationStructureStats;
+use ash::{prelude::VkResult, util::Align, vk};
+use ash_raytracing_example_shader::pod::{
+    ASSERT_MESH_INDEX_OUT_OF_RANGE, ASSERT_NAN_NORMAL, MAX_ASSERT_RECORDS, PATH_LENGTH_BINS,
+    STAT_CAMERA_MISSES, STAT_OCCLUDED_RAYS, STAT_PATH_LENGTHS, STAT_PRIMARY_RAYS,
+    STAT_SECONDARY_RAYS, VISIBLE_TO_CAMERA, VISIBLE_TO_SECONDARY, VISIBLE_TO_SHADOW,
 };
+use bvh::{Bvh, BvhOptions};
+use checkpoint::Checkpoint;
+use clap::{error::ErrorKind, CommandFactory, Parser, ValueEnum};
+use glam::{vec3, Affine3A, Vec3};
+use gltf_scene::GltfScene;
+use instance::InstanceBuilder;
+use mesh::Vertex;
+use random::Random;
+use scenes::{Scene, SceneKind};
+use window::Presenter;
+use winit::{
+    dpi::PhysicalSize,
+    event::{Event, WindowEvent},
+    event_loop::{ControlFlow, EventLoop},
+    platform::run_return::EventLoopExtRunReturn,
+    window::WindowBuilder,
+};
+
+mod allocations;
+mod as_stats;
+mod bvh;
+mod checkpoint;
+mod curves;
+mod displacement;
+mod exr;
+mod gltf_scene;
+mod instance;
+mod layout;
+mod mesh;
+mod points;
+mod primitives;
+mod random;
+mod reflect;
+mod scatter;
+mod scenes;
+mod window;
+
+// Instance masks are 8 bits on the host, while the shaders' cull masks are 32-bit.
+const VISIBLE_TO_ALL: u8 = (VISIBLE_TO_CAMERA | VISIBLE_TO_SHADOW | VISIBLE_TO_SECONDARY) as u8;
+
+// Words in the statistics buffer.
+const STAT_COUNT: usize = STAT_PATH_LENGTHS + PATH_LENGTH_BINS;
+
+// Set by the Ctrl+C handler; rendering stops after the pass in flight.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// What a scene instance is made of.
+#[derive(Clone, Copy, Debug)]
+enum Shape {
+    /// Index into the triangle meshes.
+    Mesh(u32),
+    /// Index into the curve sets, drawn by the curve hit group.
+    Curves(u32),
+    /// Index into the point clouds, drawn by the point hit group.
+    Points(u32),
+}
+
+/// Host-side mirror of `pod::MeshInfo` in the shader crate.
+#[repr(C)]
+#[derive(Clone, Debug, Copy)]
+struct MeshInfo {
+    first_index: u32,
+    first_vertex: u32,
+}
 
+/// Host-side mirror of `pod::FrameUniforms` in the shader crate.
 #[repr(C)]
 #[derive(Clone, Debug, Copy)]
-struct Vertex {
-    pos: [f32; 3],
+struct FrameUniforms {
+    camera_origin: [f32; 4],
+    camera_horizontal: [f32; 4],
+    camera_vertical: [f32; 4],
+    camera_forward: [f32; 4],
+    frame_index: u32,
+    spp: u32,
+    integrator: u32,
+    sky_intensity: f32,
+    exposure: f32,
+    image_width: u32,
+    image_height: u32,
+    tile_offset_x: u32,
+    tile_offset_y: u32,
+    backplate: u32,
+    pixel_filter: u32,
+    filter_radius: f32,
+    output_color_space: u32,
+    ray_epsilon: f32,
+    max_bounces: u32,
+    _padding: u32,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Camera {
+    origin: Vec3,
+    look_at: Vec3,
+    up: Vec3,
+    vfov_degrees: f32,
+}
+
+impl Camera {
+    /// Returns the origin, scaled right and up vectors, and forward vector that raygen combines
+    /// into primary ray directions.
+    fn basis(&self, aspect_ratio: f32) -> [[f32; 4]; 4] {
+        let half_height = (self.vfov_degrees.to_radians() * 0.5).tan();
+        let forward = (self.look_at - self.origin).normalize();
+        let right = self.up.cross(forward).normalize();
+        let up = forward.cross(right);
+
+        [
+            self.origin.extend(1.0).to_array(),
+            (right * half_height * aspect_ratio).extend(0.0).to_array(),
+            (up * half_height).extend(0.0).to_array(),
+            forward.extend(0.0).to_array(),
+        ]
+    }
+
+    /// Looks down +z at the box `min..max` from far enough away that its bounding sphere fits
+    /// into the vertical field of view.
+    fn framing(min: Vec3, max: Vec3, vfov_degrees: f32) -> Self {
+        let center = (min + max) * 0.5;
+        let radius = (max - min).length() * 0.5;
+        let distance = radius / (vfov_degrees.to_radians() * 0.5).sin();
+
+        Self {
+            origin: center - Vec3::Z * distance,
+            look_at: center,
+            up: Vec3::Y,
+            vfov_degrees,
+        }
+    }
+
+    /// Conservatively tests whether the axis-aligned box `min..max` overlaps the view frustum.
+    fn sees_box(&self, aspect_ratio: f32, min: Vec3, max: Vec3) -> bool {
+        let [origin, horizontal, vertical, forward] =
+            self.basis(aspect_ratio).map(|v| Vec3::from_slice(&v[..3]));
+
+        let center = (min + max) * 0.5 - origin;
+        let extents = (max - min) * 0.5;
+
+        // Outward normals of the four side planes, which all pass through the origin.
+        let side_normals = [
+            (horizontal, vertical),
+            (-horizontal, vertical),
+            (vertical, horizontal),
+            (-vertical, horizontal),
+        ]
+        .map(|(side, other)| {
+            let normal = (forward + side).cross(other);
+            if normal.dot(side) < 0.0 {
+                -normal
+            } else {
+                normal
+            }
+        });
+
+        side_normals
+            .iter()
+            .chain([-forward].iter())
+            .all(|normal| center.dot(*normal) - extents.dot(normal.abs()) <= 0.0)
+    }
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum OutputFormat {
+    /// 8-bit PNG.
+    Png,
+    /// 32-bit float OpenEXR, in linear colour.
+    Exr,
+}
+
+impl OutputFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Png => "png",
+            Self::Exr => "exr",
+        }
+    }
+
+    /// Format of the storage image the shaders write, and of its host-side copy.
+    fn color_format(self) -> vk::Format {
+        match self {
+            Self::Png => vk::Format::R8G8B8A8_UNORM,
+            Self::Exr => vk::Format::R32G32B32A32_SFLOAT,
+        }
+    }
+
+    fn bytes_per_pixel(self) -> usize {
+        match self {
+            Self::Png => 4,
+            Self::Exr => 16,
+        }
+    }
+}
+
+/// Rectangle of the image to trace, in pixels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Crop {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+impl Crop {
+    /// Parses `x,y,width,height`.
+    fn parse(s: &str) -> Result<Self, String> {
+        let values = s
+            .split(',')
+            .map(|value| value.trim().parse::<u32>().map_err(|e| e.to_string()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let [x, y, width, height] = values[..] else {
+            return Err(format!("expected x,y,width,height, got {} values", values.len()));
+        };
+        if width == 0 || height == 0 {
+            return Err("width and height must be at least 1".to_string());
+        }
+        Ok(Self {
+            x,
+            y,
+            width,
+            height,
+        })
+    }
+
+    fn fits_in(&self, width: u32, height: u32) -> bool {
+        self.x
+            .checked_add(self.width)
+            .map_or(false, |right| right <= width)
+            && self
+                .y
+                .checked_add(self.height)
+                .map_or(false, |bottom| bottom <= height)
+    }
+}
+
+/// Parses a duration such as `500ms`, `90s`, `5m` or `1h30m`.
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let mut total = Duration::ZERO;
+    let mut rest = s.trim();
+    if rest.is_empty() {
+        return Err("expected a duration such as 90s or 5m".to_string());
+    }
+
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let value = rest[..digits]
+            .parse::<u64>()
+            .map_err(|_| format!("expected a number at {:?}", rest))?;
+        rest = &rest[digits..];
+
+        let unit = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let part = match &rest[..unit] {
+            "ms" => Duration::from_millis(value),
+            "s" => Duration::from_secs(value),
+            "m" => Duration::from_secs(value.saturating_mul(60)),
+            "h" => Duration::from_secs(value.saturating_mul(60 * 60)),
+            other => return Err(format!("unknown unit {:?}; use ms, s, m or h", other)),
+        };
+        total = total.saturating_add(part);
+        rest = &rest[unit..];
+    }
+
+    Ok(total)
+}
+
+/// How a camera ray is turned into a colour, mirroring `integrator::Integrator` in the shader
+/// crate.
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Integrator {
+    /// Unlit instance colour.
+    Flat = 0,
+    /// Ambient occlusion within one unit of the first hit.
+    AmbientOcclusion = 1,
+    /// Shading normal of the first hit.
+    Normal = 2,
+    /// Diffuse path tracing lit by the environment.
+    Path = 3,
+}
+
+/// Weighting of samples within the filter footprint, mirroring `filter::PixelFilter` in the
+/// shader crate.
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum PixelFilter {
+    Box = 0,
+    Tent = 1,
+    Gaussian = 2,
+    BlackmanHarris = 3,
+}
+
+/// Colour space of the output image, mirroring `color::ColorSpace` in the shader crate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum ColorSpace {
+    /// Rec.709 primaries with the sRGB transfer function.
+    Srgb = 0,
+    /// Rec.709 primaries, no transfer function.
+    LinearSrgb = 1,
+    /// Rec.2020 primaries with a 2.4 gamma.
+    Rec2020 = 2,
+    /// ACEScg primaries, no transfer function.
+    #[value(name = "acescg")]
+    AcesCg = 3,
+}
+
+/// Renders the example scene with KHR ray tracing and writes it to a PNG or EXR file.
+#[derive(Parser, Debug)]
+struct Args {
+    /// Image width in pixels.
+    #[arg(long, default_value_t = 800, value_parser = clap::value_parser!(u32).range(1..))]
+    width: u32,
+    /// Image height in pixels.
+    #[arg(long, default_value_t = 600, value_parser = clap::value_parser!(u32).range(1..))]
+    height: u32,
+    /// Samples per pixel.
+    #[arg(long, default_value_t = 16, value_parser = clap::value_parser!(u32).range(1..))]
+    spp: u32,
+    /// Samples per pixel traced by each accumulation pass. A render can only stop, and previews
+    /// are only written, between passes.
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    pass_spp: u32,
+    /// Only trace the `x,y,width,height` rectangle of the image; the rest stays black.
+    #[arg(long, value_parser = Crop::parse)]
+    crop: Option<Crop>,
+    /// Instead of stopping after --spp samples per pixel, keep adding passes until the render
+    /// has run this long, e.g. `90s`, `5m` or `1h30m`. The last pass may overshoot it.
+    #[arg(long, value_parser = parse_duration)]
+    time: Option<Duration>,
+    /// Write the passes accumulated so far to preview.png at most this often while rendering,
+    /// e.g. `30s`.
+    #[arg(long, value_parser = parse_duration)]
+    preview_interval: Option<Duration>,
+    /// Carry on from the checkpoint an interrupted render wrote, adding passes to its samples
+    /// until there are --spp of them. Only the image size is checked, so use the same scene and
+    /// settings as before.
+    #[arg(long, conflicts_with = "window")]
+    resume: Option<PathBuf>,
+    /// Where to write the image. Defaults to `out.png` or `out.exr`, depending on `--format`.
+    #[arg(long)]
+    out: Option<String>,
+    /// File format of the image.
+    #[arg(long, value_enum, default_value_t = OutputFormat::Png)]
+    format: OutputFormat,
+    /// Colour space the image is written in. Colours are rendered in linear Rec.709 and
+    /// converted to it. EXR files hold linear values, so they use linear sRGB unless this is
+    /// ACEScg.
+    #[arg(long, value_enum, default_value_t = ColorSpace::Srgb)]
+    color_space: ColorSpace,
+    /// How camera rays are shaded.
+    #[arg(long, value_enum, default_value_t = Integrator::Flat)]
+    integrator: Integrator,
+    /// Bounces a path takes before it is cut off, with `--integrator path`.
+    #[arg(long, default_value_t = 4)]
+    max_bounces: u32,
+    /// Weighting of the samples around each pixel centre.
+    #[arg(long, value_enum, default_value_t = PixelFilter::BlackmanHarris)]
+    pixel_filter: PixelFilter,
+    /// In pixels, from the pixel centre to the edge of the filter. A box of radius 0.5 averages
+    /// samples within each pixel only.
+    #[arg(long, default_value_t = 1.5)]
+    filter_radius: f32,
+    /// Built-in scene to render.
+    #[arg(long, value_enum, default_value_t = SceneKind::Basic, conflicts_with = "gltf")]
+    scene: SceneKind,
+    /// Render the meshes, instances and camera of a glTF or GLB file instead of the built-in
+    /// scene.
+    #[arg(long)]
+    gltf: Option<PathBuf>,
+    /// Start rays leaving a surface this far along its normal instead of offsetting them by a
+    /// few ulps. Only meant to show the self-intersection it causes; see `--scene grazing`.
+    #[arg(long)]
+    ray_epsilon: Option<f32>,
+    /// Trace primary rays against a TLAS holding only the instances in view.
+    #[arg(long)]
+    primary_ray_culling: bool,
+    /// Ask for a high priority queue where available and render in small dispatches, so a long
+    /// render doesn't stall other GPU work such as the desktop compositor.
+    #[arg(long)]
+    low_latency: bool,
+    /// Build a SAH BVH of every mesh on the host and print its statistics.
+    #[arg(long)]
+    bvh_stats: bool,
+    /// Show the image in a window that keeps accumulating samples until it is closed, then
+    /// write it out.
+    #[arg(long)]
+    window: bool,
 }
 
 fn main() {
     const ENABLE_VALIDATION_LAYER: bool = true;
-    const WIDTH: u32 = 800;
-    const HEIGHT: u32 = 600;
-    const COLOR_FORMAT: vk::Format = vk::Format::R8G8B8A8_UNORM;
+    // `debug_print!` output from the shaders is delivered through the validation layer.
+    const DEBUG_PRINTF: bool = cfg!(feature = "debug-printf") && ENABLE_VALIDATION_LAYER;
+    const SKY_INTENSITY: f32 = 1.0;
+    // In stops.
+    const EXPOSURE: f32 = 0.0;
+    const UNIFORM_RING_SIZE: u64 = 2;
+    // Largest dispatch with --low-latency.
+    const LOW_LATENCY_DISPATCH_INVOCATIONS: u32 = 256 * 256;
+    // Raygen traces every ray itself; neither hit nor miss shaders recurse.
+    const MAX_RAY_RECURSION_DEPTH: u32 = 1;
+    // Move the world so the camera sits at the origin before building the TLAS. Single-precision
+    // positions are densest near zero, so scenes far from the world origin don't lose precision
+    // around the camera.
+    const CAMERA_RELATIVE: bool = true;
+    // Input images are sRGB encoded; sampling through the _SRGB formats linearizes them.
+    const SKYBOX_FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+    const SKYBOX_DIR: &str = "skybox";
+    const SKYBOX_FACE_SIZE: u32 = 256;
+    // Shown behind the scene to camera rays if the file exists.
+    const BACKPLATE_PATH: &str = "backplate.png";
+    const BACKPLATE_FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+
+    let args = Args::parse();
+    if let Some(crop) = args
+        .crop
+        .filter(|crop| !crop.fits_in(args.width, args.height))
+    {
+        Args::command()
+            .error(
+                ErrorKind::ValueValidation,
+                format!(
+                    "--crop {},{},{},{} doesn't fit in the {}x{} image",
+                    crop.x, crop.y, crop.width, crop.height, args.width, args.height
+                ),
+            )
+            .exit();
+    }
+    let checkpoint = args.resume.as_deref().map(|path| {
+        let checkpoint = Checkpoint::load(path)
+            .unwrap_or_else(|err| Args::command().error(ErrorKind::Io, err).exit());
+        let problem = if (checkpoint.width, checkpoint.height) != (args.width, args.height) {
+            Some(format!(
+                "the checkpoint is {}x{}, not {}x{}",
+                checkpoint.width, checkpoint.height, args.width, args.height
+            ))
+        } else if args.time.is_none() && checkpoint.samples >= args.spp {
+            Some(format!(
+                "the checkpoint already has {} samples per pixel, raise --spp to add more",
+                checkpoint.samples
+            ))
+        } else {
+            None
+        };
+        if let Some(problem) = problem {
+            Args::command()
+                .error(ErrorKind::ValueValidation, problem)
+                .exit();
+        }
+        checkpoint
+    });
+    let out = args
+        .out
+        .clone()
+        .unwrap_or_else(|| format!("out.{}", args.format.extension()));
+    let color_format = args.format.color_format();
+    let output_color_space = match args.format {
+        OutputFormat::Png => args.color_space,
+        OutputFormat::Exr if args.color_space == ColorSpace::AcesCg => ColorSpace::AcesCg,
+        OutputFormat::Exr => ColorSpace::LinearSrgb,
+    };
+
+    let mut window = args.window.then(|| {
+        let event_loop = EventLoop::new();
+        let window = WindowBuilder::new()
+            .with_title("ash-raytracing-example")
+            .with_inner_size(PhysicalSize::new(args.width, args.height))
+            .with_resizable(false)
+            .build(&event_loop)
+            .expect("failed to create window");
+        (event_loop, window)
+    });
 
     let validation_layers: Vec<CString> = if ENABLE_VALIDATION_LAYER {
         vec![CString::new("VK_LAYER_KHRONOS_validation").unwrap()]
@@ -39,10 +506,13 @@ fn main() {
         .iter()
         .map(|c_str| c_str.as_ptr())
         .collect();
-    let extension_name_ptr = extension_names
+    let mut extension_name_ptr = extension_names
         .iter()
         .map(|ext| ext.as_ptr())
         .collect::<Vec<_>>();
+    if let Some((_, window)) = &window {
+        extension_name_ptr.extend_from_slice(Presenter::required_instance_extensions(window));
+    }
 
     let entry = unsafe { ash::Entry::load() }.unwrap();
 
@@ -63,7 +533,13 @@ fn main() {
                 vk::DebugUtilsMessageSeverityFlagsEXT::WARNING |
             // vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE |
             // vk::DebugUtilsMessageSeverityFlagsEXT::INFO |
-            vk::DebugUtilsMessageSeverityFlagsEXT::ERROR,
+            vk::DebugUtilsMessageSeverityFlagsEXT::ERROR |
+            // Debug printf messages are reported as info.
+            if DEBUG_PRINTF {
+                vk::DebugUtilsMessageSeverityFlagsEXT::INFO
+            } else {
+                vk::DebugUtilsMessageSeverityFlagsEXT::empty()
+            },
             )
             .message_type(
                 vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
@@ -85,12 +561,22 @@ fn main() {
             .application_info(&application_info)
             .enabled_layer_names(validation_layers_ptr.as_slice());
 
-        let instance_create_info = if ENABLE_VALIDATION_LAYER {
+        let enabled_validation_features = [vk::ValidationFeatureEnableEXT::DEBUG_PRINTF];
+        let mut validation_features = vk::ValidationFeaturesEXT::builder()
+            .enabled_validation_features(&enabled_validation_features)
+            .build();
+
+        let instance_create_info = if DEBUG_PRINTF {
             instance_create_info
                 .enabled_extension_names(&extension_name_ptr)
                 .push_next(&mut debug_utils_create_info)
-        } else {
+                .push_next(&mut validation_features)
+        } else if ENABLE_VALIDATION_LAYER {
             instance_create_info
+                .enabled_extension_names(&extension_name_ptr)
+                .push_next(&mut debug_utils_create_info)
+        } else {
+            instance_create_info.enabled_extension_names(&extension_name_ptr)
         }
         .build();
 
@@ -98,25 +584,44 @@ fn main() {
             .expect("failed to create instance!")
     };
 
-    let (physical_device, queue_family_index) = pick_physical_device_and_queue_family_indices(
-        &instance,
-        &[
-            ash::extensions::khr::AccelerationStructure::name(),
-            ash::extensions::khr::DeferredHostOperations::name(),
-            ash::extensions::khr::RayTracingPipeline::name(),
-        ],
-    )
-    .unwrap()
-    .unwrap();
+    let mut required_device_extensions = vec![
+        ash::extensions::khr::AccelerationStructure::name(),
+        ash::extensions::khr::DeferredHostOperations::name(),
+        ash::extensions::khr::RayTracingPipeline::name(),
+    ];
+    if args.window {
+        required_device_extensions.push(ash::extensions::khr::Swapchain::name());
+    }
+
+    let (physical_device, queue_family_index) =
+        pick_physical_device_and_queue_family_indices(&instance, &required_device_extensions)
+            .unwrap()
+            .unwrap();
+
+    let memory_budget_enabled =
+        device_supports_extension(&instance, physical_device, vk::ExtMemoryBudgetFn::name());
+
+    let global_priority_supported = args.low_latency
+        && device_supports_extension(&instance, physical_device, vk::ExtGlobalPriorityFn::name());
 
-    let device: ash::Device = {
+    let create_device = |global_priority: bool| {
         let priorities = [1.0];
 
-        let queue_create_info = vk::DeviceQueueCreateInfo::builder()
-            .queue_family_index(queue_family_index)
-            .queue_priorities(&priorities)
+        let mut global_priority_info = vk::DeviceQueueGlobalPriorityCreateInfoEXT::builder()
+            .global_priority(vk::QueueGlobalPriorityEXT::HIGH)
             .build();
 
+        let mut queue_create_info_builder = vk::DeviceQueueCreateInfo::builder()
+            .queue_family_index(queue_family_index)
+            .queue_priorities(&priorities);
+
+        if global_priority {
+            queue_create_info_builder =
+                queue_create_info_builder.push_next(&mut global_priority_info);
+        }
+
+        let queue_create_infos = [queue_create_info_builder.build()];
+
         let mut features2 = vk::PhysicalDeviceFeatures2::default();
         unsafe {
             (instance.fp_v1_1().get_physical_device_features2)(physical_device, &mut features2)
@@ -135,7 +640,7 @@ fn main() {
             .ray_tracing_pipeline(true)
             .build();
 
-        let enabled_extension_names = [
+        let mut enabled_extension_names = vec![
             ash::extensions::khr::RayTracingPipeline::name().as_ptr(),
             ash::extensions::khr::AccelerationStructure::name().as_ptr(),
             ash::extensions::khr::DeferredHostOperations::name().as_ptr(),
@@ -144,24 +649,51 @@ fn main() {
             vk::KhrGetMemoryRequirements2Fn::name().as_ptr(),
         ];
 
+        if memory_budget_enabled {
+            enabled_extension_names.push(vk::ExtMemoryBudgetFn::name().as_ptr());
+        }
+
+        if global_priority {
+            enabled_extension_names.push(vk::ExtGlobalPriorityFn::name().as_ptr());
+        }
+
+        if DEBUG_PRINTF {
+            enabled_extension_names.push(vk::KhrShaderNonSemanticInfoFn::name().as_ptr());
+        }
+
+        if args.window {
+            enabled_extension_names.push(ash::extensions::khr::Swapchain::name().as_ptr());
+        }
+
         let device_create_info = vk::DeviceCreateInfo::builder()
             .push_next(&mut features2)
             .push_next(&mut features12)
             .push_next(&mut as_feature)
             .push_next(&mut raytracing_pipeline)
-            .queue_create_infos(&[queue_create_info])
+            .queue_create_infos(&queue_create_infos)
             .enabled_extension_names(&enabled_extension_names)
             .build();
 
         unsafe { instance.create_device(physical_device, &device_create_info, None) }
-            .expect("Failed to create logical Device!")
     };
 
+    // Raising the global priority may need privileges the process doesn't have.
+    let device: ash::Device = match create_device(global_priority_supported) {
+        Err(vk::Result::ERROR_NOT_PERMITTED_EXT) => {
+            eprintln!("warning: not permitted to create a high priority queue, using the default.");
+            create_device(false)
+        }
+        result => result,
+    }
+    .expect("Failed to create logical Device!");
+
     let mut rt_pipeline_properties = vk::PhysicalDeviceRayTracingPipelinePropertiesKHR::default();
+    let mut as_properties = vk::PhysicalDeviceAccelerationStructurePropertiesKHR::default();
 
     {
         let mut physical_device_properties2 = vk::PhysicalDeviceProperties2::builder()
             .push_next(&mut rt_pipeline_properties)
+            .push_next(&mut as_properties)
             .build();
 
         unsafe {
@@ -169,6 +701,21 @@ fn main() {
                 .get_physical_device_properties2(physical_device, &mut physical_device_properties2);
         }
     }
+    // The raygen shader leaves the storage image's format to the view, so one shader can write
+    // both 8-bit and float images.
+    let physical_device_features =
+        unsafe { instance.get_physical_device_features(physical_device) };
+    assert!(
+        physical_device_features.shader_storage_image_write_without_format == vk::TRUE,
+        "shaderStorageImageWriteWithoutFormat is not supported."
+    );
+    assert!(
+        MAX_RAY_RECURSION_DEPTH <= rt_pipeline_properties.max_ray_recursion_depth,
+        "ray recursion depth {} exceeds maxRayRecursionDepth {}.",
+        MAX_RAY_RECURSION_DEPTH,
+        rt_pipeline_properties.max_ray_recursion_depth,
+    );
+
     let acceleration_structure =
         ash::extensions::khr::AccelerationStructure::new(&instance, &device);
 
@@ -176,6 +723,18 @@ fn main() {
 
     let graphics_queue = unsafe { device.get_device_queue(queue_family_index, 0) };
 
+    let mut presenter = window.as_ref().map(|(_, window)| {
+        Presenter::new(
+            &entry,
+            &instance,
+            &device,
+            physical_device,
+            queue_family_index,
+            window,
+            matches!(args.format, OutputFormat::Exr),
+        )
+    });
+
     let command_pool = {
         let command_pool_create_info = vk::CommandPoolCreateInfo::builder()
             .queue_family_index(queue_family_index)
@@ -191,11 +750,11 @@ fn main() {
     let image = {
         let image_create_info = vk::ImageCreateInfo::builder()
             .image_type(vk::ImageType::TYPE_2D)
-            .format(COLOR_FORMAT)
+            .format(color_format)
             .extent(
                 vk::Extent3D::builder()
-                    .width(WIDTH)
-                    .height(HEIGHT)
+                    .width(args.width)
+                    .height(args.height)
                     .depth(1)
                     .build(),
             )
@@ -206,7 +765,8 @@ fn main() {
             .usage(
                 vk::ImageUsageFlags::COLOR_ATTACHMENT
                     | vk::ImageUsageFlags::STORAGE
-                    | vk::ImageUsageFlags::TRANSFER_SRC,
+                    | vk::ImageUsageFlags::TRANSFER_SRC
+                    | vk::ImageUsageFlags::TRANSFER_DST,
             )
             .build();
 
@@ -215,15 +775,16 @@ fn main() {
 
     let device_memory = {
         let mem_reqs = unsafe { device.get_image_memory_requirements(image) };
-        let mem_alloc_info = vk::MemoryAllocateInfo::builder()
-            .allocation_size(mem_reqs.size)
-            .memory_type_index(get_memory_type_index(
+        unsafe {
+            allocate_memory(
+                &device,
                 device_memory_properties,
-                mem_reqs.memory_type_bits,
+                mem_reqs,
                 vk::MemoryPropertyFlags::DEVICE_LOCAL,
-            ));
-
-        unsafe { device.allocate_memory(&mem_alloc_info, None) }.unwrap()
+                vk::MemoryAllocateFlags::empty(),
+                AllocationCategory::Image,
+            )
+        }
     };
 
     unsafe { device.bind_image_memory(image, device_memory, 0) }.unwrap();
@@ -231,7 +792,7 @@ fn main() {
     let image_view = {
         let image_view_create_info = vk::ImageViewCreateInfo::builder()
             .view_type(vk::ImageViewType::TYPE_2D)
-            .format(COLOR_FORMAT)
+            .format(color_format)
             .subresource_range(vk::ImageSubresourceRange {
                 aspect_mask: vk::ImageAspectFlags::COLOR,
                 base_mip_level: 0,
@@ -268,21 +829,31 @@ fn main() {
         }
         .unwrap();
 
+        let subresource_range = vk::ImageSubresourceRange::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .base_mip_level(0)
+            .level_count(1)
+            .base_array_layer(0)
+            .layer_count(1)
+            .build();
+
         let image_barrier = vk::ImageMemoryBarrier::builder()
             .src_access_mask(vk::AccessFlags::empty())
-            .dst_access_mask(vk::AccessFlags::empty())
+            .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
             .old_layout(vk::ImageLayout::UNDEFINED)
             .new_layout(vk::ImageLayout::GENERAL)
             .image(image)
-            .subresource_range(
-                vk::ImageSubresourceRange::builder()
-                    .aspect_mask(vk::ImageAspectFlags::COLOR)
-                    .base_mip_level(0)
-                    .level_count(1)
-                    .base_array_layer(0)
-                    .layer_count(1)
-                    .build(),
-            )
+            .subresource_range(subresource_range)
+            .build();
+
+        // Pixels outside the crop window are never traced, so start from black.
+        let clear_barrier = vk::ImageMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_WRITE)
+            .old_layout(vk::ImageLayout::GENERAL)
+            .new_layout(vk::ImageLayout::GENERAL)
+            .image(image)
+            .subresource_range(subresource_range)
             .build();
 
         unsafe {
@@ -296,6 +867,26 @@ fn main() {
                 &[image_barrier],
             );
 
+            device.cmd_clear_color_image(
+                command_buffer,
+                image,
+                vk::ImageLayout::GENERAL,
+                &vk::ClearColorValue {
+                    float32: [0.0, 0.0, 0.0, 1.0],
+                },
+                &[subresource_range],
+            );
+
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[clear_barrier],
+            );
+
             device.end_command_buffer(command_buffer).unwrap();
         }
 
@@ -315,271 +906,529 @@ fn main() {
         }
     }
 
+    // skybox
+
+    let (skybox_face_size, skybox_pixels) = load_skybox_faces(Path::new(SKYBOX_DIR))
+        .unwrap_or_else(|| (SKYBOX_FACE_SIZE, procedural_skybox_faces(SKYBOX_FACE_SIZE)));
+
+    // Faces are packed in +X, -X, +Y, -Y, +Z, -Z order, which is the cube map layer order.
+    let (skybox_image, skybox_device_memory, skybox_image_view) = create_texture(
+        &device,
+        command_pool,
+        graphics_queue,
+        device_memory_properties,
+        SKYBOX_FORMAT,
+        vk::ImageCreateFlags::CUBE_COMPATIBLE,
+        vk::ImageViewType::CUBE,
+        skybox_face_size,
+        skybox_face_size,
+        6,
+        &skybox_pixels,
+    );
+
+    let skybox_sampler = {
+        let sampler_create_info = vk::SamplerCreateInfo::builder()
+            .mag_filter(vk::Filter::LINEAR)
+            .min_filter(vk::Filter::LINEAR)
+            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .max_lod(0.0)
+            .build();
+
+        unsafe { device.create_sampler(&sampler_create_info, None) }.unwrap()
+    };
+
+    // backplate
+
+    let backplate = load_png_rgba8(Path::new(BACKPLATE_PATH));
+    let backplate_enabled = backplate.is_some();
+
+    // Without a backplate a single transparent texel keeps the descriptor valid.
+    let (backplate_width, backplate_height, backplate_pixels) =
+        backplate.unwrap_or((1, 1, vec![0; 4]));
+
+    let (backplate_image, backplate_device_memory, backplate_image_view) = create_texture(
+        &device,
+        command_pool,
+        graphics_queue,
+        device_memory_properties,
+        BACKPLATE_FORMAT,
+        vk::ImageCreateFlags::empty(),
+        vk::ImageViewType::TYPE_2D,
+        backplate_width,
+        backplate_height,
+        1,
+        &backplate_pixels,
+    );
+
     // acceleration structures
 
-    let (vertex_count, vertex_stride, vertex_buffer) = {
-        let vertices = [
-            Vertex {
-                pos: [-0.5, -0.5, 0.0],
-            },
-            Vertex {
-                pos: [0.0, 0.5, 0.0],
-            },
-            Vertex {
-                pos: [0.5, -0.5, 0.0],
-            },
-        ];
+    // Static geometry wants the fastest traversal and can be compacted once built; meshes that
+    // get rebuilt often should prefer a fast build instead. Nothing is refitted, so no BLAS asks
+    // for ALLOW_UPDATE, which would only cost memory and trace performance.
+    let fast_trace = vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE
+        | vk::BuildAccelerationStructureFlagsKHR::ALLOW_COMPACTION;
+    let fast_build = vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_BUILD;
+
+    // A glTF file replaces the built-in scene.
+    let scene = match args.gltf.as_deref() {
+        Some(path) => {
+            let gltf_scene = GltfScene::load(path);
+            println!(
+                "Loaded {} meshes and {} instances from {:?}",
+                gltf_scene.meshes.len(),
+                gltf_scene.instances.len(),
+                path
+            );
+            gltf_scene.into_scene(fast_trace)
+        }
+        None => Scene::builtin(args.scene, fast_trace, fast_build),
+    };
+
+    let (meshes, mut blas_flags): (Vec<_>, Vec<_>) = scene
+        .meshes
+        .into_iter()
+        .map(|(mut mesh, flags)| {
+            mesh.optimize();
+            (mesh, flags)
+        })
+        .unzip();
+
+    // The hardware builds its own BVHs; this reports how good a host-side SAH build of the same
+    // meshes is, and how fast the host traverses it, as a reference for the planned software
+    // fallback.
+    if args.bvh_stats {
+        const PROBE_RAYS: u32 = 10000;
+
+        let bvh_options = BvhOptions::default();
+        let mut random = Random::new(3);
+        for (i, mesh) in meshes.iter().enumerate() {
+            let bvh = Bvh::build(mesh, &bvh_options);
+            let stats = bvh.stats(&bvh_options);
+
+            // Rays from a sphere around the mesh towards random points in its bounds.
+            let (min, max) = mesh.bounds();
+            let center = (min + max) * 0.5;
+            let radius = (max - min).length().max(f32::MIN_POSITIVE);
+            let mut random_in = |min: Vec3, max: Vec3| {
+                vec3(
+                    random.range(min.x, max.x),
+                    random.range(min.y, max.y),
+                    random.range(min.z, max.z),
+                )
+            };
+            let rays = (0..PROBE_RAYS)
+                .map(|_| {
+                    let origin =
+                        center + random_in(Vec3::NEG_ONE, Vec3::ONE).normalize_or_zero() * radius;
+                    (origin, (random_in(min, max) - origin).normalize_or_zero())
+                })
+                .collect::<Vec<_>>();
+
+            let start = Instant::now();
+            let hits = rays
+                .iter()
+                .filter(|&&(origin, direction)| {
+                    bvh.intersect(mesh, origin, direction, 0.0, f32::INFINITY)
+                        .is_some()
+                })
+                .count();
+            let elapsed = start.elapsed();
+
+            println!(
+                "BVH {}: {} nodes, {} leaves (up to {} triangles), depth {}, SAH cost {:.2}, {} KiB, \
+                 {:.1}% of probe rays hit at {:.2} Mrays/s",
+                i,
+                stats.node_count,
+                stats.leaf_count,
+                stats.max_leaf_size,
+                stats.max_depth,
+                stats.sah_cost,
+                bvh.to_bytes().len() / 1024,
+                hits as f64 / PROBE_RAYS as f64 * 100.0,
+                PROBE_RAYS as f64 / elapsed.as_secs_f64().max(f64::MIN_POSITIVE) / 1_000_000.0,
+            );
+        }
+    }
+
+    let curve_sets = scene.curve_sets;
+    blas_flags.extend(curve_sets.iter().map(|&(_, flags)| flags));
+
+    for (i, (curves, _)) in curve_sets.iter().enumerate() {
+        println!(
+            "Curves {}: {} segments, {} KiB of geometry",
+            i,
+            curves.segment_count(),
+            curves.geometry_size() / 1024,
+        );
+    }
+
+    let point_clouds = scene.point_clouds;
+    blas_flags.extend(point_clouds.iter().map(|&(_, flags)| flags));
+
+    for (i, (points, _)) in point_clouds.iter().enumerate() {
+        println!(
+            "Point cloud {}: {} points, {} KiB of geometry",
+            i,
+            points.point_count(),
+            points.geometry_size() / 1024,
+        );
+    }
+
+    let scene_instances = scene.instances;
+    let camera = scene.camera;
+
+    // All meshes share one vertex and one index buffer; `mesh_infos` records where each starts.
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    let mut mesh_infos = Vec::with_capacity(meshes.len());
+
+    for mesh in &meshes {
+        mesh_infos.push(MeshInfo {
+            first_index: indices.len() as u32,
+            first_vertex: vertices.len() as u32,
+        });
+        vertices.extend_from_slice(&mesh.vertices);
+        indices.extend_from_slice(&mesh.indices);
+    }
 
-        let vertex_count = vertices.len();
-        let vertex_stride = std::mem::size_of::<Vertex>();
+    let vertex_stride = std::mem::size_of::<Vertex>();
 
-        let vertex_buffer_size = vertex_stride * vertex_count;
+    let vertex_buffer = {
+        let vertex_buffer_size = vertex_stride * vertices.len();
 
         let mut vertex_buffer = BufferResource::new(
             vertex_buffer_size as vk::DeviceSize,
             vk::BufferUsageFlags::VERTEX_BUFFER
+                | vk::BufferUsageFlags::STORAGE_BUFFER
                 | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
                 | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR,
             vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
             &device,
             device_memory_properties,
+            AllocationCategory::Geometry,
         );
 
         vertex_buffer.store(&vertices, &device);
 
-        (vertex_count, vertex_stride, vertex_buffer)
+        vertex_buffer
     };
 
-    let (index_count, index_buffer) = {
-        let indices: [u32; 3] = [0, 1, 2];
-
-        let index_count = indices.len();
-        let index_buffer_size = std::mem::size_of::<usize>() * index_count;
+    let index_buffer = {
+        let index_buffer_size = std::mem::size_of::<u32>() * indices.len();
 
         let mut index_buffer = BufferResource::new(
             index_buffer_size as vk::DeviceSize,
             vk::BufferUsageFlags::INDEX_BUFFER
+                | vk::BufferUsageFlags::STORAGE_BUFFER
                 | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
                 | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR,
             vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
             &device,
             device_memory_properties,
+            AllocationCategory::Geometry,
         );
 
         index_buffer.store(&indices, &device);
-        (index_count, index_buffer)
+        index_buffer
     };
 
-    let geometry = vk::AccelerationStructureGeometryKHR::builder()
-        .geometry_type(vk::GeometryTypeKHR::TRIANGLES)
-        .geometry(vk::AccelerationStructureGeometryDataKHR {
-            triangles: vk::AccelerationStructureGeometryTrianglesDataKHR::builder()
-                .vertex_data(vk::DeviceOrHostAddressConstKHR {
-                    device_address: unsafe {
-                        get_buffer_device_address(&device, vertex_buffer.buffer)
-                    },
-                })
-                .max_vertex(vertex_count as u32 - 1)
-                .vertex_stride(vertex_stride as u64)
-                .vertex_format(vk::Format::R32G32B32_SFLOAT)
-                .index_data(vk::DeviceOrHostAddressConstKHR {
-                    device_address: unsafe {
-                        get_buffer_device_address(&device, index_buffer.buffer)
-                    },
-                })
-                .index_type(vk::IndexType::UINT32)
-                .build(),
-        })
-        .flags(vk::GeometryFlagsKHR::OPAQUE)
-        .build();
+    let mesh_info_buffer = {
+        let buffer_size = std::mem::size_of::<MeshInfo>() * mesh_infos.len();
 
-    // Create bottom-level acceleration structure
+        let mut mesh_info_buffer = BufferResource::new(
+            buffer_size as vk::DeviceSize,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            &device,
+            device_memory_properties,
+            AllocationCategory::Geometry,
+        );
 
-    let (bottom_as, bottom_as_buffer) = {
-        let build_range_info = vk::AccelerationStructureBuildRangeInfoKHR::builder()
-            .first_vertex(0)
-            .primitive_count(index_count as u32 / 3)
-            .primitive_offset(0)
-            .transform_offset(0)
-            .build();
+        mesh_info_buffer.store(&mesh_infos, &device);
+        mesh_info_buffer
+    };
 
-        let geometries = [geometry];
+    // Curve sets share one segment buffer and one buffer of their segments' boxes.
+    // `first_segments` records where each set starts in both. Scenes without curves still get
+    // one-byte buffers, as Vulkan buffers can't be empty.
+    let mut segments = Vec::new();
+    let mut segment_aabbs = Vec::new();
+    let mut first_segments = Vec::with_capacity(curve_sets.len());
+
+    for (curves, _) in &curve_sets {
+        first_segments.push(segments.len() as u32);
+        segments.extend_from_slice(&curves.segments);
+        segment_aabbs.extend(curves.aabbs());
+    }
 
-        let mut build_info = vk::AccelerationStructureBuildGeometryInfoKHR::builder()
-            .flags(vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE)
-            .geometries(&geometries)
-            .mode(vk::BuildAccelerationStructureModeKHR::BUILD)
-            .ty(vk::AccelerationStructureTypeKHR::BOTTOM_LEVEL)
-            .build();
+    let segment_buffer = {
+        let mut segment_buffer = BufferResource::new(
+            std::mem::size_of_val(segments.as_slice()).max(1) as vk::DeviceSize,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            &device,
+            device_memory_properties,
+            AllocationCategory::Geometry,
+        );
 
-        let size_info = unsafe {
-            acceleration_structure.get_acceleration_structure_build_sizes(
-                vk::AccelerationStructureBuildTypeKHR::DEVICE,
-                &build_info,
-                &[index_count as u32 / 3],
-            )
-        };
+        segment_buffer.store(&segments, &device);
+        segment_buffer
+    };
 
-        let bottom_as_buffer = BufferResource::new(
-            size_info.acceleration_structure_size,
-            vk::BufferUsageFlags::ACCELERATION_STRUCTURE_STORAGE_KHR
-                | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
-                | vk::BufferUsageFlags::STORAGE_BUFFER,
-            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    let segment_aabb_buffer = {
+        let mut segment_aabb_buffer = BufferResource::new(
+            std::mem::size_of_val(segment_aabbs.as_slice()).max(1) as vk::DeviceSize,
+            vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
+                | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
             &device,
             device_memory_properties,
+            AllocationCategory::Geometry,
         );
 
-        let as_create_info = vk::AccelerationStructureCreateInfoKHR::builder()
-            .ty(build_info.ty)
-            .size(size_info.acceleration_structure_size)
-            .buffer(bottom_as_buffer.buffer)
-            .offset(0)
-            .build();
+        segment_aabb_buffer.store(&segment_aabbs, &device);
+        segment_aabb_buffer
+    };
 
-        let bottom_as =
-            unsafe { acceleration_structure.create_acceleration_structure(&as_create_info, None) }
-                .unwrap();
+    // Likewise for point clouds, with `first_points`.
+    let mut points = Vec::new();
+    let mut point_aabbs = Vec::new();
+    let mut first_points = Vec::with_capacity(point_clouds.len());
 
-        build_info.dst_acceleration_structure = bottom_as;
+    for (point_cloud, _) in &point_clouds {
+        first_points.push(points.len() as u32);
+        points.extend_from_slice(&point_cloud.points);
+        point_aabbs.extend(point_cloud.aabbs());
+    }
 
-        let scratch_buffer = BufferResource::new(
-            size_info.build_scratch_size,
-            vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS | vk::BufferUsageFlags::STORAGE_BUFFER,
-            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    let point_buffer = {
+        let mut point_buffer = BufferResource::new(
+            std::mem::size_of_val(points.as_slice()).max(1) as vk::DeviceSize,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
             &device,
             device_memory_properties,
+            AllocationCategory::Geometry,
         );
 
-        build_info.scratch_data = vk::DeviceOrHostAddressKHR {
-            device_address: unsafe { get_buffer_device_address(&device, scratch_buffer.buffer) },
-        };
-
-        let build_command_buffer = {
-            let allocate_info = vk::CommandBufferAllocateInfo::builder()
-                .command_buffer_count(1)
-                .command_pool(command_pool)
-                .level(vk::CommandBufferLevel::PRIMARY)
-                .build();
+        point_buffer.store(&points, &device);
+        point_buffer
+    };
 
-            let command_buffers =
-                unsafe { device.allocate_command_buffers(&allocate_info) }.unwrap();
-            command_buffers[0]
-        };
+    let point_aabb_buffer = {
+        let mut point_aabb_buffer = BufferResource::new(
+            std::mem::size_of_val(point_aabbs.as_slice()).max(1) as vk::DeviceSize,
+            vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
+                | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            &device,
+            device_memory_properties,
+            AllocationCategory::Geometry,
+        );
 
-        unsafe {
-            device
-                .begin_command_buffer(
-                    build_command_buffer,
-                    &vk::CommandBufferBeginInfo::builder()
-                        .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
-                        .build(),
-                )
-                .unwrap();
+        point_aabb_buffer.store(&point_aabbs, &device);
+        point_aabb_buffer
+    };
 
-            acceleration_structure.cmd_build_acceleration_structures(
-                build_command_buffer,
-                &[build_info],
-                &[&[build_range_info]],
-            );
-            device.end_command_buffer(build_command_buffer).unwrap();
-            device
-                .queue_submit(
-                    graphics_queue,
-                    &[vk::SubmitInfo::builder()
-                        .command_buffers(&[build_command_buffer])
-                        .build()],
-                    vk::Fence::null(),
-                )
-                .expect("queue submit failed.");
+    // Shaders record invariant violations here; see `report_asserts`.
+    let mut assert_buffer = {
+        let words = vec![0u32; 1 + 3 * MAX_ASSERT_RECORDS as usize];
 
-            device.queue_wait_idle(graphics_queue).unwrap();
-            device.free_command_buffers(command_pool, &[build_command_buffer]);
-            scratch_buffer.destroy(&device);
-        }
-        (bottom_as, bottom_as_buffer)
+        let mut assert_buffer = BufferResource::new(
+            std::mem::size_of_val(words.as_slice()) as vk::DeviceSize,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            &device,
+            device_memory_properties,
+            AllocationCategory::Debug,
+        );
+
+        assert_buffer.store(&words, &device);
+        assert_buffer
     };
 
-    let accel_handle = {
-        let as_addr_info = vk::AccelerationStructureDeviceAddressInfoKHR::builder()
-            .acceleration_structure(bottom_as)
-            .build();
-        unsafe { acceleration_structure.get_acceleration_structure_device_address(&as_addr_info) }
+    // Raygen adds up the rays it traces and how they end here.
+    let mut stats_buffer = {
+        let counters = [0u32; STAT_COUNT];
+
+        let mut stats_buffer = BufferResource::new(
+            std::mem::size_of_val(&counters) as vk::DeviceSize,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            &device,
+            device_memory_properties,
+            AllocationCategory::Debug,
+        );
+
+        stats_buffer.store(&counters, &device);
+        stats_buffer
     };
 
-    let (instance_count, instance_buffer) = {
-        let transform_0: [f32; 12] = [1.0, 0.0, 0.0, -1.5, 0.0, 1.0, 0.0, 1.1, 0.0, 0.0, 1.0, 0.0];
+    // Raygen keeps the weighted sum of each pixel's samples here, so later frames can add to it.
+    // Checkpoints are copied out of and back into it.
+    let accumulation_buffer = BufferResource::new(
+        (args.width * args.height) as vk::DeviceSize
+            * std::mem::size_of::<[f32; 4]>() as vk::DeviceSize,
+        vk::BufferUsageFlags::STORAGE_BUFFER
+            | vk::BufferUsageFlags::TRANSFER_SRC
+            | vk::BufferUsageFlags::TRANSFER_DST,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        &device,
+        device_memory_properties,
+        AllocationCategory::Image,
+    );
 
-        let transform_1: [f32; 12] = [1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, -1.1, 0.0, 0.0, 1.0, 0.0];
+    // Create one bottom-level acceleration structure per mesh, then one per curve set and one
+    // per point cloud
+
+    let (bottom_as_list, mut as_stats) = {
+        let vertex_address = unsafe { get_buffer_device_address(&device, vertex_buffer.buffer) };
+        let index_address = unsafe { get_buffer_device_address(&device, index_buffer.buffer) };
+        let segment_aabb_address =
+            unsafe { get_buffer_device_address(&device, segment_aabb_buffer.buffer) };
+        let point_aabb_address =
+            unsafe { get_buffer_device_address(&device, point_aabb_buffer.buffer) };
+        let aabb_stride = std::mem::size_of::<vk::AabbPositionsKHR>();
+        let aabb_geometry = |device_address| {
+            [vk::AccelerationStructureGeometryKHR::builder()
+                .geometry_type(vk::GeometryTypeKHR::AABBS)
+                .geometry(vk::AccelerationStructureGeometryDataKHR {
+                    aabbs: vk::AccelerationStructureGeometryAabbsDataKHR::builder()
+                        .data(vk::DeviceOrHostAddressConstKHR { device_address })
+                        .stride(aabb_stride as u64)
+                        .build(),
+                })
+                .flags(vk::GeometryFlagsKHR::OPAQUE)
+                .build()]
+        };
 
-        let transform_2: [f32; 12] = [1.0, 0.0, 0.0, 1.5, 0.0, 1.0, 0.0, 1.1, 0.0, 0.0, 1.0, 0.0];
+        let geometries = meshes
+            .iter()
+            .zip(&mesh_infos)
+            .map(|(mesh, mesh_info)| {
+                [vk::AccelerationStructureGeometryKHR::builder()
+                    .geometry_type(vk::GeometryTypeKHR::TRIANGLES)
+                    .geometry(vk::AccelerationStructureGeometryDataKHR {
+                        triangles: vk::AccelerationStructureGeometryTrianglesDataKHR::builder()
+                            .vertex_data(vk::DeviceOrHostAddressConstKHR {
+                                device_address: vertex_address
+                                    + (mesh_info.first_vertex as usize * vertex_stride) as u64,
+                            })
+                            // A mesh without vertices still gets a valid, empty BLAS.
+                            .max_vertex((mesh.vertices.len() as u32).saturating_sub(1))
+                            .vertex_stride(vertex_stride as u64)
+                            .vertex_format(vk::Format::R32G32B32_SFLOAT)
+                            .index_data(vk::DeviceOrHostAddressConstKHR {
+                                device_address: index_address
+                                    + (mesh_info.first_index as usize * std::mem::size_of::<u32>())
+                                        as u64,
+                            })
+                            .index_type(vk::IndexType::UINT32)
+                            .build(),
+                    })
+                    .flags(vk::GeometryFlagsKHR::OPAQUE)
+                    .build()]
+            })
+            .chain(first_segments.iter().map(|&first_segment| {
+                aabb_geometry(segment_aabb_address + (first_segment as usize * aabb_stride) as u64)
+            }))
+            .chain(first_points.iter().map(|&first_point| {
+                aabb_geometry(point_aabb_address + (first_point as usize * aabb_stride) as u64)
+            }))
+            .collect::<Vec<_>>();
+
+        // Triangles per mesh BLAS, segments per curve BLAS, points per point cloud BLAS.
+        let primitive_counts = meshes
+            .iter()
+            .map(|mesh| mesh.triangle_count())
+            .chain(curve_sets.iter().map(|(curves, _)| curves.segment_count()))
+            .chain(point_clouds.iter().map(|(points, _)| points.point_count()))
+            .collect::<Vec<_>>();
+        let blas_count = primitive_counts.len();
+
+        let build_range_infos = primitive_counts
+            .iter()
+            .map(|&primitive_count| {
+                [vk::AccelerationStructureBuildRangeInfoKHR::builder()
+                    .first_vertex(0)
+                    .primitive_count(primitive_count as u32)
+                    .primitive_offset(0)
+                    .transform_offset(0)
+                    .build()]
+            })
+            .collect::<Vec<_>>();
 
-        let instances = vec![
-            vk::AccelerationStructureInstanceKHR {
-                transform: vk::TransformMatrixKHR {
-                    matrix: transform_0,
-                },
-                instance_custom_index_and_mask: Packed24_8::new(0, 0xff),
-                instance_shader_binding_table_record_offset_and_flags: Packed24_8::new(
-                    0,
-                    vk::GeometryInstanceFlagsKHR::TRIANGLE_FACING_CULL_DISABLE.as_raw() as u8,
-                ),
-                acceleration_structure_reference: vk::AccelerationStructureReferenceKHR {
-                    device_handle: accel_handle,
-                },
-            },
-            vk::AccelerationStructureInstanceKHR {
-                transform: vk::TransformMatrixKHR {
-                    matrix: transform_1,
-                },
-                instance_custom_index_and_mask: Packed24_8::new(1, 0xff),
-                instance_shader_binding_table_record_offset_and_flags: Packed24_8::new(
-                    0,
-                    vk::GeometryInstanceFlagsKHR::TRIANGLE_FACING_CULL_DISABLE.as_raw() as u8,
-                ),
-                acceleration_structure_reference: vk::AccelerationStructureReferenceKHR {
-                    device_handle: accel_handle,
-                },
-            },
-            vk::AccelerationStructureInstanceKHR {
-                transform: vk::TransformMatrixKHR {
-                    matrix: transform_2,
-                },
-                instance_custom_index_and_mask: Packed24_8::new(2, 0xff),
-                instance_shader_binding_table_record_offset_and_flags: Packed24_8::new(
-                    0,
-                    vk::GeometryInstanceFlagsKHR::TRIANGLE_FACING_CULL_DISABLE.as_raw() as u8,
-                ),
-                acceleration_structure_reference: vk::AccelerationStructureReferenceKHR {
-                    device_handle: accel_handle,
-                },
-            },
-        ];
+        let mut build_infos = Vec::with_capacity(blas_count);
+        let mut bottom_as_list = Vec::with_capacity(blas_count);
+        let mut scratch_buffers = Vec::with_capacity(blas_count);
+        let mut scratch_sizes = Vec::with_capacity(blas_count);
 
-        let instance_buffer_size =
-            std::mem::size_of::<vk::AccelerationStructureInstanceKHR>() * instances.len();
+        for ((geometries, build_range_info), &flags) in
+            geometries.iter().zip(&build_range_infos).zip(&blas_flags)
+        {
+            let mut build_info = vk::AccelerationStructureBuildGeometryInfoKHR::builder()
+                .flags(flags)
+                .geometries(geometries)
+                .mode(vk::BuildAccelerationStructureModeKHR::BUILD)
+                .ty(vk::AccelerationStructureTypeKHR::BOTTOM_LEVEL)
+                .build();
 
-        let mut instance_buffer = BufferResource::new(
-            instance_buffer_size as vk::DeviceSize,
-            vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
-                | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR,
-            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
-            &device,
-            device_memory_properties,
-        );
+            let size_info = unsafe {
+                acceleration_structure.get_acceleration_structure_build_sizes(
+                    vk::AccelerationStructureBuildTypeKHR::DEVICE,
+                    &build_info,
+                    &[build_range_info[0].primitive_count],
+                )
+            };
 
-        instance_buffer.store(&instances, &device);
+            let bottom_as_buffer = BufferResource::new(
+                size_info.acceleration_structure_size,
+                vk::BufferUsageFlags::ACCELERATION_STRUCTURE_STORAGE_KHR
+                    | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
+                    | vk::BufferUsageFlags::STORAGE_BUFFER,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                &device,
+                device_memory_properties,
+                AllocationCategory::AccelerationStructure,
+            );
 
-        (instances.len(), instance_buffer)
-    };
+            let as_create_info = vk::AccelerationStructureCreateInfoKHR::builder()
+                .ty(build_info.ty)
+                .size(size_info.acceleration_structure_size)
+                .buffer(bottom_as_buffer.buffer)
+                .offset(0)
+                .build();
 
-    let (top_as, top_as_buffer) = {
-        let build_range_info = vk::AccelerationStructureBuildRangeInfoKHR::builder()
-            .first_vertex(0)
-            .primitive_count(instance_count as u32)
-            .primitive_offset(0)
-            .transform_offset(0)
-            .build();
+            let bottom_as = unsafe {
+                acceleration_structure.create_acceleration_structure(&as_create_info, None)
+            }
+            .unwrap();
+
+            build_info.dst_acceleration_structure = bottom_as;
+
+            let scratch_buffer = BufferResource::new(
+                size_info.build_scratch_size,
+                vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS | vk::BufferUsageFlags::STORAGE_BUFFER,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                &device,
+                device_memory_properties,
+                AllocationCategory::Scratch,
+            );
+
+            build_info.scratch_data = vk::DeviceOrHostAddressKHR {
+                device_address: unsafe {
+                    get_buffer_device_address(&device, scratch_buffer.buffer)
+                },
+            };
+
+            build_infos.push(build_info);
+            bottom_as_list.push((bottom_as, bottom_as_buffer));
+            scratch_buffers.push(scratch_buffer);
+            scratch_sizes.push(size_info.build_scratch_size);
+        }
 
         let build_command_buffer = {
             let allocate_info = vk::CommandBufferAllocateInfo::builder()
@@ -593,6 +1442,36 @@ fn main() {
             command_buffers[0]
         };
 
+        // BLASes are built one after another with a barrier in between so that the timestamp
+        // written after each build measures that build alone.
+        let timestamp_query_pool = unsafe {
+            device.create_query_pool(
+                &vk::QueryPoolCreateInfo::builder()
+                    .query_type(vk::QueryType::TIMESTAMP)
+                    .query_count(blas_count as u32 + 1)
+                    .build(),
+                None,
+            )
+        }
+        .unwrap();
+
+        let compacted = (0..blas_count)
+            .filter(|&i| {
+                blas_flags[i].contains(vk::BuildAccelerationStructureFlagsKHR::ALLOW_COMPACTION)
+            })
+            .collect::<Vec<_>>();
+
+        let compacted_size_query_pool = unsafe {
+            device.create_query_pool(
+                &vk::QueryPoolCreateInfo::builder()
+                    .query_type(vk::QueryType::ACCELERATION_STRUCTURE_COMPACTED_SIZE_KHR)
+                    .query_count(compacted.len().max(1) as u32)
+                    .build(),
+                None,
+            )
+        }
+        .unwrap();
+
         unsafe {
             device
                 .begin_command_buffer(
@@ -602,93 +1481,73 @@ fn main() {
                         .build(),
                 )
                 .unwrap();
-            let memory_barrier = vk::MemoryBarrier::builder()
-                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
-                .dst_access_mask(vk::AccessFlags::ACCELERATION_STRUCTURE_WRITE_KHR)
-                .build();
-            device.cmd_pipeline_barrier(
+
+            device.cmd_reset_query_pool(
                 build_command_buffer,
-                vk::PipelineStageFlags::TRANSFER,
-                vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
-                vk::DependencyFlags::empty(),
-                &[memory_barrier],
-                &[],
-                &[],
+                timestamp_query_pool,
+                0,
+                blas_count as u32 + 1,
+            );
+            device.cmd_reset_query_pool(
+                build_command_buffer,
+                compacted_size_query_pool,
+                0,
+                compacted.len().max(1) as u32,
+            );
+            device.cmd_write_timestamp(
+                build_command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                timestamp_query_pool,
+                0,
             );
-        }
-
-        let instances = vk::AccelerationStructureGeometryInstancesDataKHR::builder()
-            .array_of_pointers(false)
-            .data(vk::DeviceOrHostAddressConstKHR {
-                device_address: unsafe {
-                    get_buffer_device_address(&device, instance_buffer.buffer)
-                },
-            })
-            .build();
-
-        let geometry = vk::AccelerationStructureGeometryKHR::builder()
-            .geometry_type(vk::GeometryTypeKHR::INSTANCES)
-            .geometry(vk::AccelerationStructureGeometryDataKHR { instances })
-            .build();
-
-        let geometries = [geometry];
-
-        let mut build_info = vk::AccelerationStructureBuildGeometryInfoKHR::builder()
-            .flags(vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE)
-            .geometries(&geometries)
-            .mode(vk::BuildAccelerationStructureModeKHR::BUILD)
-            .ty(vk::AccelerationStructureTypeKHR::TOP_LEVEL)
-            .build();
-
-        let size_info = unsafe {
-            acceleration_structure.get_acceleration_structure_build_sizes(
-                vk::AccelerationStructureBuildTypeKHR::DEVICE,
-                &build_info,
-                &[build_range_info.primitive_count],
-            )
-        };
-
-        let top_as_buffer = BufferResource::new(
-            size_info.acceleration_structure_size,
-            vk::BufferUsageFlags::ACCELERATION_STRUCTURE_STORAGE_KHR
-                | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
-                | vk::BufferUsageFlags::STORAGE_BUFFER,
-            vk::MemoryPropertyFlags::DEVICE_LOCAL,
-            &device,
-            device_memory_properties,
-        );
-
-        let as_create_info = vk::AccelerationStructureCreateInfoKHR::builder()
-            .ty(build_info.ty)
-            .size(size_info.acceleration_structure_size)
-            .buffer(top_as_buffer.buffer)
-            .offset(0)
-            .build();
 
-        let top_as =
-            unsafe { acceleration_structure.create_acceleration_structure(&as_create_info, None) }
-                .unwrap();
+            for (i, (build_info, build_range_info)) in
+                build_infos.iter().zip(&build_range_infos).enumerate()
+            {
+                acceleration_structure.cmd_build_acceleration_structures(
+                    build_command_buffer,
+                    &[*build_info],
+                    &[&build_range_info[..]],
+                );
 
-        build_info.dst_acceleration_structure = top_as;
+                let memory_barrier = vk::MemoryBarrier::builder()
+                    .src_access_mask(vk::AccessFlags::ACCELERATION_STRUCTURE_WRITE_KHR)
+                    .dst_access_mask(
+                        vk::AccessFlags::ACCELERATION_STRUCTURE_READ_KHR
+                            | vk::AccessFlags::ACCELERATION_STRUCTURE_WRITE_KHR,
+                    )
+                    .build();
+                device.cmd_pipeline_barrier(
+                    build_command_buffer,
+                    vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
+                    vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
+                    vk::DependencyFlags::empty(),
+                    &[memory_barrier],
+                    &[],
+                    &[],
+                );
 
-        let scratch_buffer = BufferResource::new(
-            size_info.build_scratch_size,
-            vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS | vk::BufferUsageFlags::STORAGE_BUFFER,
-            vk::MemoryPropertyFlags::DEVICE_LOCAL,
-            &device,
-            device_memory_properties,
-        );
+                device.cmd_write_timestamp(
+                    build_command_buffer,
+                    vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
+                    timestamp_query_pool,
+                    i as u32 + 1,
+                );
+            }
 
-        build_info.scratch_data = vk::DeviceOrHostAddressKHR {
-            device_address: unsafe { get_buffer_device_address(&device, scratch_buffer.buffer) },
-        };
+            if !compacted.is_empty() {
+                acceleration_structure.cmd_write_acceleration_structures_properties(
+                    build_command_buffer,
+                    &compacted
+                        .iter()
+                        .map(|&i| bottom_as_list[i].0)
+                        .collect::<Vec<_>>(),
+                    vk::QueryType::ACCELERATION_STRUCTURE_COMPACTED_SIZE_KHR,
+                    compacted_size_query_pool,
+                    0,
+                );
+            }
 
-        unsafe {
-            acceleration_structure.cmd_build_acceleration_structures(
-                build_command_buffer,
-                &[build_info],
-                &[&[build_range_info]],
-            );
             device.end_command_buffer(build_command_buffer).unwrap();
             device
                 .queue_submit(
@@ -702,18 +1561,315 @@ fn main() {
 
             device.queue_wait_idle(graphics_queue).unwrap();
             device.free_command_buffers(command_pool, &[build_command_buffer]);
-            scratch_buffer.destroy(&device);
+
+            for scratch_buffer in scratch_buffers {
+                scratch_buffer.destroy(&device);
+            }
+        }
+
+        let mut timestamps = vec![0u64; blas_count + 1];
+        unsafe {
+            device.get_query_pool_results(
+                timestamp_query_pool,
+                0,
+                timestamps.len() as u32,
+                &mut timestamps,
+                vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WAIT,
+            )
+        }
+        .unwrap();
+
+        let mut compacted_sizes = vec![0u64; compacted.len()];
+        if !compacted.is_empty() {
+            unsafe {
+                device.get_query_pool_results(
+                    compacted_size_query_pool,
+                    0,
+                    compacted_sizes.len() as u32,
+                    &mut compacted_sizes,
+                    vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WAIT,
+                )
+            }
+            .unwrap();
+        }
+
+        unsafe {
+            device.destroy_query_pool(timestamp_query_pool, None);
+            device.destroy_query_pool(compacted_size_query_pool, None);
+        }
+
+        // Copy every BLAS that allows compaction into a buffer of its compacted size.
+
+        let mut built_sizes = bottom_as_list
+            .iter()
+            .map(|(_, buffer)| (buffer.size, buffer.size))
+            .collect::<Vec<_>>();
+
+        if !compacted.is_empty() {
+            let compact_command_buffer = {
+                let allocate_info = vk::CommandBufferAllocateInfo::builder()
+                    .command_buffer_count(1)
+                    .command_pool(command_pool)
+                    .level(vk::CommandBufferLevel::PRIMARY)
+                    .build();
+
+                let command_buffers =
+                    unsafe { device.allocate_command_buffers(&allocate_info) }.unwrap();
+                command_buffers[0]
+            };
+
+            unsafe {
+                device
+                    .begin_command_buffer(
+                        compact_command_buffer,
+                        &vk::CommandBufferBeginInfo::builder()
+                            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+                            .build(),
+                    )
+                    .unwrap();
+            }
+
+            let mut replaced = Vec::with_capacity(compacted.len());
+
+            for (&i, &compacted_size) in compacted.iter().zip(&compacted_sizes) {
+                let compact_as_buffer = BufferResource::new(
+                    compacted_size,
+                    vk::BufferUsageFlags::ACCELERATION_STRUCTURE_STORAGE_KHR
+                        | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
+                        | vk::BufferUsageFlags::STORAGE_BUFFER,
+                    vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                    &device,
+                    device_memory_properties,
+                    AllocationCategory::AccelerationStructure,
+                );
+
+                let as_create_info = vk::AccelerationStructureCreateInfoKHR::builder()
+                    .ty(vk::AccelerationStructureTypeKHR::BOTTOM_LEVEL)
+                    .size(compacted_size)
+                    .buffer(compact_as_buffer.buffer)
+                    .offset(0)
+                    .build();
+
+                let compact_as = unsafe {
+                    acceleration_structure.create_acceleration_structure(&as_create_info, None)
+                }
+                .unwrap();
+
+                unsafe {
+                    acceleration_structure.cmd_copy_acceleration_structure(
+                        compact_command_buffer,
+                        &vk::CopyAccelerationStructureInfoKHR::builder()
+                            .src(bottom_as_list[i].0)
+                            .dst(compact_as)
+                            .mode(vk::CopyAccelerationStructureModeKHR::COMPACT)
+                            .build(),
+                    );
+                }
+
+                built_sizes[i].1 = compacted_size;
+                replaced.push(std::mem::replace(
+                    &mut bottom_as_list[i],
+                    (compact_as, compact_as_buffer),
+                ));
+            }
+
+            unsafe {
+                device.end_command_buffer(compact_command_buffer).unwrap();
+                device
+                    .queue_submit(
+                        graphics_queue,
+                        &[vk::SubmitInfo::builder()
+                            .command_buffers(&[compact_command_buffer])
+                            .build()],
+                        vk::Fence::null(),
+                    )
+                    .expect("queue submit failed.");
+
+                device.queue_wait_idle(graphics_queue).unwrap();
+                device.free_command_buffers(command_pool, &[compact_command_buffer]);
+
+                for (bottom_as, bottom_as_buffer) in replaced {
+                    acceleration_structure.destroy_acceleration_structure(bottom_as, None);
+                    bottom_as_buffer.destroy(&device);
+                }
+            }
+        }
+
+        let timestamp_period = unsafe { instance.get_physical_device_properties(physical_device) }
+            .limits
+            .timestamp_period as f64;
+
+        for (i, (primitive_count, flags)) in primitive_counts.iter().zip(&blas_flags).enumerate() {
+            let build_ms =
+                (timestamps[i + 1] - timestamps[i]) as f64 * timestamp_period / 1_000_000.0;
+            let (built_size, final_size) = built_sizes[i];
+            println!(
+                "BLAS {}: {} {}, {:?}, built in {:.3} ms, {} KiB -> {} KiB",
+                i,
+                primitive_count,
+                if i < meshes.len() {
+                    "triangles"
+                } else if i < meshes.len() + curve_sets.len() {
+                    "curve segments"
+                } else {
+                    "points"
+                },
+                flags,
+                build_ms,
+                built_size / 1024,
+                final_size / 1024,
+            );
         }
 
-        (top_as, top_as_buffer)
+        let blas_stats = primitive_counts
+            .iter()
+            .zip(&built_sizes)
+            .zip(&scratch_sizes)
+            .enumerate()
+            .map(
+                |(i, ((&primitive_count, &(built_size, final_size)), &scratch_size))| {
+                    AccelerationStructureStats {
+                        name: format!("BLAS {}", i),
+                        primitive_count: primitive_count as u32,
+                        built_size,
+                        final_size,
+                        scratch_size,
+                    }
+                },
+            )
+            .collect::<Vec<_>>();
+
+        (bottom_as_list, blas_stats)
+    };
+
+    let accel_handles = bottom_as_list
+        .iter()
+        .map(|(bottom_as, _)| {
+            let as_addr_info = vk::AccelerationStructureDeviceAddressInfoKHR::builder()
+                .acceleration_structure(*bottom_as)
+                .build();
+            unsafe {
+                acceleration_structure.get_acceleration_structure_device_address(&as_addr_info)
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let render_origin = if CAMERA_RELATIVE {
+        camera.origin
+    } else {
+        Vec3::ZERO
+    };
+    let render_camera = Camera {
+        origin: camera.origin - render_origin,
+        look_at: camera.look_at - render_origin,
+        ..camera
+    };
+
+    let instances = scene_instances
+        .iter()
+        .map(|&(shape, transform, _, visibility)| {
+            // The custom index tells closest hit which mesh's vertices to read, or the curve and
+            // point shaders where the curve set's segments or the cloud's points start. The record
+            // offset picks the hit group: triangles, curves, then points.
+            let (custom_index, hit_group, blas_index) = match shape {
+                Shape::Mesh(mesh_index) => (mesh_index, 0, mesh_index as usize),
+                Shape::Curves(curves_index) => (
+                    first_segments[curves_index as usize],
+                    1,
+                    meshes.len() + curves_index as usize,
+                ),
+                Shape::Points(points_index) => (
+                    first_points[points_index as usize],
+                    2,
+                    meshes.len() + curve_sets.len() + points_index as usize,
+                ),
+            };
+
+            let transform = Affine3A::from_translation(-render_origin) * transform;
+
+            // The mask selects which kinds of rays can hit the instance.
+            InstanceBuilder::new(accel_handles[blas_index])
+                .transform(transform)
+                .custom_index(custom_index)
+                .mask(visibility)
+                .sbt_record_offset(hit_group)
+                .flags(vk::GeometryInstanceFlagsKHR::TRIANGLE_FACING_CULL_DISABLE)
+                .build()
+        })
+        .collect::<Vec<_>>();
+
+    assert!(
+        instances.len() as u64 <= as_properties.max_instance_count,
+        "{} instances exceed maxInstanceCount {}.",
+        instances.len(),
+        as_properties.max_instance_count,
+    );
+
+    let (top_as, top_as_buffer, instance_buffer, top_as_stats) = build_top_level_as(
+        &device,
+        &acceleration_structure,
+        command_pool,
+        graphics_queue,
+        device_memory_properties,
+        &instances,
+    );
+
+    // Primary rays can only hit instances inside the view frustum, so they get a slimmer TLAS.
+    // Secondary rays keep tracing against the full one.
+    let primary_top_as = if args.primary_ray_culling {
+        let primary_instances = instances
+            .iter()
+            .zip(&scene_instances)
+            .filter(|(_, &(shape, transform, _, _))| {
+                let (min, max) = match shape {
+                    Shape::Mesh(mesh_index) => meshes[mesh_index as usize].bounds(),
+                    Shape::Curves(curves_index) => curve_sets[curves_index as usize].0.bounds(),
+                    Shape::Points(points_index) => point_clouds[points_index as usize].0.bounds(),
+                };
+                let (min, max) = transform_bounds(transform, min, max);
+                camera.sees_box(args.width as f32 / args.height as f32, min, max)
+            })
+            .map(|(instance, _)| *instance)
+            .collect::<Vec<_>>();
+
+        println!(
+            "Primary ray TLAS: {} of {} instances in view",
+            primary_instances.len(),
+            instances.len()
+        );
+
+        Some(build_top_level_as(
+            &device,
+            &acceleration_structure,
+            command_pool,
+            graphics_queue,
+            device_memory_properties,
+            &primary_instances,
+        ))
+    } else {
+        None
     };
 
+    as_stats.push(AccelerationStructureStats {
+        name: "TLAS".to_owned(),
+        ..top_as_stats
+    });
+    if let Some((_, _, _, primary_top_as_stats)) = &primary_top_as {
+        as_stats.push(AccelerationStructureStats {
+            name: "primary TLAS".to_owned(),
+            ..primary_top_as_stats.clone()
+        });
+    }
+    as_stats::print_table(&as_stats);
+
+    const SHADER: &[u8] = include_bytes!(env!("ash_raytracing_example_shader.spv"));
+
+    // The frame uniforms at binding 6 are bound at a different offset for every tile.
+    let descriptor_bindings = reflect::descriptor_set_layout_bindings(SHADER, 0, &[6]);
+
     let (descriptor_set_layout, graphics_pipeline, pipeline_layout, shader_group_count) = {
-        let binding_flags_inner = [
-            vk::DescriptorBindingFlagsEXT::empty(),
-            vk::DescriptorBindingFlagsEXT::empty(),
-            vk::DescriptorBindingFlagsEXT::empty(),
-        ];
+        let binding_flags_inner =
+            vec![vk::DescriptorBindingFlagsEXT::empty(); descriptor_bindings.len()];
 
         let mut binding_flags = vk::DescriptorSetLayoutBindingFlagsCreateInfoEXT::builder()
             .binding_flags(&binding_flags_inner)
@@ -722,26 +1878,7 @@ fn main() {
         let descriptor_set_layout = unsafe {
             device.create_descriptor_set_layout(
                 &vk::DescriptorSetLayoutCreateInfo::builder()
-                    .bindings(&[
-                        vk::DescriptorSetLayoutBinding::builder()
-                            .descriptor_count(1)
-                            .descriptor_type(vk::DescriptorType::ACCELERATION_STRUCTURE_KHR)
-                            .stage_flags(vk::ShaderStageFlags::RAYGEN_KHR)
-                            .binding(0)
-                            .build(),
-                        vk::DescriptorSetLayoutBinding::builder()
-                            .descriptor_count(1)
-                            .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
-                            .stage_flags(vk::ShaderStageFlags::RAYGEN_KHR)
-                            .binding(1)
-                            .build(),
-                        vk::DescriptorSetLayoutBinding::builder()
-                            .descriptor_count(1)
-                            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
-                            .stage_flags(vk::ShaderStageFlags::CLOSEST_HIT_KHR)
-                            .binding(2)
-                            .build(),
-                    ])
+                    .bindings(&descriptor_bindings)
                     .push_next(&mut binding_flags)
                     .build(),
                 None,
@@ -749,8 +1886,6 @@ fn main() {
         }
         .unwrap();
 
-        const SHADER: &[u8] = include_bytes!(env!("ash_raytracing_example_shader.spv"));
-
         let shader_module = unsafe { create_shader_module(&device, SHADER).unwrap() };
 
         let layouts = vec![descriptor_set_layout];
@@ -776,7 +1911,23 @@ fn main() {
                 .any_hit_shader(vk::SHADER_UNUSED_KHR)
                 .intersection_shader(vk::SHADER_UNUSED_KHR)
                 .build(),
-            // group2 = [ miss ]
+            // group2 = [ curve intersection, curve chit ]
+            vk::RayTracingShaderGroupCreateInfoKHR::builder()
+                .ty(vk::RayTracingShaderGroupTypeKHR::PROCEDURAL_HIT_GROUP)
+                .general_shader(vk::SHADER_UNUSED_KHR)
+                .closest_hit_shader(4)
+                .any_hit_shader(vk::SHADER_UNUSED_KHR)
+                .intersection_shader(3)
+                .build(),
+            // group3 = [ point intersection, point chit ]
+            vk::RayTracingShaderGroupCreateInfoKHR::builder()
+                .ty(vk::RayTracingShaderGroupTypeKHR::PROCEDURAL_HIT_GROUP)
+                .general_shader(vk::SHADER_UNUSED_KHR)
+                .closest_hit_shader(6)
+                .any_hit_shader(vk::SHADER_UNUSED_KHR)
+                .intersection_shader(5)
+                .build(),
+            // group4 = [ miss ]
             vk::RayTracingShaderGroupCreateInfoKHR::builder()
                 .ty(vk::RayTracingShaderGroupTypeKHR::GENERAL)
                 .general_shader(2)
@@ -802,6 +1953,26 @@ fn main() {
                 .module(shader_module)
                 .name(std::ffi::CStr::from_bytes_with_nul(b"main_miss\0").unwrap())
                 .build(),
+            vk::PipelineShaderStageCreateInfo::builder()
+                .stage(vk::ShaderStageFlags::INTERSECTION_KHR)
+                .module(shader_module)
+                .name(std::ffi::CStr::from_bytes_with_nul(b"curve_intersection\0").unwrap())
+                .build(),
+            vk::PipelineShaderStageCreateInfo::builder()
+                .stage(vk::ShaderStageFlags::CLOSEST_HIT_KHR)
+                .module(shader_module)
+                .name(std::ffi::CStr::from_bytes_with_nul(b"curve_closest_hit\0").unwrap())
+                .build(),
+            vk::PipelineShaderStageCreateInfo::builder()
+                .stage(vk::ShaderStageFlags::INTERSECTION_KHR)
+                .module(shader_module)
+                .name(std::ffi::CStr::from_bytes_with_nul(b"point_intersection\0").unwrap())
+                .build(),
+            vk::PipelineShaderStageCreateInfo::builder()
+                .stage(vk::ShaderStageFlags::CLOSEST_HIT_KHR)
+                .module(shader_module)
+                .name(std::ffi::CStr::from_bytes_with_nul(b"point_closest_hit\0").unwrap())
+                .build(),
         ];
 
         let pipeline = unsafe {
@@ -811,7 +1982,7 @@ fn main() {
                 &[vk::RayTracingPipelineCreateInfoKHR::builder()
                     .stages(&shader_stages)
                     .groups(&shader_groups)
-                    .max_pipeline_ray_recursion_depth(1)
+                    .max_pipeline_ray_recursion_depth(MAX_RAY_RECURSION_DEPTH)
                     .layout(pipeline_layout)
                     .build()],
                 None,
@@ -831,31 +2002,18 @@ fn main() {
         )
     };
 
-    let command_buffer = {
-        let command_buffer_allocate_info = vk::CommandBufferAllocateInfo::builder()
-            .command_buffer_count(1)
-            .command_pool(command_pool)
-            .level(vk::CommandBufferLevel::PRIMARY)
-            .build();
-
-        unsafe { device.allocate_command_buffers(&command_buffer_allocate_info) }
-            .expect("Failed to allocate Command Buffers!")[0]
-    };
-
-    {
-        let command_buffer_begin_info = vk::CommandBufferBeginInfo::builder()
-            .flags(vk::CommandBufferUsageFlags::SIMULTANEOUS_USE)
-            .build();
-
-        unsafe { device.begin_command_buffer(command_buffer, &command_buffer_begin_info) }
-            .expect("Failed to begin recording Command Buffer at beginning!");
-    }
-
     let handle_size_aligned = aligned_size(
         rt_pipeline_properties.shader_group_handle_size,
         rt_pipeline_properties.shader_group_base_alignment,
     ) as u64;
 
+    assert!(
+        handle_size_aligned <= rt_pipeline_properties.max_shader_group_stride as u64,
+        "shader binding table stride {} exceeds maxShaderGroupStride {}.",
+        handle_size_aligned,
+        rt_pipeline_properties.max_shader_group_stride,
+    );
+
     let shader_binding_table_buffer = {
         let incoming_table_data = unsafe {
             rt_pipeline.get_ray_tracing_shader_group_handles(
@@ -890,6 +2048,7 @@ fn main() {
             vk::MemoryPropertyFlags::HOST_VISIBLE,
             &device,
             device_memory_properties,
+            AllocationCategory::ShaderBindingTable,
         );
 
         shader_binding_table_buffer.store(&table_data, &device);
@@ -898,9 +2057,13 @@ fn main() {
     };
 
     let color_buffer = {
-        let color: [f32; 12] = [1.0, 0.0, 0.0, 1.0, 0.0, 1.0, 0.0, 1.0, 0.0, 0.0, 1.0, 1.0];
+        // Colors are read as `Vec3` in the shader, which has a 16-byte stride.
+        let color = scene_instances
+            .iter()
+            .map(|&(_, _, [r, g, b], _)| [r, g, b, 1.0])
+            .collect::<Vec<[f32; 4]>>();
 
-        let buffer_size = (std::mem::size_of::<f32>() * 12) as vk::DeviceSize;
+        let buffer_size = std::mem::size_of_val(color.as_slice()) as vk::DeviceSize;
 
         let mut color_buffer = BufferResource::new(
             buffer_size,
@@ -908,33 +2071,76 @@ fn main() {
             vk::MemoryPropertyFlags::HOST_VISIBLE,
             &device,
             device_memory_properties,
+            AllocationCategory::Geometry,
         );
         color_buffer.store(&color, &device);
 
         color_buffer
     };
 
-    let descriptor_sizes = [
-        vk::DescriptorPoolSize {
-            ty: vk::DescriptorType::ACCELERATION_STRUCTURE_KHR,
-            descriptor_count: 1,
-        },
-        vk::DescriptorPoolSize {
-            ty: vk::DescriptorType::STORAGE_IMAGE,
-            descriptor_count: 1,
-        },
-        vk::DescriptorPoolSize {
-            ty: vk::DescriptorType::STORAGE_BUFFER,
-            descriptor_count: 1,
-        },
-    ];
+    let physical_device_limits =
+        unsafe { instance.get_physical_device_properties(physical_device) }.limits;
 
-    let descriptor_pool_info = vk::DescriptorPoolCreateInfo::builder()
-        .pool_sizes(&descriptor_sizes)
-        .max_sets(1);
+    // Split the image into as many dispatches as the device's launch limits require.
+    let max_dispatch_invocations = if args.low_latency {
+        rt_pipeline_properties
+            .max_ray_dispatch_invocation_count
+            .min(LOW_LATENCY_DISPATCH_INVOCATIONS)
+    } else {
+        rt_pipeline_properties.max_ray_dispatch_invocation_count
+    };
 
-    let descriptor_pool =
-        unsafe { device.create_descriptor_pool(&descriptor_pool_info, None) }.unwrap();
+    let Crop {
+        x: crop_x,
+        y: crop_y,
+        width: crop_width,
+        height: crop_height,
+    } = args.crop.unwrap_or(Crop {
+        x: 0,
+        y: 0,
+        width: args.width,
+        height: args.height,
+    });
+
+    let tiles = dispatch_tiles(
+        crop_width,
+        crop_height,
+        max_dispatch_invocations,
+        [0, 1].map(|i| {
+            physical_device_limits.max_compute_work_group_count[i]
+                .saturating_mul(physical_device_limits.max_compute_work_group_size[i])
+        }),
+    )
+    .into_iter()
+    .map(|(x, y, width, height)| (x + crop_x, y + crop_y, width, height))
+    .collect::<Vec<_>>();
+
+    if tiles.len() > 1 {
+        println!(
+            "Rendering {}x{} in {} tiles.",
+            crop_width,
+            crop_height,
+            tiles.len()
+        );
+    }
+
+    // Every tile of a frame is recorded into the same command buffer, so each needs its own
+    // uniform slot.
+    let mut uniform_ring = UniformRing::<FrameUniforms>::new(
+        UNIFORM_RING_SIZE.max(tiles.len() as u64),
+        physical_device_limits.min_uniform_buffer_offset_alignment,
+        &device,
+        device_memory_properties,
+    );
+
+    let descriptor_sizes = reflect::descriptor_pool_sizes(&descriptor_bindings);
+
+    let descriptor_pool_info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(&descriptor_sizes)
+        .max_sets(1);
+
+    let descriptor_pool =
+        unsafe { device.create_descriptor_pool(&descriptor_pool_info, None) }.unwrap();
 
     let mut count_allocate_info = vk::DescriptorSetVariableDescriptorCountAllocateInfo::builder()
         .descriptor_counts(&[1])
@@ -967,6 +2173,23 @@ fn main() {
     // This is only set by the builder for images, buffers, or views; need to set explicitly after
     accel_write.descriptor_count = 1;
 
+    let primary_accel_structs = [primary_top_as
+        .as_ref()
+        .map_or(top_as, |(primary_top_as, _, _, _)| *primary_top_as)];
+    let mut primary_accel_info = vk::WriteDescriptorSetAccelerationStructureKHR::builder()
+        .acceleration_structures(&primary_accel_structs)
+        .build();
+
+    let mut primary_accel_write = vk::WriteDescriptorSet::builder()
+        .dst_set(descriptor_set)
+        .dst_binding(8)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::ACCELERATION_STRUCTURE_KHR)
+        .push_next(&mut primary_accel_info)
+        .build();
+
+    primary_accel_write.descriptor_count = 1;
+
     let image_info = [vk::DescriptorImageInfo::builder()
         .image_layout(vk::ImageLayout::GENERAL)
         .image_view(image_view)
@@ -993,90 +2216,313 @@ fn main() {
         .buffer_info(&buffer_info)
         .build();
 
+    let vertex_buffer_info = [vk::DescriptorBufferInfo::builder()
+        .buffer(vertex_buffer.buffer)
+        .range(vk::WHOLE_SIZE)
+        .build()];
+
+    let vertex_buffer_write = vk::WriteDescriptorSet::builder()
+        .dst_set(descriptor_set)
+        .dst_binding(4)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .buffer_info(&vertex_buffer_info)
+        .build();
+
+    let index_buffer_info = [vk::DescriptorBufferInfo::builder()
+        .buffer(index_buffer.buffer)
+        .range(vk::WHOLE_SIZE)
+        .build()];
+
+    let index_buffer_write = vk::WriteDescriptorSet::builder()
+        .dst_set(descriptor_set)
+        .dst_binding(5)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .buffer_info(&index_buffer_info)
+        .build();
+
+    let mesh_info_buffer_info = [vk::DescriptorBufferInfo::builder()
+        .buffer(mesh_info_buffer.buffer)
+        .range(vk::WHOLE_SIZE)
+        .build()];
+
+    let mesh_info_buffer_write = vk::WriteDescriptorSet::builder()
+        .dst_set(descriptor_set)
+        .dst_binding(7)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .buffer_info(&mesh_info_buffer_info)
+        .build();
+
+    let assert_buffer_info = [vk::DescriptorBufferInfo::builder()
+        .buffer(assert_buffer.buffer)
+        .range(vk::WHOLE_SIZE)
+        .build()];
+
+    let assert_buffer_write = vk::WriteDescriptorSet::builder()
+        .dst_set(descriptor_set)
+        .dst_binding(10)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .buffer_info(&assert_buffer_info)
+        .build();
+
+    let stats_buffer_info = [vk::DescriptorBufferInfo::builder()
+        .buffer(stats_buffer.buffer)
+        .range(vk::WHOLE_SIZE)
+        .build()];
+
+    let stats_buffer_write = vk::WriteDescriptorSet::builder()
+        .dst_set(descriptor_set)
+        .dst_binding(11)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .buffer_info(&stats_buffer_info)
+        .build();
+
+    let segment_buffer_info = [vk::DescriptorBufferInfo::builder()
+        .buffer(segment_buffer.buffer)
+        .range(vk::WHOLE_SIZE)
+        .build()];
+
+    let segment_buffer_write = vk::WriteDescriptorSet::builder()
+        .dst_set(descriptor_set)
+        .dst_binding(12)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .buffer_info(&segment_buffer_info)
+        .build();
+
+    let point_buffer_info = [vk::DescriptorBufferInfo::builder()
+        .buffer(point_buffer.buffer)
+        .range(vk::WHOLE_SIZE)
+        .build()];
+
+    let point_buffer_write = vk::WriteDescriptorSet::builder()
+        .dst_set(descriptor_set)
+        .dst_binding(13)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .buffer_info(&point_buffer_info)
+        .build();
+
+    let accumulation_buffer_info = [vk::DescriptorBufferInfo::builder()
+        .buffer(accumulation_buffer.buffer)
+        .range(vk::WHOLE_SIZE)
+        .build()];
+
+    let accumulation_buffer_write = vk::WriteDescriptorSet::builder()
+        .dst_set(descriptor_set)
+        .dst_binding(14)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .buffer_info(&accumulation_buffer_info)
+        .build();
+
+    let uniform_buffer_info = [vk::DescriptorBufferInfo::builder()
+        .buffer(uniform_ring.buffer.buffer)
+        .range(std::mem::size_of::<FrameUniforms>() as vk::DeviceSize)
+        .build()];
+
+    let uniform_buffer_write = vk::WriteDescriptorSet::builder()
+        .dst_set(descriptor_set)
+        .dst_binding(6)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
+        .buffer_info(&uniform_buffer_info)
+        .build();
+
+    let skybox_info = [vk::DescriptorImageInfo::builder()
+        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .image_view(skybox_image_view)
+        .sampler(skybox_sampler)
+        .build()];
+
+    let skybox_write = vk::WriteDescriptorSet::builder()
+        .dst_set(descriptor_set)
+        .dst_binding(3)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .image_info(&skybox_info)
+        .build();
+
+    let backplate_info = [vk::DescriptorImageInfo::builder()
+        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .image_view(backplate_image_view)
+        .sampler(skybox_sampler)
+        .build()];
+
+    let backplate_write = vk::WriteDescriptorSet::builder()
+        .dst_set(descriptor_set)
+        .dst_binding(9)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .image_info(&backplate_info)
+        .build();
+
     unsafe {
-        device.update_descriptor_sets(&[accel_write, image_write, buffers_write], &[]);
+        device.update_descriptor_sets(
+            &[
+                accel_write,
+                image_write,
+                buffers_write,
+                skybox_write,
+                vertex_buffer_write,
+                index_buffer_write,
+                uniform_buffer_write,
+                mesh_info_buffer_write,
+                primary_accel_write,
+                backplate_write,
+                assert_buffer_write,
+                stats_buffer_write,
+                segment_buffer_write,
+                point_buffer_write,
+                accumulation_buffer_write,
+            ],
+            &[],
+        );
     }
 
-    {
-        // |[ raygen shader ]|[ hit shader  ]|[ miss shader ]|
-        // |                 |               |               |
-        // | 0               | 1             | 2             | 3
+    allocations::print_report(&instance, physical_device, memory_budget_enabled);
 
-        let sbt_address =
-            unsafe { get_buffer_device_address(&device, shader_binding_table_buffer.buffer) };
+    let render_query_pool = unsafe {
+        device.create_query_pool(
+            &vk::QueryPoolCreateInfo::builder()
+                .query_type(vk::QueryType::TIMESTAMP)
+                .query_count(2)
+                .build(),
+            None,
+        )
+    }
+    .unwrap();
 
-        let sbt_raygen_region = vk::StridedDeviceAddressRegionKHR::builder()
-            .device_address(sbt_address)
-            .size(handle_size_aligned)
-            .stride(handle_size_aligned)
-            .build();
+    // |[ raygen shader ]|[ triangle hit ]|[ curve hit ]|[ point hit ]|[ miss shader ]|
+    // |                 |                |             |             |               |
+    // | 0               | 1              | 2           | 3           | 4             | 5
 
-        let sbt_miss_region = vk::StridedDeviceAddressRegionKHR::builder()
-            .device_address(sbt_address + 2 * handle_size_aligned)
-            .size(handle_size_aligned)
-            .stride(handle_size_aligned)
-            .build();
+    let sbt_address =
+        unsafe { get_buffer_device_address(&device, shader_binding_table_buffer.buffer) };
 
-        let sbt_hit_region = vk::StridedDeviceAddressRegionKHR::builder()
-            .device_address(sbt_address + handle_size_aligned)
-            .size(handle_size_aligned)
-            .stride(handle_size_aligned)
-            .build();
+    let sbt_raygen_region = vk::StridedDeviceAddressRegionKHR::builder()
+        .device_address(sbt_address)
+        .size(handle_size_aligned)
+        .stride(handle_size_aligned)
+        .build();
+
+    let sbt_miss_region = vk::StridedDeviceAddressRegionKHR::builder()
+        .device_address(sbt_address + 4 * handle_size_aligned)
+        .size(handle_size_aligned)
+        .stride(handle_size_aligned)
+        .build();
+
+    let sbt_hit_region = vk::StridedDeviceAddressRegionKHR::builder()
+        .device_address(sbt_address + handle_size_aligned)
+        .size(3 * handle_size_aligned)
+        .stride(handle_size_aligned)
+        .build();
+
+    let sbt_call_region = vk::StridedDeviceAddressRegionKHR::default();
+
+    let [camera_origin, camera_horizontal, camera_vertical, camera_forward] =
+        render_camera.basis(args.width as f32 / args.height as f32);
+
+    let frame_uniforms =
+        |frame_index: u32, spp: u32, tile_offset_x: u32, tile_offset_y: u32| FrameUniforms {
+            camera_origin,
+            camera_horizontal,
+            camera_vertical,
+            camera_forward,
+            frame_index,
+            spp,
+            integrator: args.integrator as u32,
+            sky_intensity: SKY_INTENSITY,
+            exposure: EXPOSURE.exp2(),
+            image_width: args.width,
+            image_height: args.height,
+            tile_offset_x,
+            tile_offset_y,
+            backplate: backplate_enabled as u32,
+            pixel_filter: args.pixel_filter as u32,
+            filter_radius: args.filter_radius,
+            output_color_space: output_color_space as u32,
+            ray_epsilon: args.ray_epsilon.unwrap_or(0.0),
+            max_bounces: args.max_bounces,
+            _padding: 0,
+        };
 
-        let sbt_call_region = vk::StridedDeviceAddressRegionKHR::default();
+    // Binds the pipeline and traces one `tile_width` x `tile_height` tile with the uniforms at
+    // `uniform_offset`.
+    let cmd_trace_tile = |command_buffer, uniform_offset, tile_width, tile_height| unsafe {
+        device.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::RAY_TRACING_KHR,
+            graphics_pipeline,
+        );
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::RAY_TRACING_KHR,
+            pipeline_layout,
+            0,
+            &[descriptor_set],
+            &[uniform_offset],
+        );
+        rt_pipeline.cmd_trace_rays(
+            command_buffer,
+            &sbt_raygen_region,
+            &sbt_miss_region,
+            &sbt_hit_region,
+            &sbt_call_region,
+            tile_width,
+            tile_height,
+            1,
+        );
+    };
 
+    // Records one accumulation pass over `tiles`, tracing `spp` samples per pixel as frame
+    // `frame_index`.
+    let cmd_trace_pass = |command_buffer,
+                          uniform_ring: &mut UniformRing<FrameUniforms>,
+                          frame_index,
+                          spp,
+                          tiles: &[(u32, u32, u32, u32)]| {
+        // Raygen reads the samples the last pass accumulated, and a blit or read back of the
+        // image has to be done with it before it is overwritten.
+        let accumulation_barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE)
+            .build();
         unsafe {
-            device.cmd_bind_pipeline(
-                command_buffer,
-                vk::PipelineBindPoint::RAY_TRACING_KHR,
-                graphics_pipeline,
-            );
-            device.cmd_bind_descriptor_sets(
+            device.cmd_pipeline_barrier(
                 command_buffer,
-                vk::PipelineBindPoint::RAY_TRACING_KHR,
-                pipeline_layout,
-                0,
-                &[descriptor_set],
+                vk::PipelineStageFlags::RAY_TRACING_SHADER_KHR | vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::RAY_TRACING_SHADER_KHR,
+                vk::DependencyFlags::empty(),
+                &[accumulation_barrier],
+                &[],
                 &[],
             );
-            rt_pipeline.cmd_trace_rays(
-                command_buffer,
-                &sbt_raygen_region,
-                &sbt_miss_region,
-                &sbt_hit_region,
-                &sbt_call_region,
-                WIDTH,
-                HEIGHT,
-                1,
-            );
-            device.end_command_buffer(command_buffer).unwrap();
         }
-    }
-
-    {
-        let submit_infos = [vk::SubmitInfo::builder()
-            .command_buffers(&[command_buffer])
-            .build()];
-
-        unsafe {
-            device
-                .queue_submit(graphics_queue, &submit_infos, vk::Fence::null())
-                .expect("Failed to execute queue submit.");
 
-            device.queue_wait_idle(graphics_queue).unwrap();
+        for &(tile_offset_x, tile_offset_y, tile_width, tile_height) in tiles {
+            let uniform_offset = uniform_ring.push(
+                &frame_uniforms(frame_index, spp, tile_offset_x, tile_offset_y),
+                &device,
+            );
+            cmd_trace_tile(command_buffer, uniform_offset, tile_width, tile_height);
         }
-    }
+    };
 
     // transfer to host
 
     let dst_image = {
         let dst_image_create_info = vk::ImageCreateInfo::builder()
             .image_type(vk::ImageType::TYPE_2D)
-            .format(COLOR_FORMAT)
+            .format(color_format)
             .extent(
                 vk::Extent3D::builder()
-                    .width(WIDTH)
-                    .height(HEIGHT)
+                    .width(args.width)
+                    .height(args.height)
                     .depth(1)
                     .build(),
             )
@@ -1093,90 +2539,586 @@ fn main() {
 
     let dst_device_memory = {
         let dst_mem_reqs = unsafe { device.get_image_memory_requirements(dst_image) };
-        let dst_mem_alloc_info = vk::MemoryAllocateInfo::builder()
-            .allocation_size(dst_mem_reqs.size)
-            .memory_type_index(get_memory_type_index(
+        unsafe {
+            allocate_memory(
+                &device,
                 device_memory_properties,
-                dst_mem_reqs.memory_type_bits,
+                dst_mem_reqs,
                 vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
-            ));
-
-        unsafe { device.allocate_memory(&dst_mem_alloc_info, None) }.unwrap()
+                vk::MemoryAllocateFlags::empty(),
+                AllocationCategory::Image,
+            )
+        }
     };
     unsafe { device.bind_image_memory(dst_image, dst_device_memory, 0) }.unwrap();
 
-    let copy_cmd = {
-        let allocate_info = vk::CommandBufferAllocateInfo::builder()
-            .command_pool(command_pool)
-            .level(vk::CommandBufferLevel::PRIMARY)
-            .command_buffer_count(1)
-            .build();
-
-        unsafe { device.allocate_command_buffers(&allocate_info) }.unwrap()[0]
-    };
+    // Whether a pass has written the image since the program started.
+    let traced = if let Some((event_loop, window)) = &mut window {
+        let presenter = presenter.as_mut().unwrap();
+        // Linear filtering of 32-bit float images is optional.
+        let blit_filter = match args.format {
+            OutputFormat::Png => vk::Filter::LINEAR,
+            OutputFormat::Exr => vk::Filter::NEAREST,
+        };
 
-    {
-        let cmd_begin_info = vk::CommandBufferBeginInfo::builder().build();
+        // Every frame is one accumulation pass of --pass-spp samples per pixel, until the window
+        // is closed.
+        let mut frame_index = 0;
+        event_loop.run_return(|event, _, control_flow| {
+            *control_flow = ControlFlow::Poll;
+
+            match event {
+                Event::WindowEvent {
+                    event: WindowEvent::CloseRequested,
+                    ..
+                } => *control_flow = ControlFlow::Exit,
+                Event::MainEventsCleared => {
+                    let Some(swapchain_index) = presenter.acquire(physical_device, args.width, args.height)
+                    else {
+                        return;
+                    };
+
+                    let command_buffer = unsafe {
+                        device.allocate_command_buffers(
+                            &vk::CommandBufferAllocateInfo::builder()
+                                .command_buffer_count(1)
+                                .command_pool(command_pool)
+                                .level(vk::CommandBufferLevel::PRIMARY)
+                                .build(),
+                        )
+                    }
+                    .unwrap()[0];
+
+                    unsafe {
+                        device.begin_command_buffer(
+                            command_buffer,
+                            &vk::CommandBufferBeginInfo::builder()
+                                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+                                .build(),
+                        )
+                    }
+                    .unwrap();
+
+                    cmd_trace_pass(
+                        command_buffer,
+                        &mut uniform_ring,
+                        frame_index,
+                        args.pass_spp,
+                        &tiles,
+                    );
+
+                    presenter.cmd_blit(
+                        &device,
+                        command_buffer,
+                        swapchain_index,
+                        image,
+                        args.width,
+                        args.height,
+                        blit_filter,
+                    );
+
+                    unsafe { device.end_command_buffer(command_buffer) }.unwrap();
+
+                    presenter.submit_and_present(
+                        &device,
+                        graphics_queue,
+                        command_buffer,
+                        swapchain_index,
+                    );
+
+                    unsafe {
+                        device.queue_wait_idle(graphics_queue).unwrap();
+                        device.free_command_buffers(command_pool, &[command_buffer]);
+                    }
+
+                    frame_index += 1;
+                    window.set_title(&format!(
+                        "ash-raytracing-example - {} spp",
+                        frame_index * args.pass_spp
+                    ));
+                }
+                _ => {}
+            }
+        });
 
-        unsafe { device.begin_command_buffer(copy_cmd, &cmd_begin_info) }.unwrap();
-    }
+        if frame_index > 0 {
+            println!(
+                "Accumulated {} samples per pixel; writing them to {}.",
+                frame_index * args.pass_spp,
+                out
+            );
+        }
+        frame_index > 0
+    } else {
+        // A second Ctrl+C exits right away, for when the pass in flight takes too long.
+        ctrlc::set_handler(|| {
+            if INTERRUPTED.swap(true, Ordering::Relaxed) {
+                std::process::exit(130);
+            }
+            eprintln!("Interrupted; finishing the pass in flight. Press Ctrl+C again to abort.");
+        })
+        .expect("failed to set Ctrl+C handler");
 
-    {
-        let image_barrier = vk::ImageMemoryBarrier::builder()
-            .src_access_mask(vk::AccessFlags::empty())
-            .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
-            .old_layout(vk::ImageLayout::UNDEFINED)
-            .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
-            .image(dst_image)
-            .subresource_range(
-                vk::ImageSubresourceRange::builder()
-                    .aspect_mask(vk::ImageAspectFlags::COLOR)
-                    .base_mip_level(0)
-                    .level_count(1)
-                    .base_array_layer(0)
-                    .layer_count(1)
-                    .build(),
+        let new_staging_buffer = || {
+            BufferResource::new(
+                accumulation_buffer.size,
+                vk::BufferUsageFlags::TRANSFER_SRC | vk::BufferUsageFlags::TRANSFER_DST,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                &device,
+                device_memory_properties,
+                AllocationCategory::Staging,
             )
-            .build();
+        };
 
-        unsafe {
-            device.cmd_pipeline_barrier(
-                copy_cmd,
-                vk::PipelineStageFlags::TRANSFER,
-                vk::PipelineStageFlags::TRANSFER,
-                vk::DependencyFlags::empty(),
-                &[],
-                &[],
-                &[image_barrier],
+        // A resumed render adds its passes to those in the checkpoint, carrying on with the next
+        // frame index.
+        let (mut completed_passes, mut samples) = match checkpoint {
+            Some(checkpoint) => {
+                let mut staging_buffer = new_staging_buffer();
+                staging_buffer.store(&checkpoint.accumulation, &device);
+                copy_buffer(
+                    &device,
+                    command_pool,
+                    graphics_queue,
+                    staging_buffer.buffer,
+                    accumulation_buffer.buffer,
+                    accumulation_buffer.size,
+                );
+                unsafe { staging_buffer.destroy(&device) };
+                println!(
+                    "Resuming from {} samples per pixel in {} passes.",
+                    checkpoint.samples, checkpoint.passes
+                );
+                (checkpoint.passes, checkpoint.samples)
+            }
+            None => (0, 0),
+        };
+        let first_pass = completed_passes;
+
+        // Trace --pass-spp samples per pixel at a time, so previews can be written and Ctrl+C or
+        // --time can stop between passes. The image always holds the average of the passes
+        // accumulated so far. With --time, passes go on until the deadline.
+        let passes = match args.time {
+            Some(_) => u32::MAX,
+            None => {
+                let remaining = args.spp - samples;
+                completed_passes
+                    + remaining / args.pass_spp
+                    + (remaining % args.pass_spp != 0) as u32
+            }
+        };
+        let mut render_ms = 0.0;
+        // The GPU counters are only 32 bits, so they are added up here and cleared after every
+        // pass, long before they could wrap.
+        let mut counters = [0u64; STAT_COUNT];
+        let render_start = Instant::now();
+        let mut last_preview = render_start;
+        let preview = format!("preview.{}", args.format.extension());
+        while completed_passes < passes {
+            if INTERRUPTED.load(Ordering::Relaxed) {
+                break;
+            }
+            if let Some(time) = args.time {
+                if render_start.elapsed() >= time {
+                    println!("Time budget of {:?} used up.", time);
+                    break;
+                }
+            }
+
+            let spp = match args.time {
+                Some(_) => args.pass_spp,
+                None => args.pass_spp.min(args.spp - samples),
+            };
+
+            // Every tile is a submission of its own, so low latency mode can give the GPU to other
+            // work between them. The first and last tiles bracket the pass with timestamps.
+            for (i, tile) in tiles.iter().enumerate() {
+                let command_buffer = unsafe {
+                    device.allocate_command_buffers(
+                        &vk::CommandBufferAllocateInfo::builder()
+                            .command_buffer_count(1)
+                            .command_pool(command_pool)
+                            .level(vk::CommandBufferLevel::PRIMARY)
+                            .build(),
+                    )
+                }
+                .unwrap()[0];
+
+                unsafe {
+                    device.begin_command_buffer(
+                        command_buffer,
+                        &vk::CommandBufferBeginInfo::builder()
+                            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+                            .build(),
+                    )
+                }
+                .unwrap();
+
+                if i == 0 {
+                    unsafe {
+                        device.cmd_reset_query_pool(command_buffer, render_query_pool, 0, 2);
+                        device.cmd_write_timestamp(
+                            command_buffer,
+                            vk::PipelineStageFlags::TOP_OF_PIPE,
+                            render_query_pool,
+                            0,
+                        );
+                    }
+                }
+
+                cmd_trace_pass(
+                    command_buffer,
+                    &mut uniform_ring,
+                    completed_passes,
+                    spp,
+                    std::slice::from_ref(tile),
+                );
+
+                unsafe {
+                    if i + 1 == tiles.len() {
+                        device.cmd_write_timestamp(
+                            command_buffer,
+                            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                            render_query_pool,
+                            1,
+                        );
+                    }
+
+                    device.end_command_buffer(command_buffer).unwrap();
+
+                    let submit_infos = [vk::SubmitInfo::builder()
+                        .command_buffers(&[command_buffer])
+                        .build()];
+                    device
+                        .queue_submit(graphics_queue, &submit_infos, vk::Fence::null())
+                        .expect("Failed to execute queue submit.");
+
+                    device.queue_wait_idle(graphics_queue).unwrap();
+                    device.free_command_buffers(command_pool, &[command_buffer]);
+                }
+            }
+
+            let mut timestamps = [0u64; 2];
+            unsafe {
+                device.get_query_pool_results(
+                    render_query_pool,
+                    0,
+                    timestamps.len() as u32,
+                    &mut timestamps,
+                    vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WAIT,
+                )
+            }
+            .unwrap();
+            render_ms += (timestamps[1] - timestamps[0]) as f64
+                * physical_device_limits.timestamp_period as f64
+                / 1_000_000.0;
+
+            unsafe {
+                let data = stats_buffer.map(0, vk::WHOLE_SIZE, &device) as *const u32;
+                for (total, &count) in counters
+                    .iter_mut()
+                    .zip(std::slice::from_raw_parts(data, STAT_COUNT))
+                {
+                    *total += u64::from(count);
+                }
+                stats_buffer.unmap(&device);
+            }
+            stats_buffer.store(&[0u32; STAT_COUNT], &device);
+
+            completed_passes += 1;
+            samples += spp;
+
+            if let Some(preview_interval) = args.preview_interval {
+                if completed_passes < passes && last_preview.elapsed() >= preview_interval {
+                    read_back_image(
+                        &device,
+                        command_pool,
+                        graphics_queue,
+                        image,
+                        dst_image,
+                        dst_device_memory,
+                        args.width,
+                        args.height,
+                        args.format,
+                        &preview,
+                    );
+                    println!("Wrote {} with {} samples per pixel.", preview, samples);
+                    last_preview = Instant::now();
+                }
+            }
+        }
+
+        if completed_passes < passes && completed_passes > first_pass {
+            println!(
+                "Accumulated {} samples per pixel in {} passes; writing them to {}.",
+                samples, completed_passes, out
             );
         }
-    }
 
-    {
-        let copy_region = vk::ImageCopy::builder()
-            .src_subresource(
-                vk::ImageSubresourceLayers::builder()
-                    .aspect_mask(vk::ImageAspectFlags::COLOR)
-                    .layer_count(1)
-                    .build(),
-            )
-            .dst_subresource(
-                vk::ImageSubresourceLayers::builder()
-                    .aspect_mask(vk::ImageAspectFlags::COLOR)
-                    .layer_count(1)
-                    .build(),
-            )
-            .extent(
-                vk::Extent3D::builder()
-                    .width(WIDTH)
-                    .height(HEIGHT)
-                    .depth(1)
-                    .build(),
+        // Save what an interrupted render accumulated, so --resume can carry on from there.
+        let interrupted = INTERRUPTED.load(Ordering::Relaxed);
+        if interrupted && completed_passes > 0 && completed_passes < passes {
+            let mut staging_buffer = new_staging_buffer();
+            copy_buffer(
+                &device,
+                command_pool,
+                graphics_queue,
+                accumulation_buffer.buffer,
+                staging_buffer.buffer,
+                accumulation_buffer.size,
+            );
+            let accumulation = unsafe {
+                let data = staging_buffer.map(0, vk::WHOLE_SIZE, &device) as *const [f32; 4];
+                let accumulation =
+                    std::slice::from_raw_parts(data, (args.width * args.height) as usize).to_vec();
+                staging_buffer.unmap(&device);
+                staging_buffer.destroy(&device);
+                accumulation
+            };
+
+            let path = format!("{}.checkpoint", out);
+            Checkpoint {
+                width: args.width,
+                height: args.height,
+                passes: completed_passes,
+                samples,
+                accumulation,
+            }
+            .write(Path::new(&path));
+            println!("Wrote {}; carry on with --resume {}.", path, path);
+        }
+
+        let primary_rays = counters[STAT_PRIMARY_RAYS] as f64 / 1_000_000.0;
+        let secondary_rays = counters[STAT_SECONDARY_RAYS] as f64 / 1_000_000.0;
+        // Without a finished pass there is no render time to divide by.
+        let rate = if completed_passes == first_pass {
+            "n/a".to_string()
+        } else {
+            format!(
+                "{:.1} Mrays/s",
+                (primary_rays + secondary_rays) / (render_ms / 1000.0)
             )
-            .build();
+        };
+        println!(
+            "Traced {:.2} M primary and {:.2} M secondary rays in {:.3} ms ({}).",
+            primary_rays, secondary_rays, render_ms, rate,
+        );
 
-        unsafe {
-            device.cmd_copy_image(
+        let percent = |count: u64, total: u64| count as f64 / total.max(1) as f64 * 100.0;
+        println!(
+            "  {:.1}% of camera rays hit the scene, {:.1}% of secondary rays were occluded.",
+            100.0 - percent(counters[STAT_CAMERA_MISSES], counters[STAT_PRIMARY_RAYS]),
+            percent(counters[STAT_OCCLUDED_RAYS], counters[STAT_SECONDARY_RAYS]),
+        );
+
+        let path_lengths = &counters[STAT_PATH_LENGTHS..STAT_PATH_LENGTHS + PATH_LENGTH_BINS];
+        let paths = path_lengths.iter().sum::<u64>();
+        let histogram = path_lengths
+            .iter()
+            .enumerate()
+            .map(|(i, &count)| {
+                let plus = if i + 1 == PATH_LENGTH_BINS { "+" } else { "" };
+                format!("{}{}: {:.1}%", i + 1, plus, percent(count, paths))
+            })
+            .collect::<Vec<_>>();
+        println!("  Rays per path: {}", histogram.join(", "));
+
+        completed_passes > first_pass
+    };
+
+    if cfg!(debug_assertions) {
+        report_asserts(&mut assert_buffer, &device);
+    }
+
+    // The storage image only holds something once a pass has written it.
+    if traced {
+        read_back_image(
+            &device,
+            command_pool,
+            graphics_queue,
+            image,
+            dst_image,
+            dst_device_memory,
+            args.width,
+            args.height,
+            args.format,
+            &out,
+        );
+    } else {
+        println!("No pass finished, so {} isn't written.", out);
+    }
+
+    unsafe {
+        device.free_memory(dst_device_memory, None);
+        device.destroy_image(dst_image, None);
+    }
+
+    // clean up
+
+    unsafe {
+        device.destroy_query_pool(render_query_pool, None);
+        device.destroy_command_pool(command_pool, None);
+    }
+
+    unsafe {
+        // device.destroy_descriptor_set_layout(layout, allocation_callbacks)
+        device.destroy_descriptor_pool(descriptor_pool, None);
+        shader_binding_table_buffer.destroy(&device);
+        device.destroy_pipeline(graphics_pipeline, None);
+        device.destroy_descriptor_set_layout(descriptor_set_layout, None);
+    }
+
+    unsafe {
+        device.destroy_pipeline_layout(pipeline_layout, None);
+    }
+
+    unsafe {
+        for (bottom_as, bottom_as_buffer) in bottom_as_list {
+            acceleration_structure.destroy_acceleration_structure(bottom_as, None);
+            bottom_as_buffer.destroy(&device);
+        }
+
+        acceleration_structure.destroy_acceleration_structure(top_as, None);
+        top_as_buffer.destroy(&device);
+
+        if let Some((primary_top_as, primary_top_as_buffer, primary_instance_buffer, _)) =
+            primary_top_as
+        {
+            acceleration_structure.destroy_acceleration_structure(primary_top_as, None);
+            primary_top_as_buffer.destroy(&device);
+            primary_instance_buffer.destroy(&device);
+        }
+
+        device.destroy_image_view(image_view, None);
+        device.destroy_image(image, None);
+        device.free_memory(device_memory, None);
+
+        device.destroy_sampler(skybox_sampler, None);
+        device.destroy_image_view(skybox_image_view, None);
+        device.destroy_image(skybox_image, None);
+        device.free_memory(skybox_device_memory, None);
+
+        device.destroy_image_view(backplate_image_view, None);
+        device.destroy_image(backplate_image, None);
+        device.free_memory(backplate_device_memory, None);
+    }
+
+    unsafe {
+        uniform_ring.destroy(&device);
+        color_buffer.destroy(&device);
+        mesh_info_buffer.destroy(&device);
+        assert_buffer.destroy(&device);
+        stats_buffer.destroy(&device);
+        instance_buffer.destroy(&device);
+        vertex_buffer.destroy(&device);
+        index_buffer.destroy(&device);
+        segment_buffer.destroy(&device);
+        segment_aabb_buffer.destroy(&device);
+        point_buffer.destroy(&device);
+        point_aabb_buffer.destroy(&device);
+        accumulation_buffer.destroy(&device);
+    }
+
+    if let Some(presenter) = presenter {
+        unsafe { presenter.destroy(&device) };
+    }
+
+    unsafe {
+        device.destroy_device(None);
+    }
+
+    unsafe {
+        instance.destroy_instance(None);
+    }
+}
+
+/// Copies `image` into the host-visible, linear `dst_image` and writes it to `path` in `format`,
+/// which must be the one both images were created with.
+#[allow(clippy::too_many_arguments)]
+fn read_back_image(
+    device: &ash::Device,
+    command_pool: vk::CommandPool,
+    graphics_queue: vk::Queue,
+    image: vk::Image,
+    dst_image: vk::Image,
+    dst_device_memory: vk::DeviceMemory,
+    width: u32,
+    height: u32,
+    format: OutputFormat,
+    path: &str,
+) {
+    let copy_cmd = {
+        let allocate_info = vk::CommandBufferAllocateInfo::builder()
+            .command_pool(command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(1)
+            .build();
+
+        unsafe { device.allocate_command_buffers(&allocate_info) }.unwrap()[0]
+    };
+
+    {
+        let cmd_begin_info = vk::CommandBufferBeginInfo::builder().build();
+
+        unsafe { device.begin_command_buffer(copy_cmd, &cmd_begin_info) }.unwrap();
+    }
+
+    {
+        let image_barrier = vk::ImageMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::empty())
+            .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .old_layout(vk::ImageLayout::UNDEFINED)
+            .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .image(dst_image)
+            .subresource_range(
+                vk::ImageSubresourceRange::builder()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .base_mip_level(0)
+                    .level_count(1)
+                    .base_array_layer(0)
+                    .layer_count(1)
+                    .build(),
+            )
+            .build();
+
+        unsafe {
+            device.cmd_pipeline_barrier(
+                copy_cmd,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[image_barrier],
+            );
+        }
+    }
+
+    {
+        let copy_region = vk::ImageCopy::builder()
+            .src_subresource(
+                vk::ImageSubresourceLayers::builder()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .layer_count(1)
+                    .build(),
+            )
+            .dst_subresource(
+                vk::ImageSubresourceLayers::builder()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .layer_count(1)
+                    .build(),
+            )
+            .extent(
+                vk::Extent3D::builder()
+                    .width(width)
+                    .height(height)
+                    .depth(1)
+                    .build(),
+            )
+            .build();
+
+        unsafe {
+            device.cmd_copy_image(
                 copy_cmd,
                 image,
                 vk::ImageLayout::GENERAL,
@@ -1263,74 +3205,148 @@ fn main() {
 
     let mut data = unsafe { data.offset(subresource_layout.offset as isize) };
 
-    let mut png_encoder = png::Encoder::new(File::create("out.png").unwrap(), WIDTH, HEIGHT);
-
-    png_encoder.set_depth(png::BitDepth::Eight);
-    png_encoder.set_color(png::ColorType::Rgba);
-
-    let mut png_writer = png_encoder
-        .write_header()
-        .unwrap()
-        .into_stream_writer_with_size((4 * WIDTH) as usize)
-        .unwrap();
-
-    for _ in 0..HEIGHT {
-        let row = unsafe { std::slice::from_raw_parts(data, 4 * WIDTH as usize) };
-        png_writer.write_all(row).unwrap();
+    let row_size = format.bytes_per_pixel() * width as usize;
+    let mut pixels = Vec::with_capacity(row_size * height as usize);
+    for _ in 0..height {
+        pixels.extend_from_slice(unsafe { std::slice::from_raw_parts(data, row_size) });
         data = unsafe { data.offset(subresource_layout.row_pitch as isize) };
     }
 
-    png_writer.finish().unwrap();
-
-    unsafe {
-        device.unmap_memory(dst_device_memory);
-        device.free_memory(dst_device_memory, None);
-        device.destroy_image(dst_image, None);
-    }
+    match format {
+        OutputFormat::Png => {
+            let mut png_encoder = png::Encoder::new(File::create(path).unwrap(), width, height);
 
-    // clean up
+            png_encoder.set_depth(png::BitDepth::Eight);
+            png_encoder.set_color(png::ColorType::Rgba);
 
-    unsafe {
-        device.destroy_command_pool(command_pool, None);
+            let mut png_writer = png_encoder.write_header().unwrap();
+            png_writer.write_image_data(&pixels).unwrap();
+            png_writer.finish().unwrap();
+        }
+        OutputFormat::Exr => {
+            let pixels = pixels
+                .chunks_exact(4)
+                .map(|bytes| f32::from_ne_bytes(bytes.try_into().unwrap()))
+                .collect::<Vec<_>>();
+            exr::write_rgba32f(path, width, height, &pixels);
+        }
     }
 
     unsafe {
-        // device.destroy_descriptor_set_layout(layout, allocation_callbacks)
-        device.destroy_descriptor_pool(descriptor_pool, None);
-        shader_binding_table_buffer.destroy(&device);
-        device.destroy_pipeline(graphics_pipeline, None);
-        device.destroy_descriptor_set_layout(descriptor_set_layout, None);
+        device.unmap_memory(dst_device_memory);
+        device.free_command_buffers(command_pool, &[copy_cmd]);
     }
+}
 
-    unsafe {
-        device.destroy_pipeline_layout(pipeline_layout, None);
+/// Copies the first `size` bytes of `src` to `dst` and waits for the copy. Ray tracing shaders
+/// must be done writing `src`; the copy is visible to them and to the host afterwards.
+fn copy_buffer(
+    device: &ash::Device,
+    command_pool: vk::CommandPool,
+    graphics_queue: vk::Queue,
+    src: vk::Buffer,
+    dst: vk::Buffer,
+    size: vk::DeviceSize,
+) {
+    let command_buffer = unsafe {
+        device.allocate_command_buffers(
+            &vk::CommandBufferAllocateInfo::builder()
+                .command_buffer_count(1)
+                .command_pool(command_pool)
+                .level(vk::CommandBufferLevel::PRIMARY)
+                .build(),
+        )
     }
+    .unwrap()[0];
 
     unsafe {
-        acceleration_structure.destroy_acceleration_structure(bottom_as, None);
-        bottom_as_buffer.destroy(&device);
+        device
+            .begin_command_buffer(
+                command_buffer,
+                &vk::CommandBufferBeginInfo::builder()
+                    .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+                    .build(),
+            )
+            .unwrap();
+
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::RAY_TRACING_SHADER_KHR | vk::PipelineStageFlags::HOST,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::SHADER_WRITE | vk::AccessFlags::HOST_WRITE)
+                .dst_access_mask(vk::AccessFlags::TRANSFER_READ | vk::AccessFlags::TRANSFER_WRITE)
+                .build()],
+            &[],
+            &[],
+        );
+        device.cmd_copy_buffer(
+            command_buffer,
+            src,
+            dst,
+            &[vk::BufferCopy::builder().size(size).build()],
+        );
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::RAY_TRACING_SHADER_KHR | vk::PipelineStageFlags::HOST,
+            vk::DependencyFlags::empty(),
+            &[vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(
+                    vk::AccessFlags::SHADER_READ
+                        | vk::AccessFlags::SHADER_WRITE
+                        | vk::AccessFlags::HOST_READ,
+                )
+                .build()],
+            &[],
+            &[],
+        );
 
-        acceleration_structure.destroy_acceleration_structure(top_as, None);
-        top_as_buffer.destroy(&device);
+        device.end_command_buffer(command_buffer).unwrap();
 
-        device.destroy_image_view(image_view, None);
-        device.destroy_image(image, None);
-        device.free_memory(device_memory, None);
-    }
+        let command_buffers = [command_buffer];
+        let submit_infos = [vk::SubmitInfo::builder()
+            .command_buffers(&command_buffers)
+            .build()];
+        device
+            .queue_submit(graphics_queue, &submit_infos, vk::Fence::null())
+            .expect("Failed to execute queue submit.");
 
-    unsafe {
-        color_buffer.destroy(&device);
-        instance_buffer.destroy(&device);
-        vertex_buffer.destroy(&device);
-        index_buffer.destroy(&device);
+        device.queue_wait_idle(graphics_queue).unwrap();
+        device.free_command_buffers(command_pool, &command_buffers);
     }
+}
 
-    unsafe {
-        device.destroy_device(None);
+/// Prints the invariant violations the shaders recorded in `assert_buffer`.
+fn report_asserts(assert_buffer: &mut BufferResource, device: &ash::Device) {
+    let words = unsafe {
+        let data = assert_buffer.map(0, vk::WHOLE_SIZE, device) as *const u32;
+        let words = std::slice::from_raw_parts(data, 1 + 3 * MAX_ASSERT_RECORDS as usize).to_vec();
+        assert_buffer.unmap(device);
+        words
+    };
+
+    let count = words[0];
+    if count == 0 {
+        return;
     }
 
-    unsafe {
-        instance.destroy_instance(None);
+    eprintln!(
+        "warning: shaders reported {} failed invariant checks:",
+        count
+    );
+    for record in words[1..].chunks_exact(3).take(count as usize) {
+        let kind = match record[0] {
+            ASSERT_NAN_NORMAL => "NaN normal",
+            ASSERT_MESH_INDEX_OUT_OF_RANGE => "mesh index out of range",
+            _ => "unknown",
+        };
+        eprintln!("  {} at pixel ({}, {})", kind, record[1], record[2]);
+    }
+    if count > MAX_ASSERT_RECORDS {
+        eprintln!("  ... and {} more", count - MAX_ASSERT_RECORDS);
     }
 }
 
@@ -1351,6 +3367,17 @@ fn check_validation_layer_support<'a>(
         .all(|l| supported_layers.contains(l)))
 }
 
+fn device_supports_extension(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    extension: &CStr,
+) -> bool {
+    unsafe { instance.enumerate_device_extension_properties(physical_device) }
+        .unwrap()
+        .iter()
+        .any(|ext| unsafe { CStr::from_ptr(ext.extension_name.as_ptr()) } == extension)
+}
+
 fn pick_physical_device_and_queue_family_indices(
     instance: &ash::Instance,
     extensions: &[&CStr],
@@ -1402,42 +3429,204 @@ unsafe fn create_shader_module(device: &ash::Device, code: &[u8]) -> VkResult<vk
     device.create_shader_module(&shader_module_create_info, None)
 }
 
+/// Loads `px.png`, `nx.png`, `py.png`, `ny.png`, `pz.png` and `nz.png` from `dir` as RGBA8
+/// cube map faces. Returns `None` if the directory does not contain a skybox.
+fn load_skybox_faces(dir: &Path) -> Option<(u32, Vec<u8>)> {
+    const FACE_NAMES: [&str; 6] = ["px.png", "nx.png", "py.png", "ny.png", "pz.png", "nz.png"];
+
+    if !dir.join(FACE_NAMES[0]).exists() {
+        return None;
+    }
+
+    let mut face_size = None;
+    let mut pixels = Vec::new();
+
+    for name in FACE_NAMES {
+        let path = dir.join(name);
+        let (width, height, face_pixels) =
+            load_png_rgba8(&path).expect("failed to open skybox face");
+
+        assert_eq!(width, height, "skybox face {:?} is not square", path);
+        assert_eq!(
+            *face_size.get_or_insert(width),
+            width,
+            "skybox face {:?} differs in size from the other faces",
+            path
+        );
+
+        pixels.extend_from_slice(&face_pixels);
+    }
+
+    face_size.map(|face_size| (face_size, pixels))
+}
+
+/// Loads a PNG as RGBA8, returning its width, height and pixels, or `None` if the file does not
+/// exist.
+fn load_png_rgba8(path: &Path) -> Option<(u32, u32, Vec<u8>)> {
+    if !path.exists() {
+        return None;
+    }
+
+    let mut decoder = png::Decoder::new(File::open(path).expect("failed to open image"));
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info().unwrap();
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf).unwrap();
+
+    let buf = &buf[..info.buffer_size()];
+    let pixels = match info.color_type {
+        png::ColorType::Rgba => buf.to_vec(),
+        png::ColorType::Rgb => buf
+            .chunks_exact(3)
+            .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 255])
+            .collect(),
+        png::ColorType::Grayscale => buf.iter().flat_map(|&l| [l, l, l, 255]).collect(),
+        png::ColorType::GrayscaleAlpha => buf
+            .chunks_exact(2)
+            .flat_map(|la| [la[0], la[0], la[0], la[1]])
+            .collect(),
+        color_type => panic!("unsupported color type {:?} in {:?}", color_type, path),
+    };
+
+    Some((info.width, info.height, pixels))
+}
+
+/// Generates RGBA8 cube map faces for a simple vertical sky gradient.
+fn procedural_skybox_faces(face_size: u32) -> Vec<u8> {
+    let mut pixels = Vec::with_capacity((6 * face_size * face_size * 4) as usize);
+
+    for face in 0..6 {
+        for y in 0..face_size {
+            for x in 0..face_size {
+                let s = 2.0 * (x as f32 + 0.5) / face_size as f32 - 1.0;
+                let t = 2.0 * (y as f32 + 0.5) / face_size as f32 - 1.0;
+
+                // Major axis direction for each face as laid out by the Vulkan spec.
+                let direction = match face {
+                    0 => [1.0, -t, -s],
+                    1 => [-1.0, -t, s],
+                    2 => [s, 1.0, t],
+                    3 => [s, -1.0, -t],
+                    4 => [s, -t, 1.0],
+                    _ => [-s, -t, -1.0],
+                };
+
+                let length = direction.iter().map(|v| v * v).sum::<f32>().sqrt();
+                let a = 0.5 * (direction[1] / length + 1.0);
+
+                let horizon = [1.0, 1.0, 1.0];
+                let zenith = [0.5, 0.7, 1.0];
+
+                for (h, z) in horizon.iter().zip(zenith) {
+                    pixels.push((255.0 * ((1.0 - a) * h + a * z)) as u8);
+                }
+                pixels.push(255);
+            }
+        }
+    }
+
+    pixels
+}
+
 fn get_memory_type_index(
     device_memory_properties: vk::PhysicalDeviceMemoryProperties,
     mut type_bits: u32,
     properties: vk::MemoryPropertyFlags,
-) -> u32 {
+) -> Option<u32> {
     for i in 0..device_memory_properties.memory_type_count {
         if (type_bits & 1) == 1
             && (device_memory_properties.memory_types[i as usize].property_flags & properties)
                 == properties
         {
-            return i;
+            return Some(i);
         }
         type_bits >>= 1;
     }
-    0
+    None
 }
 
-#[allow(clippy::missing_safety_doc)]
-pub unsafe extern "system" fn default_vulkan_debug_utils_callback(
-    message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
-    message_type: vk::DebugUtilsMessageTypeFlagsEXT,
-    p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT,
-    _p_user_data: *mut c_void,
-) -> vk::Bool32 {
-    let severity = match message_severity {
-        vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE => "[Verbose]",
-        vk::DebugUtilsMessageSeverityFlagsEXT::WARNING => "[Warning]",
-        vk::DebugUtilsMessageSeverityFlagsEXT::ERROR => "[Error]",
-        vk::DebugUtilsMessageSeverityFlagsEXT::INFO => "[Info]",
-        _ => "[Unknown]",
-    };
-    let types = match message_type {
-        vk::DebugUtilsMessageTypeFlagsEXT::GENERAL => "[General]",
-        vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE => "[Performance]",
-        vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION => "[Validation]",
-        _ => "[Unknown]",
+/// Allocates memory for `requirements` with `properties`. If device-local memory runs out and
+/// `category` tolerates slower access, retries with host-visible memory and prints a warning
+/// instead of failing the run.
+unsafe fn allocate_memory(
+    device: &ash::Device,
+    device_memory_properties: vk::PhysicalDeviceMemoryProperties,
+    requirements: vk::MemoryRequirements,
+    properties: vk::MemoryPropertyFlags,
+    allocate_flags: vk::MemoryAllocateFlags,
+    category: AllocationCategory,
+) -> vk::DeviceMemory {
+    let allocate = |memory_type_index| {
+        let mut memory_allocate_flags_info = vk::MemoryAllocateFlagsInfo::builder()
+            .flags(allocate_flags)
+            .build();
+
+        let mut allocate_info_builder = vk::MemoryAllocateInfo::builder()
+            .allocation_size(requirements.size)
+            .memory_type_index(memory_type_index);
+
+        if !allocate_flags.is_empty() {
+            allocate_info_builder =
+                allocate_info_builder.push_next(&mut memory_allocate_flags_info);
+        }
+
+        device.allocate_memory(&allocate_info_builder.build(), None)
+    };
+
+    let memory_type_index = get_memory_type_index(
+        device_memory_properties,
+        requirements.memory_type_bits,
+        properties,
+    )
+    .expect("no suitable memory type.");
+
+    let fallback_type_index = get_memory_type_index(
+        device_memory_properties,
+        requirements.memory_type_bits,
+        vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+    )
+    .filter(|_| {
+        properties.contains(vk::MemoryPropertyFlags::DEVICE_LOCAL)
+            && category.allows_host_fallback()
+    });
+
+    let memory = match (allocate(memory_type_index), fallback_type_index) {
+        (Err(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY), Some(fallback_type_index)) => {
+            eprintln!(
+                "warning: out of device memory for {:?} ({} KiB), falling back to host-visible memory.",
+                category,
+                requirements.size / 1024,
+            );
+            allocate(fallback_type_index)
+        }
+        (result, _) => result,
+    }
+    .unwrap();
+
+    allocations::record_allocation(category, requirements.size);
+
+    memory
+}
+
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "system" fn default_vulkan_debug_utils_callback(
+    message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    message_type: vk::DebugUtilsMessageTypeFlagsEXT,
+    p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT,
+    _p_user_data: *mut c_void,
+) -> vk::Bool32 {
+    let severity = match message_severity {
+        vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE => "[Verbose]",
+        vk::DebugUtilsMessageSeverityFlagsEXT::WARNING => "[Warning]",
+        vk::DebugUtilsMessageSeverityFlagsEXT::ERROR => "[Error]",
+        vk::DebugUtilsMessageSeverityFlagsEXT::INFO => "[Info]",
+        _ => "[Unknown]",
+    };
+    let types = match message_type {
+        vk::DebugUtilsMessageTypeFlagsEXT::GENERAL => "[General]",
+        vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE => "[Performance]",
+        vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION => "[Validation]",
+        _ => "[Unknown]",
     };
     let message = CStr::from_ptr((*p_callback_data).p_message);
     println!("[Debug]{}{}{:?}", severity, types, message);
@@ -1450,6 +3639,8 @@ struct BufferResource {
     buffer: vk::Buffer,
     memory: vk::DeviceMemory,
     size: vk::DeviceSize,
+    category: AllocationCategory,
+    allocation_size: vk::DeviceSize,
 }
 
 impl BufferResource {
@@ -1459,6 +3650,7 @@ impl BufferResource {
         memory_properties: vk::MemoryPropertyFlags,
         device: &ash::Device,
         device_memory_properties: vk::PhysicalDeviceMemoryProperties,
+        category: AllocationCategory,
     ) -> Self {
         unsafe {
             let buffer_info = vk::BufferCreateInfo::builder()
@@ -1471,55 +3663,64 @@ impl BufferResource {
 
             let memory_req = device.get_buffer_memory_requirements(buffer);
 
-            let memory_index = get_memory_type_index(
+            let allocate_flags = if usage.contains(vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS) {
+                vk::MemoryAllocateFlags::DEVICE_ADDRESS
+            } else {
+                vk::MemoryAllocateFlags::empty()
+            };
+
+            let memory = allocate_memory(
+                device,
                 device_memory_properties,
-                memory_req.memory_type_bits,
+                memory_req,
                 memory_properties,
+                allocate_flags,
+                category,
             );
 
-            let mut memory_allocate_flags_info = vk::MemoryAllocateFlagsInfo::builder()
-                .flags(vk::MemoryAllocateFlags::DEVICE_ADDRESS)
-                .build();
-
-            let mut allocate_info_builder = vk::MemoryAllocateInfo::builder();
-
-            if usage.contains(vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS) {
-                allocate_info_builder =
-                    allocate_info_builder.push_next(&mut memory_allocate_flags_info);
-            }
-
-            let allocate_info = allocate_info_builder
-                .allocation_size(memory_req.size)
-                .memory_type_index(memory_index)
-                .build();
-
-            let memory = device.allocate_memory(&allocate_info, None).unwrap();
-
             device.bind_buffer_memory(buffer, memory, 0).unwrap();
 
             BufferResource {
                 buffer,
                 memory,
                 size,
+                category,
+                allocation_size: memory_req.size,
             }
         }
     }
 
     fn store<T: Copy>(&mut self, data: &[T], device: &ash::Device) {
+        self.store_at(0, data, device);
+    }
+
+    fn store_at<T: Copy>(&mut self, offset: vk::DeviceSize, data: &[T], device: &ash::Device) {
         unsafe {
             let size = std::mem::size_of_val(data) as u64;
-            assert!(self.size >= size, "Data size is larger than buffer size.");
-            let mapped_ptr = self.map(size, device);
+            // Mapping zero bytes isn't allowed.
+            if size == 0 {
+                return;
+            }
+            assert!(
+                self.size >= offset + size,
+                "Data size is larger than buffer size."
+            );
+            let mapped_ptr = self.map(offset, size, device);
             let mut mapped_slice = Align::new(mapped_ptr, std::mem::align_of::<T>() as u64, size);
             mapped_slice.copy_from_slice(data);
             self.unmap(device);
         }
     }
 
-    fn map(&mut self, size: vk::DeviceSize, device: &ash::Device) -> *mut std::ffi::c_void {
+    fn map(
+        &mut self,
+        offset: vk::DeviceSize,
+        size: vk::DeviceSize,
+        device: &ash::Device,
+    ) -> *mut std::ffi::c_void {
         unsafe {
             let data: *mut std::ffi::c_void = device
-                .map_memory(self.memory, 0, size, vk::MemoryMapFlags::empty())
+                .map_memory(self.memory, offset, size, vk::MemoryMapFlags::empty())
                 .unwrap();
             data
         }
@@ -1534,7 +3735,475 @@ impl BufferResource {
     unsafe fn destroy(self, device: &ash::Device) {
         device.destroy_buffer(self.buffer, None);
         device.free_memory(self.memory, None);
+        allocations::record_free(self.category, self.allocation_size);
+    }
+}
+
+/// Uniform buffer split into aligned slots that are written round-robin, so the uniforms of a
+/// new frame never overwrite ones the GPU may still be reading. Each frame binds its slot as a
+/// dynamic offset.
+struct UniformRing<T> {
+    buffer: BufferResource,
+    slot_size: vk::DeviceSize,
+    slot_count: u64,
+    next_slot: u64,
+    _marker: PhantomData<T>,
+}
+
+impl<T: Copy> UniformRing<T> {
+    fn new(
+        slot_count: u64,
+        min_offset_alignment: vk::DeviceSize,
+        device: &ash::Device,
+        device_memory_properties: vk::PhysicalDeviceMemoryProperties,
+    ) -> Self {
+        let slot_size = aligned_size(
+            std::mem::size_of::<T>() as u32,
+            min_offset_alignment.max(1) as u32,
+        ) as vk::DeviceSize;
+
+        let buffer = BufferResource::new(
+            slot_size * slot_count,
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            device,
+            device_memory_properties,
+            AllocationCategory::Uniform,
+        );
+
+        Self {
+            buffer,
+            slot_size,
+            slot_count,
+            next_slot: 0,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Writes `data` into the next slot and returns its dynamic offset.
+    fn push(&mut self, data: &T, device: &ash::Device) -> u32 {
+        let offset = self.next_slot * self.slot_size;
+        self.buffer
+            .store_at(offset, std::slice::from_ref(data), device);
+        self.next_slot = (self.next_slot + 1) % self.slot_count;
+        offset as u32
+    }
+
+    unsafe fn destroy(self, device: &ash::Device) {
+        self.buffer.destroy(device);
+    }
+}
+
+/// Splits a `width` x `height` launch into `(x, y, width, height)` tiles that each stay within
+/// `max_invocations` rays and `max_size` rays per dimension.
+fn dispatch_tiles(
+    width: u32,
+    height: u32,
+    max_invocations: u32,
+    max_size: [u32; 2],
+) -> Vec<(u32, u32, u32, u32)> {
+    let tile_width = width.min(max_size[0]).min(max_invocations).max(1);
+    let tile_height = height
+        .min(max_size[1])
+        .min(max_invocations / tile_width)
+        .max(1);
+
+    let mut tiles = Vec::new();
+    for y in (0..height).step_by(tile_height as usize) {
+        for x in (0..width).step_by(tile_width as usize) {
+            tiles.push((x, y, tile_width.min(width - x), tile_height.min(height - y)));
+        }
+    }
+    tiles
+}
+
+/// Uploads `instances` and builds a top-level acceleration structure over them. Returns the
+/// acceleration structure, its buffer, the instance buffer and its sizes.
+fn build_top_level_as(
+    device: &ash::Device,
+    acceleration_structure: &ash::extensions::khr::AccelerationStructure,
+    command_pool: vk::CommandPool,
+    graphics_queue: vk::Queue,
+    device_memory_properties: vk::PhysicalDeviceMemoryProperties,
+    instances: &[vk::AccelerationStructureInstanceKHR],
+) -> (
+    vk::AccelerationStructureKHR,
+    BufferResource,
+    BufferResource,
+    AccelerationStructureStats,
+) {
+    // A zero-sized buffer is invalid, so keep room for one instance even if all are culled.
+    let instance_buffer_size =
+        std::mem::size_of::<vk::AccelerationStructureInstanceKHR>() * instances.len().max(1);
+
+    let mut instance_buffer = BufferResource::new(
+        instance_buffer_size as vk::DeviceSize,
+        vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
+            | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR,
+        vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        device,
+        device_memory_properties,
+        AllocationCategory::Geometry,
+    );
+
+    instance_buffer.store(instances, device);
+
+    let build_range_info = vk::AccelerationStructureBuildRangeInfoKHR::builder()
+        .first_vertex(0)
+        .primitive_count(instances.len() as u32)
+        .primitive_offset(0)
+        .transform_offset(0)
+        .build();
+
+    let build_command_buffer = {
+        let allocate_info = vk::CommandBufferAllocateInfo::builder()
+            .command_buffer_count(1)
+            .command_pool(command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .build();
+
+        let command_buffers = unsafe { device.allocate_command_buffers(&allocate_info) }.unwrap();
+        command_buffers[0]
+    };
+
+    unsafe {
+        device
+            .begin_command_buffer(
+                build_command_buffer,
+                &vk::CommandBufferBeginInfo::builder()
+                    .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+                    .build(),
+            )
+            .unwrap();
+        let memory_barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::ACCELERATION_STRUCTURE_WRITE_KHR)
+            .build();
+        device.cmd_pipeline_barrier(
+            build_command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
+            vk::DependencyFlags::empty(),
+            &[memory_barrier],
+            &[],
+            &[],
+        );
+    }
+
+    let instances = vk::AccelerationStructureGeometryInstancesDataKHR::builder()
+        .array_of_pointers(false)
+        .data(vk::DeviceOrHostAddressConstKHR {
+            device_address: unsafe { get_buffer_device_address(device, instance_buffer.buffer) },
+        })
+        .build();
+
+    let geometry = vk::AccelerationStructureGeometryKHR::builder()
+        .geometry_type(vk::GeometryTypeKHR::INSTANCES)
+        .geometry(vk::AccelerationStructureGeometryDataKHR { instances })
+        .build();
+
+    let geometries = [geometry];
+
+    let mut build_info = vk::AccelerationStructureBuildGeometryInfoKHR::builder()
+        .flags(vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE)
+        .geometries(&geometries)
+        .mode(vk::BuildAccelerationStructureModeKHR::BUILD)
+        .ty(vk::AccelerationStructureTypeKHR::TOP_LEVEL)
+        .build();
+
+    let size_info = unsafe {
+        acceleration_structure.get_acceleration_structure_build_sizes(
+            vk::AccelerationStructureBuildTypeKHR::DEVICE,
+            &build_info,
+            &[build_range_info.primitive_count],
+        )
+    };
+
+    let top_as_buffer = BufferResource::new(
+        size_info.acceleration_structure_size,
+        vk::BufferUsageFlags::ACCELERATION_STRUCTURE_STORAGE_KHR
+            | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
+            | vk::BufferUsageFlags::STORAGE_BUFFER,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        device,
+        device_memory_properties,
+        AllocationCategory::AccelerationStructure,
+    );
+
+    let as_create_info = vk::AccelerationStructureCreateInfoKHR::builder()
+        .ty(build_info.ty)
+        .size(size_info.acceleration_structure_size)
+        .buffer(top_as_buffer.buffer)
+        .offset(0)
+        .build();
+
+    let top_as =
+        unsafe { acceleration_structure.create_acceleration_structure(&as_create_info, None) }
+            .unwrap();
+
+    build_info.dst_acceleration_structure = top_as;
+
+    let scratch_buffer = BufferResource::new(
+        size_info.build_scratch_size,
+        vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS | vk::BufferUsageFlags::STORAGE_BUFFER,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        device,
+        device_memory_properties,
+        AllocationCategory::Scratch,
+    );
+
+    build_info.scratch_data = vk::DeviceOrHostAddressKHR {
+        device_address: unsafe { get_buffer_device_address(device, scratch_buffer.buffer) },
+    };
+
+    unsafe {
+        acceleration_structure.cmd_build_acceleration_structures(
+            build_command_buffer,
+            &[build_info],
+            &[&[build_range_info]],
+        );
+        device.end_command_buffer(build_command_buffer).unwrap();
+        device
+            .queue_submit(
+                graphics_queue,
+                &[vk::SubmitInfo::builder()
+                    .command_buffers(&[build_command_buffer])
+                    .build()],
+                vk::Fence::null(),
+            )
+            .expect("queue submit failed.");
+
+        device.queue_wait_idle(graphics_queue).unwrap();
+        device.free_command_buffers(command_pool, &[build_command_buffer]);
+        scratch_buffer.destroy(device);
+    }
+
+    let stats = AccelerationStructureStats {
+        name: String::new(),
+        primitive_count: build_range_info.primitive_count,
+        built_size: size_info.acceleration_structure_size,
+        final_size: size_info.acceleration_structure_size,
+        scratch_size: size_info.build_scratch_size,
+    };
+
+    (top_as, top_as_buffer, instance_buffer, stats)
+}
+
+/// Creates a sampled, device-local image of `layer_count` layers, uploads `pixels` into it
+/// through a staging buffer, and returns the image, its memory and a view of `view_type`.
+#[allow(clippy::too_many_arguments)]
+fn create_texture(
+    device: &ash::Device,
+    command_pool: vk::CommandPool,
+    graphics_queue: vk::Queue,
+    device_memory_properties: vk::PhysicalDeviceMemoryProperties,
+    format: vk::Format,
+    flags: vk::ImageCreateFlags,
+    view_type: vk::ImageViewType,
+    width: u32,
+    height: u32,
+    layer_count: u32,
+    pixels: &[u8],
+) -> (vk::Image, vk::DeviceMemory, vk::ImageView) {
+    let image = {
+        let image_create_info = vk::ImageCreateInfo::builder()
+            .flags(flags)
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format)
+            .extent(
+                vk::Extent3D::builder()
+                    .width(width)
+                    .height(height)
+                    .depth(1)
+                    .build(),
+            )
+            .mip_levels(1)
+            .array_layers(layer_count)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST)
+            .build();
+
+        unsafe { device.create_image(&image_create_info, None) }.unwrap()
+    };
+
+    let device_memory = {
+        let mem_reqs = unsafe { device.get_image_memory_requirements(image) };
+        unsafe {
+            allocate_memory(
+                device,
+                device_memory_properties,
+                mem_reqs,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                vk::MemoryAllocateFlags::empty(),
+                AllocationCategory::Texture,
+            )
+        }
+    };
+
+    unsafe { device.bind_image_memory(image, device_memory, 0) }.unwrap();
+
+    {
+        let subresource_range = vk::ImageSubresourceRange::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .base_mip_level(0)
+            .level_count(1)
+            .base_array_layer(0)
+            .layer_count(layer_count)
+            .build();
+
+        let mut staging_buffer = BufferResource::new(
+            pixels.len() as vk::DeviceSize,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            device,
+            device_memory_properties,
+            AllocationCategory::Staging,
+        );
+
+        staging_buffer.store(pixels, device);
+
+        let command_buffer = {
+            let allocate_info = vk::CommandBufferAllocateInfo::builder()
+                .command_buffer_count(1)
+                .command_pool(command_pool)
+                .level(vk::CommandBufferLevel::PRIMARY)
+                .build();
+
+            let command_buffers =
+                unsafe { device.allocate_command_buffers(&allocate_info) }.unwrap();
+            command_buffers[0]
+        };
+
+        unsafe {
+            device.begin_command_buffer(
+                command_buffer,
+                &vk::CommandBufferBeginInfo::builder()
+                    .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+                    .build(),
+            )
+        }
+        .unwrap();
+
+        let to_transfer_barrier = vk::ImageMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::empty())
+            .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .old_layout(vk::ImageLayout::UNDEFINED)
+            .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .image(image)
+            .subresource_range(subresource_range)
+            .build();
+
+        // Layers are tightly packed one after another in the staging buffer.
+        let copy_region = vk::BufferImageCopy::builder()
+            .image_subresource(
+                vk::ImageSubresourceLayers::builder()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .mip_level(0)
+                    .base_array_layer(0)
+                    .layer_count(layer_count)
+                    .build(),
+            )
+            .image_extent(
+                vk::Extent3D::builder()
+                    .width(width)
+                    .height(height)
+                    .depth(1)
+                    .build(),
+            )
+            .build();
+
+        let to_shader_read_barrier = vk::ImageMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ)
+            .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image(image)
+            .subresource_range(subresource_range)
+            .build();
+
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[to_transfer_barrier],
+            );
+
+            device.cmd_copy_buffer_to_image(
+                command_buffer,
+                staging_buffer.buffer,
+                image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[copy_region],
+            );
+
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::RAY_TRACING_SHADER_KHR,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[to_shader_read_barrier],
+            );
+
+            device.end_command_buffer(command_buffer).unwrap();
+        }
+
+        let command_buffers = [command_buffer];
+
+        let submit_infos = [vk::SubmitInfo::builder()
+            .command_buffers(&command_buffers)
+            .build()];
+
+        unsafe {
+            device
+                .queue_submit(graphics_queue, &submit_infos, vk::Fence::null())
+                .expect("Failed to execute queue submit.");
+
+            device.queue_wait_idle(graphics_queue).unwrap();
+            device.free_command_buffers(command_pool, &[command_buffer]);
+            staging_buffer.destroy(device);
+        }
     }
+
+    let image_view = {
+        let image_view_create_info = vk::ImageViewCreateInfo::builder()
+            .view_type(view_type)
+            .format(format)
+            .subresource_range(vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count,
+            })
+            .image(image)
+            .build();
+
+        unsafe { device.create_image_view(&image_view_create_info, None) }.unwrap()
+    };
+
+    (image, device_memory, image_view)
+}
+
+/// Axis-aligned bounds of the box `min..max` after `transform`.
+fn transform_bounds(transform: Affine3A, min: Vec3, max: Vec3) -> (Vec3, Vec3) {
+    (0..8).fold(
+        (Vec3::splat(f32::INFINITY), Vec3::splat(f32::NEG_INFINITY)),
+        |(new_min, new_max), corner| {
+            let point = transform.transform_point3(Vec3::new(
+                if corner & 1 == 0 { min.x } else { max.x },
+                if corner & 2 == 0 { min.y } else { max.y },
+                if corner & 4 == 0 { min.z } else { max.z },
+            ));
+            (new_min.min(point), new_max.max(point))
+        },
+    )
 }
 
 fn aligned_size(value: u32, alignment: u32) -> u32 {
@@ -1548,3 +4217,143 @@ unsafe fn get_buffer_device_address(device: &ash::Device, buffer: vk::Buffer) ->
 
     device.get_buffer_device_address(&buffer_device_address_info)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_crop() {
+        assert_eq!(
+            Crop::parse("10, 20,300,400"),
+            Ok(Crop {
+                x: 10,
+                y: 20,
+                width: 300,
+                height: 400
+            })
+        );
+        assert!(Crop::parse("10,20,300").is_err());
+        assert!(Crop::parse("10,20,300,400,5").is_err());
+        assert!(Crop::parse("10,20,0,400").is_err());
+        assert!(Crop::parse("-1,20,300,400").is_err());
+        assert!(Crop::parse("").is_err());
+    }
+
+    #[test]
+    fn crop_must_fit_in_the_image() {
+        let crop = |s| Crop::parse(s).unwrap();
+        assert!(crop("0,0,800,600").fits_in(800, 600));
+        assert!(crop("700,500,100,100").fits_in(800, 600));
+        assert!(!crop("701,500,100,100").fits_in(800, 600));
+        assert!(!crop("700,501,100,100").fits_in(800, 600));
+        assert!(!crop("4294967295,0,1,1").fits_in(800, 600));
+    }
+
+    #[test]
+    fn crop_flag_uses_the_parser() {
+        let args = Args::try_parse_from(["test", "--crop", "1,2,3,4"]).unwrap();
+        assert_eq!(
+            args.crop,
+            Some(Crop {
+                x: 1,
+                y: 2,
+                width: 3,
+                height: 4
+            })
+        );
+        assert!(Args::try_parse_from(["test", "--crop", "1,2"]).is_err());
+    }
+
+    #[test]
+    fn enums_match_the_shader_ids() {
+        use ash_raytracing_example_shader::{color, filter, integrator};
+
+        for (host, shader) in [
+            (Integrator::Flat, integrator::Integrator::Flat),
+            (
+                Integrator::AmbientOcclusion,
+                integrator::Integrator::AmbientOcclusion,
+            ),
+            (Integrator::Normal, integrator::Integrator::Normal),
+            (Integrator::Path, integrator::Integrator::Path),
+        ] {
+            assert_eq!(host as u32, shader as u32, "{:?}", host);
+        }
+        for (host, shader) in [
+            (PixelFilter::Box, filter::PixelFilter::Box),
+            (PixelFilter::Tent, filter::PixelFilter::Tent),
+            (PixelFilter::Gaussian, filter::PixelFilter::Gaussian),
+            (
+                PixelFilter::BlackmanHarris,
+                filter::PixelFilter::BlackmanHarris,
+            ),
+        ] {
+            assert_eq!(host as u32, shader as u32, "{:?}", host);
+        }
+        for (host, shader) in [
+            (ColorSpace::Srgb, color::ColorSpace::Srgb),
+            (ColorSpace::LinearSrgb, color::ColorSpace::LinearSrgb),
+            (ColorSpace::Rec2020, color::ColorSpace::Rec2020),
+            (ColorSpace::AcesCg, color::ColorSpace::AcesCg),
+        ] {
+            assert_eq!(host as u32, shader as u32, "{:?}", host);
+        }
+    }
+
+    #[test]
+    fn settings_have_flags() {
+        let args = Args::try_parse_from([
+            "test",
+            "--integrator",
+            "ambient-occlusion",
+            "--pixel-filter",
+            "gaussian",
+            "--color-space",
+            "acescg",
+            "--max-bounces",
+            "8",
+            "--preview-interval",
+            "30s",
+            "--low-latency",
+            "--primary-ray-culling",
+        ])
+        .unwrap();
+        assert!(matches!(args.integrator, Integrator::AmbientOcclusion));
+        assert!(matches!(args.pixel_filter, PixelFilter::Gaussian));
+        assert_eq!(args.color_space, ColorSpace::AcesCg);
+        assert_eq!(args.max_bounces, 8);
+        assert_eq!(args.preview_interval, Some(Duration::from_secs(30)));
+        assert!(args.low_latency && args.primary_ray_culling);
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("90s"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("5m"), Ok(Duration::from_secs(300)));
+        assert_eq!(parse_duration("1h30m"), Ok(Duration::from_secs(5400)));
+        assert_eq!(parse_duration(" 2m5s "), Ok(Duration::from_secs(125)));
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("60").is_err());
+        assert!(parse_duration("s").is_err());
+        assert!(parse_duration("1.5s").is_err());
+        assert!(parse_duration("10d").is_err());
+    }
+
+    #[test]
+    fn time_flag_uses_the_parser() {
+        let args = Args::try_parse_from(["test", "--time", "60s"]).unwrap();
+        assert_eq!(args.time, Some(Duration::from_secs(60)));
+        assert!(Args::try_parse_from(["test", "--time", "soon"]).is_err());
+    }
+
+    #[test]
+    fn resume_is_headless_only() {
+        let args = Args::try_parse_from(["test", "--resume", "out.png.checkpoint"]).unwrap();
+        assert_eq!(args.resume, Some(PathBuf::from("out.png.checkpoint")));
+        assert!(
+            Args::try_parse_from(["test", "--resume", "out.png.checkpoint", "--window"]).is_err()
+        );
+    }
+}
@@ -0,0 +1,353 @@
+use std::collections::HashMap;
+
+use ash_raytracing_example_shader::math::uniform_triangle;
+use glam::{vec2, Vec2, Vec3};
+
+use crate::random::Random;
+
+/// Vertex layout shared by every triangle mesh, mirrored by `Vertex` in the shader crate.
+#[repr(C)]
+#[derive(Clone, Debug, Copy)]
+pub struct Vertex {
+    pub pos: [f32; 3],
+    pub normal: [f32; 3],
+    pub uv: [f32; 2],
+}
+
+/// Indexed triangle list. Every three indices form one triangle.
+#[derive(Clone, Debug, Default)]
+pub struct Mesh {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+}
+
+impl Mesh {
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    /// Axis-aligned bounds of the vertex positions.
+    pub fn bounds(&self) -> (Vec3, Vec3) {
+        self.vertices.iter().fold(
+            (Vec3::splat(f32::INFINITY), Vec3::splat(f32::NEG_INFINITY)),
+            |(min, max), vertex| {
+                let pos = Vec3::from(vertex.pos);
+                (min.min(pos), max.max(pos))
+            },
+        )
+    }
+
+    /// Bytes the vertex and index buffers of this mesh take up on the device.
+    pub fn geometry_size(&self) -> usize {
+        std::mem::size_of_val(self.vertices.as_slice())
+            + std::mem::size_of_val(self.indices.as_slice())
+    }
+
+    /// Picks `count` points spread uniformly over the surface, with normals and texture
+    /// coordinates interpolated from the triangle they land on. A mesh without triangles has no
+    /// surface to sample, so it gives no points.
+    pub fn sample_surface(&self, count: usize, random: &mut Random) -> Vec<Vertex> {
+        if self.triangle_count() == 0 {
+            return Vec::new();
+        }
+
+        let triangles = self
+            .indices
+            .chunks_exact(3)
+            .map(|triangle| [0, 1, 2].map(|i| self.vertices[triangle[i] as usize]))
+            .collect::<Vec<_>>();
+
+        // Running sum of triangle areas, so triangles are picked in proportion to their area.
+        let mut area_sum = 0.0;
+        let cumulative_areas = triangles
+            .iter()
+            .map(|[a, b, c]| {
+                let (a, b, c) = (Vec3::from(a.pos), Vec3::from(b.pos), Vec3::from(c.pos));
+                area_sum += (b - a).cross(c - a).length() * 0.5;
+                area_sum
+            })
+            .collect::<Vec<_>>();
+
+        (0..count)
+            .map(|_| {
+                let target = random.next_f32() * area_sum;
+                let triangle = cumulative_areas
+                    .partition_point(|&area| area < target)
+                    .min(triangles.len() - 1);
+                let [a, b, c] = triangles[triangle];
+
+                let Vec2 { x: b1, y: b2 } =
+                    uniform_triangle(vec2(random.next_f32(), random.next_f32()));
+                let b0 = 1.0 - b1 - b2;
+
+                let pos = Vec3::from(a.pos) * b0 + Vec3::from(b.pos) * b1 + Vec3::from(c.pos) * b2;
+                let normal = Vec3::from(a.normal) * b0
+                    + Vec3::from(b.normal) * b1
+                    + Vec3::from(c.normal) * b2;
+                let uv = Vec2::from(a.uv) * b0 + Vec2::from(b.uv) * b1 + Vec2::from(c.uv) * b2;
+
+                Vertex {
+                    pos: pos.to_array(),
+                    normal: normal.normalize_or_zero().to_array(),
+                    uv: uv.to_array(),
+                }
+            })
+            .collect()
+    }
+
+    /// Splits every triangle into four at its edge midpoints. Midpoints are shared between
+    /// neighbouring triangles so the result stays watertight.
+    pub fn subdivide(&self) -> Mesh {
+        let mut vertices = self.vertices.clone();
+        let mut midpoints = HashMap::new();
+        let mut midpoint = |a: u32, b: u32| {
+            *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                let (a, b) = (vertices[a as usize], vertices[b as usize]);
+                vertices.push(Vertex {
+                    pos: ((Vec3::from(a.pos) + Vec3::from(b.pos)) * 0.5).to_array(),
+                    normal: (Vec3::from(a.normal) + Vec3::from(b.normal))
+                        .normalize_or_zero()
+                        .to_array(),
+                    uv: ((Vec2::from(a.uv) + Vec2::from(b.uv)) * 0.5).to_array(),
+                });
+                vertices.len() as u32 - 1
+            })
+        };
+
+        let indices = self
+            .indices
+            .chunks_exact(3)
+            .flat_map(|triangle| {
+                let [a, b, c] = [triangle[0], triangle[1], triangle[2]];
+                let ab = midpoint(a, b);
+                let bc = midpoint(b, c);
+                let ca = midpoint(c, a);
+                [a, ab, ca, b, bc, ab, c, ca, bc, ab, bc, ca]
+            })
+            .collect();
+
+        Mesh { vertices, indices }
+    }
+
+    /// Post-process applied to every mesh before it is uploaded: welds duplicated vertices,
+    /// generates the normals the source left zero, and reorders vertices by first use.
+    pub fn optimize(&mut self) {
+        self.weld();
+
+        // Merged glTF primitives may mix authored and missing normals, so only the missing ones
+        // are replaced.
+        if self.vertices.iter().any(|vertex| vertex.normal == [0.0; 3]) {
+            let authored = self
+                .vertices
+                .iter()
+                .map(|vertex| vertex.normal)
+                .collect::<Vec<_>>();
+            self.generate_normals();
+            for (vertex, normal) in self.vertices.iter_mut().zip(authored) {
+                if normal != [0.0; 3] {
+                    vertex.normal = normal;
+                }
+            }
+        }
+
+        self.optimize_vertex_fetch();
+    }
+
+    /// Merges vertices whose attributes are bitwise identical.
+    pub fn weld(&mut self) {
+        let mut remap = HashMap::with_capacity(self.vertices.len());
+        let mut vertices = Vec::with_capacity(self.vertices.len());
+
+        let new_indices = self
+            .vertices
+            .iter()
+            .map(|vertex| {
+                *remap.entry(vertex.key()).or_insert_with(|| {
+                    vertices.push(*vertex);
+                    vertices.len() as u32 - 1
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for index in &mut self.indices {
+            *index = new_indices[*index as usize];
+        }
+        self.vertices = vertices;
+    }
+
+    /// Replaces the vertex normals with smooth, area-weighted face normals.
+    pub fn generate_normals(&mut self) {
+        let mut normals = vec![Vec3::ZERO; self.vertices.len()];
+
+        for triangle in self.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| Vec3::from(self.vertices[triangle[i] as usize].pos));
+            // The cross product's length is twice the triangle area, which does the weighting.
+            let face_normal = (b - a).cross(c - a);
+
+            for &index in triangle {
+                normals[index as usize] += face_normal;
+            }
+        }
+
+        for (vertex, normal) in self.vertices.iter_mut().zip(normals) {
+            vertex.normal = normal.normalize_or_zero().to_array();
+        }
+    }
+
+    /// Reorders vertices into the order the index buffer first references them, dropping
+    /// unreferenced vertices, so neighbouring triangles fetch neighbouring memory.
+    pub fn optimize_vertex_fetch(&mut self) {
+        let mut remap = vec![u32::MAX; self.vertices.len()];
+        let mut vertices = Vec::with_capacity(self.vertices.len());
+
+        for index in &mut self.indices {
+            let new_index = &mut remap[*index as usize];
+            if *new_index == u32::MAX {
+                *new_index = vertices.len() as u32;
+                vertices.push(self.vertices[*index as usize]);
+            }
+            *index = *new_index;
+        }
+
+        self.vertices = vertices;
+    }
+}
+
+impl Vertex {
+    fn key(&self) -> [u32; 8] {
+        let mut key = [0; 8];
+        for (key, value) in key
+            .iter_mut()
+            .zip(self.pos.iter().chain(&self.normal).chain(&self.uv))
+        {
+            *key = value.to_bits();
+        }
+        key
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::primitives;
+
+    #[test]
+    fn sampling_an_empty_mesh_gives_no_points() {
+        let mut random = Random::new(1);
+        assert!(Mesh::default().sample_surface(100, &mut random).is_empty());
+
+        // Vertices alone don't make a surface either.
+        let mut points = primitives::triangle();
+        points.indices.clear();
+        assert!(points.sample_surface(100, &mut random).is_empty());
+    }
+
+    #[test]
+    fn samples_lie_on_the_surface() {
+        let mut random = Random::new(1);
+        let plane = primitives::plane(vec2(2.0, 1.0));
+        let samples = plane.sample_surface(1000, &mut random);
+        assert_eq!(samples.len(), 1000);
+
+        for sample in &samples {
+            let [x, y, z] = sample.pos;
+            assert_eq!(y, 0.0);
+            assert!(x.abs() <= 2.0 && z.abs() <= 1.0, "{:?}", sample.pos);
+            assert_eq!(sample.normal, [0.0, 1.0, 0.0]);
+        }
+
+        // Both halves of the plane have the same area, so they get about the same share.
+        let left = samples.iter().filter(|sample| sample.pos[0] < 0.0).count();
+        assert!((400..600).contains(&left), "{}", left);
+    }
+
+    fn vertex(pos: [f32; 3], normal: [f32; 3]) -> Vertex {
+        Vertex {
+            pos,
+            normal,
+            uv: [0.0; 2],
+        }
+    }
+
+    #[test]
+    fn weld_merges_bitwise_duplicates() {
+        let a = vertex([0.0, 0.0, 0.0], [0.0, 1.0, 0.0]);
+        let b = vertex([1.0, 0.0, 0.0], [0.0, 1.0, 0.0]);
+        let c = vertex([0.0, 0.0, 1.0], [0.0, 1.0, 0.0]);
+        // Equal to `a` as floats, but not bitwise.
+        let negative_zero = vertex([-0.0, 0.0, 0.0], [0.0, 1.0, 0.0]);
+        let mut mesh = Mesh {
+            vertices: vec![a, b, a, c, b, negative_zero],
+            indices: vec![0, 1, 3, 2, 3, 4, 5, 1, 3],
+        };
+        mesh.weld();
+
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(mesh.indices, [0, 1, 2, 0, 2, 1, 3, 1, 2]);
+        assert_eq!(mesh.vertices[3].pos[0].to_bits(), (-0.0f32).to_bits());
+    }
+
+    #[test]
+    fn generated_plane_normals_point_up() {
+        let mut plane = primitives::plane(vec2(2.0, 1.0));
+        for vertex in &mut plane.vertices {
+            vertex.normal = [0.0; 3];
+        }
+        plane.generate_normals();
+
+        for vertex in &plane.vertices {
+            assert!(
+                Vec3::from(vertex.normal).abs_diff_eq(Vec3::Y, 1e-6),
+                "{:?}",
+                vertex.normal
+            );
+        }
+    }
+
+    #[test]
+    fn vertex_fetch_order_follows_first_use() {
+        // Vertices are told apart by x; vertex 1 is never used.
+        let mut mesh = Mesh {
+            vertices: (0..5)
+                .map(|i| vertex([i as f32, 0.0, 0.0], [0.0, 1.0, 0.0]))
+                .collect(),
+            indices: vec![4, 2, 0, 0, 2, 3],
+        };
+        mesh.optimize_vertex_fetch();
+
+        let order = mesh
+            .vertices
+            .iter()
+            .map(|vertex| vertex.pos[0])
+            .collect::<Vec<_>>();
+        assert_eq!(order, [4.0, 2.0, 0.0, 3.0]);
+        assert_eq!(mesh.indices, [0, 1, 2, 2, 1, 3]);
+    }
+
+    #[test]
+    fn optimize_only_generates_missing_normals() {
+        // One triangle with authored normals that differ from its face normal, one without.
+        let mut mesh = Mesh {
+            vertices: vec![
+                vertex([2.0, 0.0, 0.0], [0.0, 0.0, -1.0]),
+                vertex([2.0, 0.0, 1.0], [0.0, 0.0, -1.0]),
+                vertex([3.0, 0.0, 0.0], [0.0, 0.0, -1.0]),
+                vertex([0.0, 0.0, 0.0], [0.0; 3]),
+                vertex([0.0, 0.0, 1.0], [0.0; 3]),
+                vertex([1.0, 0.0, 0.0], [0.0; 3]),
+            ],
+            indices: vec![0, 1, 2, 3, 4, 5],
+        };
+        mesh.optimize();
+
+        assert_eq!(mesh.vertices.len(), 6);
+        for (i, vertex) in mesh.vertices.iter().enumerate() {
+            let expected = if i < 3 {
+                [0.0, 0.0, -1.0]
+            } else {
+                [0.0, 1.0, 0.0]
+            };
+            assert_eq!(vertex.normal, expected, "vertex {}", i);
+        }
+    }
+}
@@ -0,0 +1,126 @@
+//! Point clouds drawn as small spheres by the point intersection shader, for looking at scanned
+//! data such as LiDAR or photogrammetry output inside the ray tracer.
+
+use std::{fs, path::Path};
+
+use ash::vk;
+use glam::Vec3;
+
+use crate::{mesh::Mesh, random::Random};
+
+/// One point, mirrored by `Point` in the shader crate.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct Point {
+    pub position: [f32; 3],
+    pub radius: f32,
+    /// rgb: colour, a: unused.
+    pub color: [f32; 4],
+}
+
+impl Point {
+    fn bounds(&self) -> (Vec3, Vec3) {
+        let position = Vec3::from(self.position);
+        (position - self.radius, position + self.radius)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct PointCloud {
+    pub points: Vec<Point>,
+}
+
+impl PointCloud {
+    pub fn point_count(&self) -> usize {
+        self.points.len()
+    }
+
+    /// Axis-aligned bounds of every point, including its radius.
+    pub fn bounds(&self) -> (Vec3, Vec3) {
+        self.points.iter().fold(
+            (Vec3::splat(f32::INFINITY), Vec3::splat(f32::NEG_INFINITY)),
+            |(min, max), point| {
+                let (point_min, point_max) = point.bounds();
+                (min.min(point_min), max.max(point_max))
+            },
+        )
+    }
+
+    /// One box per point, in the layout BLAS builds read for AABB geometry.
+    pub fn aabbs(&self) -> Vec<vk::AabbPositionsKHR> {
+        self.points
+            .iter()
+            .map(|point| {
+                let (min, max) = point.bounds();
+                vk::AabbPositionsKHR {
+                    min_x: min.x,
+                    min_y: min.y,
+                    min_z: min.z,
+                    max_x: max.x,
+                    max_y: max.y,
+                    max_z: max.z,
+                }
+            })
+            .collect()
+    }
+
+    /// Bytes the point and box buffers of this cloud take up on the device.
+    pub fn geometry_size(&self) -> usize {
+        self.points.len()
+            * (std::mem::size_of::<Point>() + std::mem::size_of::<vk::AabbPositionsKHR>())
+    }
+
+    /// Loads a text file with one `x y z` or `x y z r g b` point per line, colours in `0..=255`,
+    /// as exported by most scanning tools. Empty lines and lines starting with `#` are skipped.
+    /// Every point gets `radius`. Returns `None` if the file does not exist.
+    pub fn load_xyz(path: &Path, radius: f32) -> Option<Self> {
+        if !path.exists() {
+            return None;
+        }
+
+        let text = fs::read_to_string(path).expect("failed to read point cloud");
+        let points = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                let values = line
+                    .split_whitespace()
+                    .map(|value| value.parse::<f32>().expect("invalid number in point cloud"))
+                    .collect::<Vec<_>>();
+
+                let color = match values[..] {
+                    [_, _, _] => [1.0; 3],
+                    [_, _, _, r, g, b] => [r / 255.0, g / 255.0, b / 255.0],
+                    _ => panic!("point cloud lines must have 3 or 6 values: {:?}", line),
+                };
+
+                Point {
+                    position: [values[0], values[1], values[2]],
+                    radius,
+                    color: [color[0], color[1], color[2], 1.0],
+                }
+            })
+            .collect();
+
+        Some(Self { points })
+    }
+
+    /// Scatters `count` points uniformly over the surface of `mesh`, coloured by their normal,
+    /// as a stand-in for a scan of it.
+    pub fn sample_mesh(mesh: &Mesh, count: usize, radius: f32) -> Self {
+        let points = mesh
+            .sample_surface(count, &mut Random::new(1))
+            .into_iter()
+            .map(|sample| Point {
+                position: sample.pos,
+                radius,
+                color: (Vec3::from(sample.normal) * 0.5 + 0.5)
+                    .extend(1.0)
+                    .to_array(),
+            })
+            .collect();
+
+        Self { points }
+    }
+}
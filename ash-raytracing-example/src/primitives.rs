@@ -0,0 +1,364 @@
+//! Procedural triangle meshes, so scenes can be composed without external asset files.
+//!
+//! Every generator is centred on the origin and produces outward-facing normals and
+//! texture coordinates in `[0, 1]`.
+
+use std::{
+    collections::HashMap,
+    f32::consts::{PI, TAU},
+};
+
+use glam::{vec2, vec3, Vec2, Vec3};
+
+use crate::mesh::{Mesh, Vertex};
+
+fn vertex(pos: Vec3, normal: Vec3, uv: Vec2) -> Vertex {
+    Vertex {
+        pos: pos.to_array(),
+        normal: normal.to_array(),
+        uv: uv.to_array(),
+    }
+}
+
+/// Indexes a `(columns + 1) x (rows + 1)` vertex grid as two triangles per cell.
+fn grid_indices(columns: u32, rows: u32) -> Vec<u32> {
+    let mut indices = Vec::with_capacity((columns * rows * 6) as usize);
+
+    for row in 0..rows {
+        for column in 0..columns {
+            let a = row * (columns + 1) + column;
+            let b = a + columns + 1;
+            indices.extend_from_slice(&[a, b, a + 1, a + 1, b, b + 1]);
+        }
+    }
+
+    indices
+}
+
+/// The single triangle this example started out with, facing `-z`.
+pub fn triangle() -> Mesh {
+    let normal = vec3(0.0, 0.0, -1.0);
+
+    Mesh {
+        vertices: vec![
+            vertex(vec3(-0.5, -0.5, 0.0), normal, vec2(0.0, 0.0)),
+            vertex(vec3(0.0, 0.5, 0.0), normal, vec2(0.5, 1.0)),
+            vertex(vec3(0.5, -0.5, 0.0), normal, vec2(1.0, 0.0)),
+        ],
+        indices: vec![0, 1, 2],
+    }
+}
+
+/// Latitude/longitude sphere around the `y` axis.
+pub fn uv_sphere(radius: f32, segments: u32, rings: u32) -> Mesh {
+    let mut vertices = Vec::with_capacity(((segments + 1) * (rings + 1)) as usize);
+
+    for ring in 0..=rings {
+        let v = ring as f32 / rings as f32;
+        let theta = v * PI;
+
+        for segment in 0..=segments {
+            let u = segment as f32 / segments as f32;
+            let phi = u * TAU;
+
+            let normal = vec3(
+                theta.sin() * phi.cos(),
+                theta.cos(),
+                theta.sin() * phi.sin(),
+            );
+            vertices.push(vertex(normal * radius, normal, vec2(u, v)));
+        }
+    }
+
+    // Drop the zero-area triangles that touch the poles.
+    let indices = grid_indices(segments, rings)
+        .chunks_exact(3)
+        .filter(|triangle| {
+            let pole = |i: u32| i <= segments || i >= rings * (segments + 1);
+            triangle.iter().filter(|&&i| pole(i)).count() < 2
+        })
+        .flatten()
+        .copied()
+        .collect();
+
+    Mesh { vertices, indices }
+}
+
+/// Subdivided icosahedron, which spreads triangles more evenly than `uv_sphere`.
+pub fn icosphere(radius: f32, subdivisions: u32) -> Mesh {
+    let t = (1.0 + 5f32.sqrt()) / 2.0;
+
+    let mut positions: Vec<Vec3> = [
+        vec3(-1.0, t, 0.0),
+        vec3(1.0, t, 0.0),
+        vec3(-1.0, -t, 0.0),
+        vec3(1.0, -t, 0.0),
+        vec3(0.0, -1.0, t),
+        vec3(0.0, 1.0, t),
+        vec3(0.0, -1.0, -t),
+        vec3(0.0, 1.0, -t),
+        vec3(t, 0.0, -1.0),
+        vec3(t, 0.0, 1.0),
+        vec3(-t, 0.0, -1.0),
+        vec3(-t, 0.0, 1.0),
+    ]
+    .iter()
+    .map(|p| p.normalize())
+    .collect();
+
+    let mut indices: Vec<u32> = vec![
+        0, 11, 5, 0, 5, 1, 0, 1, 7, 0, 7, 10, 0, 10, 11, 1, 5, 9, 5, 11, 4, 11, 10, 2, 10, 7, 6, 7,
+        1, 8, 3, 9, 4, 3, 4, 2, 3, 2, 6, 3, 6, 8, 3, 8, 9, 4, 9, 5, 2, 4, 11, 6, 2, 10, 8, 6, 7, 9,
+        8, 1,
+    ];
+
+    for _ in 0..subdivisions {
+        let mut midpoints = HashMap::new();
+        let mut midpoint = |a: u32, b: u32| {
+            *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                positions.push(((positions[a as usize] + positions[b as usize]) * 0.5).normalize());
+                positions.len() as u32 - 1
+            })
+        };
+
+        indices = indices
+            .chunks_exact(3)
+            .flat_map(|triangle| {
+                let [a, b, c] = [triangle[0], triangle[1], triangle[2]];
+                let ab = midpoint(a, b);
+                let bc = midpoint(b, c);
+                let ca = midpoint(c, a);
+                [a, ab, ca, b, bc, ab, c, ca, bc, ab, bc, ca]
+            })
+            .collect();
+    }
+
+    let vertices = positions
+        .into_iter()
+        .map(|normal| {
+            let uv = vec2(
+                0.5 + normal.z.atan2(normal.x) / TAU,
+                normal.y.clamp(-1.0, 1.0).acos() / PI,
+            );
+            vertex(normal * radius, normal, uv)
+        })
+        .collect();
+
+    Mesh { vertices, indices }
+}
+
+/// Axis-aligned box with flat-shaded faces.
+pub fn cuboid(half_extents: Vec3) -> Mesh {
+    let mut mesh = Mesh::default();
+
+    for (normal, up) in [
+        (Vec3::X, Vec3::Y),
+        (Vec3::NEG_X, Vec3::Y),
+        (Vec3::Y, Vec3::Z),
+        (Vec3::NEG_Y, Vec3::Z),
+        (Vec3::Z, Vec3::Y),
+        (Vec3::NEG_Z, Vec3::Y),
+    ] {
+        let right = up.cross(normal);
+        let first = mesh.vertices.len() as u32;
+
+        for uv in [
+            vec2(0.0, 0.0),
+            vec2(1.0, 0.0),
+            vec2(0.0, 1.0),
+            vec2(1.0, 1.0),
+        ] {
+            let corner = normal + (uv.x * 2.0 - 1.0) * right + (uv.y * 2.0 - 1.0) * up;
+            mesh.vertices
+                .push(vertex(corner * half_extents, normal, uv));
+        }
+
+        mesh.indices
+            .extend(grid_indices(1, 1).into_iter().map(|i| first + i));
+    }
+
+    mesh
+}
+
+/// Square in the `xz` plane facing `+y`.
+pub fn plane(half_size: Vec2) -> Mesh {
+    let mut vertices = Vec::with_capacity(4);
+
+    for z in 0..2 {
+        for x in 0..2 {
+            let uv = vec2(x as f32, z as f32);
+            let pos = vec3(
+                (uv.x * 2.0 - 1.0) * half_size.x,
+                0.0,
+                (uv.y * 2.0 - 1.0) * half_size.y,
+            );
+            vertices.push(vertex(pos, Vec3::Y, uv));
+        }
+    }
+
+    Mesh {
+        vertices,
+        indices: grid_indices(1, 1),
+    }
+}
+
+/// Torus around the `z` axis.
+pub fn torus(
+    major_radius: f32,
+    minor_radius: f32,
+    major_segments: u32,
+    minor_segments: u32,
+) -> Mesh {
+    let mut vertices = Vec::with_capacity(((major_segments + 1) * (minor_segments + 1)) as usize);
+
+    for minor in 0..=minor_segments {
+        let v = minor as f32 / minor_segments as f32;
+        let (sin_v, cos_v) = (v * TAU).sin_cos();
+
+        for major in 0..=major_segments {
+            let u = major as f32 / major_segments as f32;
+            let (sin_u, cos_u) = (u * TAU).sin_cos();
+
+            let normal = vec3(cos_v * cos_u, cos_v * sin_u, sin_v);
+            let center = vec3(major_radius * cos_u, major_radius * sin_u, 0.0);
+            vertices.push(vertex(center + normal * minor_radius, normal, vec2(u, v)));
+        }
+    }
+
+    Mesh {
+        vertices,
+        indices: grid_indices(major_segments, minor_segments),
+    }
+}
+
+/// Blade of grass standing on the origin along `y`, `width` wide at the root and narrowing to a
+/// point at `height`. It bends towards `+z` by `bend` at the tip and faces `-z`. Both sides are
+/// the same triangles, so it relies on back-face culling being off.
+pub fn grass_blade(width: f32, height: f32, bend: f32, segments: u32) -> Mesh {
+    let mut vertices = Vec::with_capacity((segments * 2 + 1) as usize);
+
+    for i in 0..segments {
+        let v = i as f32 / segments as f32;
+        let half_width = width * 0.5 * (1.0 - v);
+        let center = vec3(0.0, v * height, bend * v * v);
+        vertices.push(vertex(
+            center - Vec3::X * half_width,
+            Vec3::ZERO,
+            vec2(0.0, v),
+        ));
+        vertices.push(vertex(
+            center + Vec3::X * half_width,
+            Vec3::ZERO,
+            vec2(1.0, v),
+        ));
+    }
+    vertices.push(vertex(vec3(0.0, height, bend), Vec3::ZERO, vec2(0.5, 1.0)));
+
+    let mut indices = Vec::with_capacity((segments * 6) as usize);
+    for i in 0..segments - 1 {
+        let a = i * 2;
+        indices.extend_from_slice(&[a, a + 2, a + 1, a + 1, a + 2, a + 3]);
+    }
+    let last = (segments - 1) * 2;
+    indices.extend_from_slice(&[last, segments * 2, last + 1]);
+
+    let mut mesh = Mesh { vertices, indices };
+    mesh.generate_normals();
+    mesh
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checks the triangle count, index range, unit normals and texture coordinates in `[0, 1]`.
+    /// Returns the vertex positions and normals.
+    fn check(mesh: &Mesh, triangle_count: usize) -> Vec<(Vec3, Vec3)> {
+        assert_eq!(mesh.indices.len() % 3, 0);
+        assert_eq!(mesh.triangle_count(), triangle_count);
+        assert!(mesh
+            .indices
+            .iter()
+            .all(|&index| (index as usize) < mesh.vertices.len()));
+
+        for vertex in &mesh.vertices {
+            let length = Vec3::from(vertex.normal).length();
+            assert!((length - 1.0).abs() < 1e-5, "{:?}", vertex.normal);
+            assert!(vertex.uv.iter().all(|uv| (0.0..=1.0).contains(uv)));
+        }
+
+        mesh.vertices
+            .iter()
+            .map(|vertex| (Vec3::from(vertex.pos), Vec3::from(vertex.normal)))
+            .collect()
+    }
+
+    #[test]
+    fn triangle_faces_negative_z() {
+        for (_, normal) in check(&triangle(), 1) {
+            assert_eq!(normal, Vec3::NEG_Z);
+        }
+    }
+
+    #[test]
+    fn uv_sphere_normals_point_outwards() {
+        // Each ring of cells has two triangles per segment, except at the poles.
+        let (segments, rings) = (12, 6);
+        for (pos, normal) in check(&uv_sphere(0.5, segments, rings), 2 * 12 * (6 - 1)) {
+            assert!((pos.length() - 0.5).abs() < 1e-5);
+            assert!(normal.dot(pos) > 0.0);
+        }
+    }
+
+    #[test]
+    fn icosphere_normals_point_outwards() {
+        for subdivisions in 0..3 {
+            let mesh = icosphere(2.0, subdivisions);
+            assert_eq!(mesh.vertices.len(), 10 * 4usize.pow(subdivisions) + 2);
+            for (pos, normal) in check(&mesh, 20 * 4usize.pow(subdivisions)) {
+                assert!((pos.length() - 2.0).abs() < 1e-5);
+                assert!(normal.dot(pos) > 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn cuboid_normals_point_outwards() {
+        let half_extents = vec3(1.0, 2.0, 3.0);
+        for (pos, normal) in check(&cuboid(half_extents), 12) {
+            assert_eq!(pos.abs(), half_extents);
+            assert!(normal.dot(pos) > 0.0);
+        }
+    }
+
+    #[test]
+    fn plane_faces_up() {
+        for (pos, normal) in check(&plane(vec2(2.0, 1.0)), 2) {
+            assert_eq!(pos.y, 0.0);
+            assert_eq!(normal, Vec3::Y);
+        }
+    }
+
+    #[test]
+    fn torus_normals_point_outwards() {
+        let (major_radius, minor_radius) = (1.0, 0.25);
+        let (major_segments, minor_segments) = (16, 8);
+        let mesh = torus(major_radius, minor_radius, major_segments, minor_segments);
+        for (pos, normal) in check(&mesh, 2 * 16 * 8) {
+            // The closest point on the circle the tube winds around.
+            let center = vec3(pos.x, pos.y, 0.0).normalize() * major_radius;
+            assert!(((pos - center).length() - minor_radius).abs() < 1e-5);
+            assert!(normal.dot(pos - center) > 0.0);
+        }
+    }
+
+    #[test]
+    fn grass_blade_faces_negative_z() {
+        // Two triangles per segment, except for the single one at the tip.
+        let segments = 4;
+        for (pos, normal) in check(&grass_blade(0.1, 1.0, 0.3, segments), 2 * 4 - 1) {
+            assert!((0.0..=1.0).contains(&pos.y));
+            assert!(normal.z < 0.0, "{:?}", normal);
+        }
+    }
+}
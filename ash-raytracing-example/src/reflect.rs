@@ -0,0 +1,158 @@
+//! Descriptor set layout derived from the compiled shader module, so adding a binding to the
+//! shader crate doesn't need a matching hand-written layout on the host.
+
+use std::collections::BTreeMap;
+
+use ash::vk;
+use rspirv::{
+    dr::{Instruction, Operand},
+    spirv::{Decoration, ExecutionModel, Op, StorageClass},
+};
+
+/// Returns the bindings of descriptor set `set` used by any entry point in `spirv`, ordered by
+/// binding number.
+///
+/// Uniform buffers listed in `dynamic_uniform_buffers` become `UNIFORM_BUFFER_DYNAMIC`; SPIR-V
+/// can't tell the two apart.
+pub fn descriptor_set_layout_bindings(
+    spirv: &[u8],
+    set: u32,
+    dynamic_uniform_buffers: &[u32],
+) -> Vec<vk::DescriptorSetLayoutBinding> {
+    let module = rspirv::dr::load_bytes(spirv).expect("failed to parse shader module");
+
+    let mut descriptor_sets = BTreeMap::new();
+    let mut bindings = BTreeMap::new();
+    for annotation in &module.annotations {
+        if let [Operand::IdRef(target), Operand::Decoration(decoration), Operand::LiteralInt32(value)] =
+            annotation.operands[..]
+        {
+            match decoration {
+                Decoration::DescriptorSet => {
+                    descriptor_sets.insert(target, value);
+                }
+                Decoration::Binding => {
+                    bindings.insert(target, value);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let definitions = module
+        .types_global_values
+        .iter()
+        .filter_map(|instruction| Some((instruction.result_id?, instruction)))
+        .collect::<BTreeMap<_, _>>();
+
+    // Several entry points using the same binding each declare their own variable.
+    let mut layout = BTreeMap::<u32, vk::DescriptorSetLayoutBinding>::new();
+    for entry_point in &module.entry_points {
+        let Operand::ExecutionModel(execution_model) = entry_point.operands[0] else {
+            continue;
+        };
+        let stage = shader_stage(execution_model);
+
+        // Since SPIR-V 1.4 the interface lists every global variable the entry point uses.
+        for operand in &entry_point.operands[3..] {
+            let Operand::IdRef(variable) = *operand else {
+                continue;
+            };
+            if descriptor_sets.get(&variable) != Some(&set) {
+                continue;
+            }
+            let binding = bindings[&variable];
+
+            let descriptor_type = descriptor_type(definitions[&variable], &definitions);
+            let descriptor_type = if descriptor_type == vk::DescriptorType::UNIFORM_BUFFER
+                && dynamic_uniform_buffers.contains(&binding)
+            {
+                vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC
+            } else {
+                descriptor_type
+            };
+
+            let layout_binding = layout.entry(binding).or_insert(
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(binding)
+                    .descriptor_type(descriptor_type)
+                    .descriptor_count(1)
+                    .build(),
+            );
+            assert_eq!(
+                layout_binding.descriptor_type, descriptor_type,
+                "binding {} is declared with different types",
+                binding
+            );
+            layout_binding.stage_flags |= stage;
+        }
+    }
+
+    layout.into_values().collect()
+}
+
+/// Counts the descriptors of each type in `bindings`, for sizing a descriptor pool that holds
+/// one set of them.
+pub fn descriptor_pool_sizes(
+    bindings: &[vk::DescriptorSetLayoutBinding],
+) -> Vec<vk::DescriptorPoolSize> {
+    let mut sizes = Vec::<vk::DescriptorPoolSize>::new();
+    for binding in bindings {
+        match sizes
+            .iter_mut()
+            .find(|size| size.ty == binding.descriptor_type)
+        {
+            Some(size) => size.descriptor_count += binding.descriptor_count,
+            None => sizes.push(vk::DescriptorPoolSize {
+                ty: binding.descriptor_type,
+                descriptor_count: binding.descriptor_count,
+            }),
+        }
+    }
+    sizes
+}
+
+fn descriptor_type(
+    variable: &Instruction,
+    definitions: &BTreeMap<u32, &Instruction>,
+) -> vk::DescriptorType {
+    let pointer = definitions[&variable.result_type.expect("variable without a type")];
+    let [Operand::StorageClass(storage_class), Operand::IdRef(pointee)] = pointer.operands[..]
+    else {
+        panic!("descriptor variable is not a pointer");
+    };
+
+    match storage_class {
+        StorageClass::StorageBuffer => return vk::DescriptorType::STORAGE_BUFFER,
+        StorageClass::Uniform => return vk::DescriptorType::UNIFORM_BUFFER,
+        _ => {}
+    }
+
+    let pointee = definitions[&pointee];
+    match pointee.class.opcode {
+        Op::TypeAccelerationStructureKHR => vk::DescriptorType::ACCELERATION_STRUCTURE_KHR,
+        Op::TypeSampledImage => vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+        Op::TypeSampler => vk::DescriptorType::SAMPLER,
+        // The sixth operand is 1 for images used with a sampler and 2 for storage images.
+        Op::TypeImage => match pointee.operands[5] {
+            Operand::LiteralInt32(2) => vk::DescriptorType::STORAGE_IMAGE,
+            _ => vk::DescriptorType::SAMPLED_IMAGE,
+        },
+        opcode => panic!("unsupported descriptor type {:?}", opcode),
+    }
+}
+
+fn shader_stage(execution_model: ExecutionModel) -> vk::ShaderStageFlags {
+    match execution_model {
+        ExecutionModel::Vertex => vk::ShaderStageFlags::VERTEX,
+        ExecutionModel::Fragment => vk::ShaderStageFlags::FRAGMENT,
+        ExecutionModel::GLCompute => vk::ShaderStageFlags::COMPUTE,
+        ExecutionModel::RayGenerationKHR => vk::ShaderStageFlags::RAYGEN_KHR,
+        ExecutionModel::IntersectionKHR => vk::ShaderStageFlags::INTERSECTION_KHR,
+        ExecutionModel::AnyHitKHR => vk::ShaderStageFlags::ANY_HIT_KHR,
+        ExecutionModel::ClosestHitKHR => vk::ShaderStageFlags::CLOSEST_HIT_KHR,
+        ExecutionModel::MissKHR => vk::ShaderStageFlags::MISS_KHR,
+        ExecutionModel::CallableKHR => vk::ShaderStageFlags::CALLABLE_KHR,
+        _ => panic!("unsupported execution model {:?}", execution_model),
+    }
+}
@@ -0,0 +1,65 @@
+//! Scatters copies of a mesh over a surface as TLAS instances, for vegetation and for seeing how
+//! the TLAS copes with many instances.
+
+use glam::{Affine3A, Quat, Vec3};
+
+use crate::{displacement::HeightMap, mesh::Mesh, random::Random};
+
+/// Candidates rejected by the density map before giving up, per requested instance.
+const MAX_ATTEMPTS_PER_INSTANCE: usize = 16;
+
+#[derive(Clone, Copy, Debug)]
+pub struct ScatterOptions {
+    pub count: usize,
+    /// Every copy gets a uniform scale picked from this range.
+    pub min_scale: f32,
+    pub max_scale: f32,
+    /// How far copies lean from straight up (`+y`) towards the surface normal, from 0 to 1.
+    pub normal_alignment: f32,
+    pub seed: u32,
+}
+
+/// Places up to `options.count` copies on `surface`, each turned by a random angle around its
+/// up axis. With a `density` map, a candidate position is kept with the probability the map
+/// gives at its texture coordinate, so fewer copies may come back for sparse maps.
+///
+/// The transforms are in the surface's object space.
+pub fn scatter(
+    surface: &Mesh,
+    density: Option<&HeightMap>,
+    options: &ScatterOptions,
+) -> Vec<Affine3A> {
+    let mut random = Random::new(options.seed);
+    let mut transforms = Vec::with_capacity(options.count);
+
+    for _ in 0..MAX_ATTEMPTS_PER_INSTANCE {
+        let remaining = options.count - transforms.len();
+        if remaining == 0 {
+            break;
+        }
+
+        for sample in surface.sample_surface(remaining, &mut random) {
+            if let Some(density) = density {
+                if random.next_f32() >= density.sample(sample.uv.into()) {
+                    continue;
+                }
+            }
+
+            let up = Vec3::Y
+                .lerp(Vec3::from(sample.normal), options.normal_alignment)
+                .try_normalize()
+                .unwrap_or(Vec3::Y);
+            let rotation = Quat::from_rotation_arc(Vec3::Y, up)
+                * Quat::from_rotation_y(random.range(0.0, std::f32::consts::TAU));
+            let scale = random.range(options.min_scale, options.max_scale);
+
+            transforms.push(Affine3A::from_scale_rotation_translation(
+                Vec3::splat(scale),
+                rotation,
+                Vec3::from(sample.pos),
+            ));
+        }
+    }
+
+    transforms
+}
@@ -0,0 +1,234 @@
+//! The scenes that can be rendered: the built-in ones, or one loaded by `gltf_scene`.
+
+use std::path::Path;
+
+use ash::vk;
+use clap::ValueEnum;
+use glam::{vec2, vec3, Affine3A, Vec3};
+
+use crate::{
+    curves::{self, Curves},
+    displacement::{self, HeightMap},
+    mesh::Mesh,
+    points::PointCloud,
+    primitives,
+    random::Random,
+    scatter::{self, ScatterOptions},
+    Camera, Shape, VISIBLE_TO_ALL,
+};
+
+// Height map applied to the ground plane if the file exists.
+const DISPLACEMENT_MAP: &str = "displacement.png";
+const DISPLACEMENT_SUBDIVISIONS: u32 = 7;
+const DISPLACEMENT_SCALE: f32 = 0.25;
+// Point cloud shown instead of the sampled torus if the file exists; see `PointCloud::load_xyz`.
+const POINT_CLOUD_PATH: &str = "points.xyz";
+const POINT_RADIUS: f32 = 0.006;
+// Grass blades scattered over the ground, thinned out by the density map if the file exists.
+const GRASS_COUNT: usize = 20000;
+const GRASS_DENSITY_MAP: &str = "grass_density.png";
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum SceneKind {
+    /// Primitives on a ground plane.
+    Basic,
+    /// The basic scene with the heavier geometry added: 20000 grass blade instances, a fur ball
+    /// and a point cloud.
+    Showcase,
+    /// A ground plane two kilometres across seen at a grazing angle. Shows the
+    /// self-intersection that a fixed `--ray-epsilon` causes far from the camera.
+    Grazing,
+}
+
+/// Geometry, instances and camera of a scene.
+pub struct Scene {
+    /// (mesh, BLAS build flags)
+    pub meshes: Vec<(Mesh, vk::BuildAccelerationStructureFlagsKHR)>,
+    /// (curves, BLAS build flags). Their BLASes follow the meshes'.
+    pub curve_sets: Vec<(Curves, vk::BuildAccelerationStructureFlagsKHR)>,
+    /// (points, BLAS build flags). Their BLASes follow the curve sets'.
+    pub point_clouds: Vec<(PointCloud, vk::BuildAccelerationStructureFlagsKHR)>,
+    /// (shape, object-to-world transform, color, visibility)
+    pub(crate) instances: Vec<(Shape, Affine3A, [f32; 3], u8)>,
+    pub(crate) camera: Camera,
+}
+
+impl Scene {
+    /// Builds `kind`. Meshes that are only built once ask for `fast_trace`; the ones meant to
+    /// show off a quick rebuild ask for `fast_build`.
+    pub fn builtin(
+        kind: SceneKind,
+        fast_trace: vk::BuildAccelerationStructureFlagsKHR,
+        fast_build: vk::BuildAccelerationStructureFlagsKHR,
+    ) -> Self {
+        match kind {
+            SceneKind::Basic => primitives_on_ground(false, fast_trace, fast_build),
+            SceneKind::Showcase => primitives_on_ground(true, fast_trace, fast_build),
+            SceneKind::Grazing => grazing(fast_trace),
+        }
+    }
+}
+
+/// The basic scene, plus grass, fur and points if `showcase` is set.
+fn primitives_on_ground(
+    showcase: bool,
+    fast_trace: vk::BuildAccelerationStructureFlagsKHR,
+    fast_build: vk::BuildAccelerationStructureFlagsKHR,
+) -> Scene {
+    let ground = primitives::plane(vec2(4.0, 4.0));
+    let ground = match HeightMap::load(Path::new(DISPLACEMENT_MAP)) {
+        Some(height_map) => {
+            let displaced = displacement::displace(
+                &ground,
+                &height_map,
+                DISPLACEMENT_SUBDIVISIONS,
+                DISPLACEMENT_SCALE,
+            );
+            println!(
+                "Displaced ground: {} -> {} triangles, {} KiB -> {} KiB of geometry",
+                ground.triangle_count(),
+                displaced.triangle_count(),
+                ground.geometry_size() / 1024,
+                displaced.geometry_size() / 1024,
+            );
+            displaced
+        }
+        None => ground,
+    };
+
+    let grass = if showcase {
+        let grass = scatter::scatter(
+            &ground,
+            HeightMap::load(Path::new(GRASS_DENSITY_MAP)).as_ref(),
+            &ScatterOptions {
+                count: GRASS_COUNT,
+                min_scale: 0.6,
+                max_scale: 1.4,
+                normal_alignment: 0.5,
+                seed: 1,
+            },
+        );
+        println!("Scattered {} grass blades over the ground", grass.len());
+        grass
+    } else {
+        Vec::new()
+    };
+
+    let mut meshes = vec![
+        (primitives::triangle(), fast_build),
+        (primitives::uv_sphere(0.45, 32, 16), fast_trace),
+        (primitives::icosphere(0.45, 2), fast_trace),
+        (primitives::cuboid(Vec3::splat(0.35)), fast_trace),
+        (primitives::torus(0.32, 0.12, 32, 16), fast_build),
+        (ground, fast_trace),
+        (primitives::icosphere(0.25, 3), fast_trace),
+    ];
+    let mut curve_sets = Vec::new();
+    let mut point_clouds = Vec::new();
+    if showcase {
+        meshes.push((primitives::grass_blade(0.02, 0.15, 0.04, 4), fast_trace));
+        curve_sets.push((
+            curves::fur_ball(0.25, 4000, 0.12, 4, 0.004, 0.25),
+            fast_trace,
+        ));
+        point_clouds.push((
+            PointCloud::load_xyz(Path::new(POINT_CLOUD_PATH), POINT_RADIUS).unwrap_or_else(|| {
+                PointCloud::sample_mesh(&primitives::torus(0.32, 0.12, 32, 16), 20000, POINT_RADIUS)
+            }),
+            fast_trace,
+        ));
+    }
+
+    let ground_translation = vec3(0.0, -1.05, 1.5);
+
+    // (shape, translation, color)
+    let placed = [
+        (Shape::Mesh(0), vec3(0.0, 1.1, 0.0), [1.0, 0.0, 0.0]),
+        (Shape::Mesh(1), vec3(-1.8, -0.6, 1.0), [0.0, 1.0, 0.0]),
+        (Shape::Mesh(2), vec3(-0.6, -0.6, 1.0), [0.0, 0.0, 1.0]),
+        (Shape::Mesh(3), vec3(0.6, -0.6, 1.0), [1.0, 1.0, 0.0]),
+        (Shape::Mesh(4), vec3(1.8, -0.6, 1.0), [1.0, 0.0, 1.0]),
+        (Shape::Mesh(5), ground_translation, [0.8, 0.8, 0.8]),
+        (Shape::Mesh(6), vec3(-1.2, 0.6, 1.0), [0.6, 0.4, 0.2]),
+    ];
+    let showcase_placed = [
+        (Shape::Curves(0), vec3(-1.2, 0.6, 1.0), [0.6, 0.4, 0.2]),
+        (Shape::Points(0), vec3(1.2, 0.6, 1.0), [1.0, 1.0, 1.0]),
+    ];
+
+    let mut random = Random::new(2);
+    let instances = placed
+        .into_iter()
+        .chain(showcase_placed.into_iter().filter(|_| showcase))
+        .map(|(shape, translation, color)| {
+            (
+                shape,
+                Affine3A::from_translation(translation),
+                color,
+                VISIBLE_TO_ALL,
+            )
+        })
+        .chain(grass.iter().map(|&transform| {
+            (
+                Shape::Mesh(7),
+                Affine3A::from_translation(ground_translation) * transform,
+                [random.range(0.1, 0.3), random.range(0.4, 0.7), 0.1],
+                VISIBLE_TO_ALL,
+            )
+        }))
+        .collect();
+
+    Scene {
+        meshes,
+        curve_sets,
+        point_clouds,
+        instances,
+        camera: Camera {
+            origin: vec3(0.0, 0.0, -2.0),
+            look_at: vec3(0.0, 0.0, 0.0),
+            up: vec3(0.0, 1.0, 0.0),
+            vfov_degrees: 90.0,
+        },
+    }
+}
+
+/// Hit points a few hundred units from the camera are only accurate to a few ulps, which is far
+/// more than a small fixed offset there, so rays leaving the ground hit it again.
+/// `offset_ray` scales its offset with the position and keeps the whole plane clean.
+fn grazing(fast_trace: vk::BuildAccelerationStructureFlagsKHR) -> Scene {
+    let meshes = vec![
+        (primitives::plane(vec2(1000.0, 1000.0)), fast_trace),
+        (primitives::cuboid(Vec3::splat(0.5)), fast_trace),
+    ];
+
+    let ground = (
+        Shape::Mesh(0),
+        Affine3A::IDENTITY,
+        [0.8, 0.8, 0.8],
+        VISIBLE_TO_ALL,
+    );
+    // Boxes resting on the ground at doubling distances, to compare near and far.
+    let boxes = (0..8).map(|i| {
+        let distance = 4.0 * 2.0f32.powi(i);
+        let side = if i % 2 == 0 { -1.0 } else { 1.0 };
+        (
+            Shape::Mesh(1),
+            Affine3A::from_translation(vec3(side * distance * 0.1, 0.5, distance)),
+            [0.8, 0.3, 0.2],
+            VISIBLE_TO_ALL,
+        )
+    });
+
+    Scene {
+        meshes,
+        curve_sets: Vec::new(),
+        point_clouds: Vec::new(),
+        instances: [ground].into_iter().chain(boxes).collect(),
+        camera: Camera {
+            origin: vec3(0.0, 1.0, -2.0),
+            look_at: vec3(0.0, 0.6, 100.0),
+            up: vec3(0.0, 1.0, 0.0),
+            vfov_degrees: 60.0,
+        },
+    }
+}
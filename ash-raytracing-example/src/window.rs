@@ -0,0 +1,364 @@
+//! Presents the storage image in a window for `--window`, so a render can be watched while it
+//! keeps accumulating samples.
+
+use std::os::raw::c_char;
+
+use ash::{extensions::khr, vk};
+use raw_window_handle::{HasRawDisplayHandle, HasRawWindowHandle};
+use winit::window::Window;
+
+/// A window surface, its swapchain and the semaphores for the one frame in flight.
+pub struct Presenter {
+    surface_loader: khr::Surface,
+    swapchain_loader: khr::Swapchain,
+    surface: vk::SurfaceKHR,
+    swapchain: vk::SwapchainKHR,
+    images: Vec<vk::Image>,
+    extent: vk::Extent2D,
+    /// Whether the storage image holds linear colour rather than encoded colour.
+    linear: bool,
+    out_of_date: bool,
+    image_available: vk::Semaphore,
+    render_finished: vk::Semaphore,
+}
+
+impl Presenter {
+    /// Creates a surface for `window`, which `queue_family_index` must be able to present to.
+    /// `linear` says whether the storage image holds linear colour, as it does for EXR output.
+    pub fn new(
+        entry: &ash::Entry,
+        instance: &ash::Instance,
+        device: &ash::Device,
+        physical_device: vk::PhysicalDevice,
+        queue_family_index: u32,
+        window: &Window,
+        linear: bool,
+    ) -> Self {
+        let surface = unsafe {
+            ash_window::create_surface(
+                entry,
+                instance,
+                window.raw_display_handle(),
+                window.raw_window_handle(),
+                None,
+            )
+        }
+        .expect("failed to create window surface");
+
+        let surface_loader = khr::Surface::new(entry, instance);
+        assert!(
+            unsafe {
+                surface_loader.get_physical_device_surface_support(
+                    physical_device,
+                    queue_family_index,
+                    surface,
+                )
+            }
+            .unwrap(),
+            "the ray tracing queue can't present to the window"
+        );
+
+        let semaphore_create_info = vk::SemaphoreCreateInfo::default();
+        let mut presenter = Self {
+            surface_loader,
+            swapchain_loader: khr::Swapchain::new(instance, device),
+            surface,
+            swapchain: vk::SwapchainKHR::null(),
+            images: Vec::new(),
+            extent: vk::Extent2D::default(),
+            linear,
+            out_of_date: false,
+            image_available: unsafe { device.create_semaphore(&semaphore_create_info, None) }
+                .unwrap(),
+            render_finished: unsafe { device.create_semaphore(&semaphore_create_info, None) }
+                .unwrap(),
+        };
+
+        let size = window.inner_size();
+        presenter.create_swapchain(physical_device, size.width, size.height);
+        presenter
+    }
+
+    /// Instance extensions the surface of `window` needs.
+    pub fn required_instance_extensions(window: &Window) -> &'static [*const c_char] {
+        ash_window::enumerate_required_extensions(window.raw_display_handle())
+            .expect("unsupported windowing system")
+    }
+
+    /// Acquires the next swapchain image. Returns `None` if there is nothing to present to this
+    /// frame, for example while the swapchain is being recreated or the window is minimized.
+    pub fn acquire(
+        &mut self,
+        physical_device: vk::PhysicalDevice,
+        width: u32,
+        height: u32,
+    ) -> Option<u32> {
+        if self.out_of_date {
+            self.create_swapchain(physical_device, width, height);
+        }
+        if self.extent.width == 0 || self.extent.height == 0 {
+            return None;
+        }
+
+        match unsafe {
+            self.swapchain_loader.acquire_next_image(
+                self.swapchain,
+                u64::MAX,
+                self.image_available,
+                vk::Fence::null(),
+            )
+        } {
+            Ok((index, suboptimal)) => {
+                self.out_of_date = suboptimal;
+                Some(index)
+            }
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                self.out_of_date = true;
+                None
+            }
+            Err(error) => panic!("failed to acquire swapchain image: {}", error),
+        }
+    }
+
+    /// Records copying `image`, last written by the ray tracing shaders and in `GENERAL`
+    /// layout, onto swapchain image `index`, scaled to the window with `filter`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn cmd_blit(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        index: u32,
+        image: vk::Image,
+        width: u32,
+        height: u32,
+        filter: vk::Filter,
+    ) {
+        let swapchain_image = self.images[index as usize];
+        let subresource_range = vk::ImageSubresourceRange::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .level_count(1)
+            .layer_count(1)
+            .build();
+
+        let source_barrier = vk::ImageMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+            .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+            .old_layout(vk::ImageLayout::GENERAL)
+            .new_layout(vk::ImageLayout::GENERAL)
+            .image(image)
+            .subresource_range(subresource_range)
+            .build();
+
+        let to_transfer = vk::ImageMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::empty())
+            .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .old_layout(vk::ImageLayout::UNDEFINED)
+            .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .image(swapchain_image)
+            .subresource_range(subresource_range)
+            .build();
+
+        let to_present = vk::ImageMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::empty())
+            .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .new_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+            .image(swapchain_image)
+            .subresource_range(subresource_range)
+            .build();
+
+        let subresource = vk::ImageSubresourceLayers::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .layer_count(1)
+            .build();
+
+        let blit = vk::ImageBlit::builder()
+            .src_subresource(subresource)
+            .src_offsets([
+                vk::Offset3D::default(),
+                vk::Offset3D {
+                    x: width as i32,
+                    y: height as i32,
+                    z: 1,
+                },
+            ])
+            .dst_subresource(subresource)
+            .dst_offsets([
+                vk::Offset3D::default(),
+                vk::Offset3D {
+                    x: self.extent.width as i32,
+                    y: self.extent.height as i32,
+                    z: 1,
+                },
+            ])
+            .build();
+
+        unsafe {
+            // The submission waits for the swapchain image at the transfer stage, so the layout
+            // transition has to come after that stage too.
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::RAY_TRACING_SHADER_KHR | vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[source_barrier, to_transfer],
+            );
+
+            device.cmd_blit_image(
+                command_buffer,
+                image,
+                vk::ImageLayout::GENERAL,
+                swapchain_image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[blit],
+                filter,
+            );
+
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[to_present],
+            );
+        }
+    }
+
+    /// Submits `command_buffer`, which must end with `cmd_blit` onto image `index`, and presents
+    /// that image once it has run.
+    pub fn submit_and_present(
+        &mut self,
+        device: &ash::Device,
+        queue: vk::Queue,
+        command_buffer: vk::CommandBuffer,
+        index: u32,
+    ) {
+        let wait_semaphores = [self.image_available];
+        let signal_semaphores = [self.render_finished];
+        let command_buffers = [command_buffer];
+
+        // Tracing doesn't touch the swapchain image, so only the blit has to wait for it.
+        let submit_infos = [vk::SubmitInfo::builder()
+            .wait_semaphores(&wait_semaphores)
+            .wait_dst_stage_mask(&[vk::PipelineStageFlags::TRANSFER])
+            .command_buffers(&command_buffers)
+            .signal_semaphores(&signal_semaphores)
+            .build()];
+
+        unsafe { device.queue_submit(queue, &submit_infos, vk::Fence::null()) }
+            .expect("Failed to execute queue submit.");
+
+        let swapchains = [self.swapchain];
+        let image_indices = [index];
+        let present_info = vk::PresentInfoKHR::builder()
+            .wait_semaphores(&signal_semaphores)
+            .swapchains(&swapchains)
+            .image_indices(&image_indices);
+
+        match unsafe { self.swapchain_loader.queue_present(queue, &present_info) } {
+            Ok(suboptimal) => self.out_of_date |= suboptimal,
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => self.out_of_date = true,
+            Err(error) => panic!("failed to present: {}", error),
+        }
+    }
+
+    pub unsafe fn destroy(self, device: &ash::Device) {
+        device.destroy_semaphore(self.image_available, None);
+        device.destroy_semaphore(self.render_finished, None);
+        self.swapchain_loader
+            .destroy_swapchain(self.swapchain, None);
+        self.surface_loader.destroy_surface(self.surface, None);
+    }
+
+    /// (Re)creates the swapchain at the surface's current size, falling back to
+    /// `width` x `height` where the surface leaves that to the swapchain.
+    fn create_swapchain(&mut self, physical_device: vk::PhysicalDevice, width: u32, height: u32) {
+        let capabilities = unsafe {
+            self.surface_loader
+                .get_physical_device_surface_capabilities(physical_device, self.surface)
+        }
+        .unwrap();
+        assert!(
+            capabilities
+                .supported_usage_flags
+                .contains(vk::ImageUsageFlags::TRANSFER_DST),
+            "the window surface can't be blitted to"
+        );
+
+        // Encoded colours (PNG output) must reach the swapchain unchanged, while linear ones
+        // (EXR output) need an _SRGB format, which the blit encodes them for. ACEScg output is
+        // still shown with sRGB primaries.
+        let formats = unsafe {
+            self.surface_loader
+                .get_physical_device_surface_formats(physical_device, self.surface)
+        }
+        .unwrap();
+        let wanted = if self.linear {
+            [vk::Format::B8G8R8A8_SRGB, vk::Format::R8G8B8A8_SRGB]
+        } else {
+            [vk::Format::B8G8R8A8_UNORM, vk::Format::R8G8B8A8_UNORM]
+        };
+        let format = formats
+            .iter()
+            .find(|format| wanted.contains(&format.format))
+            .unwrap_or(&formats[0]);
+
+        self.extent = if capabilities.current_extent.width == u32::MAX {
+            vk::Extent2D {
+                width: width.clamp(
+                    capabilities.min_image_extent.width,
+                    capabilities.max_image_extent.width,
+                ),
+                height: height.clamp(
+                    capabilities.min_image_extent.height,
+                    capabilities.max_image_extent.height,
+                ),
+            }
+        } else {
+            capabilities.current_extent
+        };
+        // A minimized window has no size; try again next frame.
+        self.out_of_date = self.extent.width == 0 || self.extent.height == 0;
+        if self.out_of_date {
+            return;
+        }
+
+        let image_count = if capabilities.max_image_count > 0 {
+            (capabilities.min_image_count + 1).min(capabilities.max_image_count)
+        } else {
+            capabilities.min_image_count + 1
+        };
+
+        let old_swapchain = self.swapchain;
+        let swapchain_create_info = vk::SwapchainCreateInfoKHR::builder()
+            .surface(self.surface)
+            .min_image_count(image_count)
+            .image_format(format.format)
+            .image_color_space(format.color_space)
+            .image_extent(self.extent)
+            .image_array_layers(1)
+            .image_usage(vk::ImageUsageFlags::TRANSFER_DST)
+            .image_sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .pre_transform(capabilities.current_transform)
+            .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
+            .present_mode(vk::PresentModeKHR::FIFO)
+            .clipped(true)
+            .old_swapchain(old_swapchain);
+
+        self.swapchain = unsafe {
+            self.swapchain_loader
+                .create_swapchain(&swapchain_create_info, None)
+        }
+        .expect("failed to create swapchain");
+        self.images =
+            unsafe { self.swapchain_loader.get_swapchain_images(self.swapchain) }.unwrap();
+
+        unsafe {
+            self.swapchain_loader.destroy_swapchain(old_swapchain, None);
+        }
+    }
+}